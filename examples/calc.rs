@@ -1,4 +1,23 @@
-use panfix::{pattern, Grammar, GrammarError, ParseError, Parser, Source, Visitor};
+use panfix::{
+    op_enum, pattern, Grammar, GrammarError, OpId, OpMap, ParseError, Parser, Source, Visitor,
+};
+
+op_enum! {
+    enum CalcOp {
+        LexError,
+        Blank,
+        Juxtapose,
+        ChainError,
+        Number,
+        Parens,
+        Times,
+        Divide,
+        Negative,
+        Plus,
+        Minus,
+        Log,
+    }
+}
 
 fn make_parser() -> Result<Parser, GrammarError> {
     let mut grammar = Grammar::new_with_unicode_whitespace()?;
@@ -16,26 +35,31 @@ fn make_parser() -> Result<Parser, GrammarError> {
     grammar.finish()
 }
 
-fn calc<'s>(expr: Visitor<'s, '_, '_>) -> Result<f64, ParseError<'s>> {
-    match expr.name() {
-        "Blank" => Err(expr.error("missing expression", "Missing expression.")),
-        "Juxtapose" => Err(expr.error(
+fn calc<'s>(ops: &OpMap<CalcOp>, expr: Visitor<'s, '_, '_>) -> Result<f64, ParseError<'s>> {
+    let calc = |expr| calc(ops, expr);
+    match ops.get(expr.op_id()) {
+        CalcOp::LexError => Err(expr.error("unknown token", "Unrecognized token.")),
+        CalcOp::Blank => Err(expr.error("missing expression", "Missing expression.")),
+        CalcOp::Juxtapose => Err(expr.error(
             "extra expression",
             "Multiple expressions. There can only be one.",
         )),
-        "Number" => match expr.source().parse::<f64>() {
+        CalcOp::ChainError => Err(expr.error(
+            "missing parentheses",
+            "These operators can't be combined without parentheses.",
+        )),
+        CalcOp::Number => match expr.source().parse::<f64>() {
             Ok(n) => Ok(n),
             Err(err) => Err(expr.error("invalid number", &format!("Invalid number '{}'", err))),
         },
-        "Times" => Ok(calc(expr.child(0))? * calc(expr.child(1))?),
-        "Divide" => Ok(calc(expr.child(0))? / calc(expr.child(1))?),
-        "Parens" => calc(expr.child(0)),
-        "Negative" => Ok(-calc(expr.child(0))?),
-        "Plus" => Ok(calc(expr.child(0))? + calc(expr.child(1))?),
-        "Minus" => Ok(calc(expr.child(0))? - calc(expr.child(1))?),
-        "Log" if expr.child(0).name() == "Blank" => Ok(f64::ln(calc(expr.child(1))?)),
-        "Log" => Ok(calc(expr.child(1))?.log(calc(expr.child(0))?)),
-        op => panic!("Bug: missing case in parser: {}", op),
+        CalcOp::Times => Ok(calc(expr.child(0))? * calc(expr.child(1))?),
+        CalcOp::Divide => Ok(calc(expr.child(0))? / calc(expr.child(1))?),
+        CalcOp::Parens => calc(expr.child(0)),
+        CalcOp::Negative => Ok(-calc(expr.child(0))?),
+        CalcOp::Plus => Ok(calc(expr.child(0))? + calc(expr.child(1))?),
+        CalcOp::Minus => Ok(calc(expr.child(0))? - calc(expr.child(1))?),
+        CalcOp::Log if expr.child(0).op_id() == OpId::BLANK => Ok(f64::ln(calc(expr.child(1))?)),
+        CalcOp::Log => Ok(calc(expr.child(1))?.log(calc(expr.child(0))?)),
     }
 }
 
@@ -65,7 +89,7 @@ fn main() {
 
     // Parse and calculate
     match parser.parse(&source) {
-        Ok(tree) => match calc(tree.visitor()) {
+        Ok(tree) => match calc(&parser.op_map().unwrap(), tree.visitor()) {
             Ok(answer) => println!("{}", answer),
             Err(err) => println!("{}", err),
         },
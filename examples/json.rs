@@ -1,4 +1,6 @@
-use panfix::{pattern, Grammar, GrammarError, ParseError, Parser, Source, Visitor};
+use panfix::{
+    op_enum, pattern, Grammar, GrammarError, OpId, OpMap, ParseError, Parser, Source, Visitor,
+};
 use std::collections::HashMap;
 use std::mem;
 
@@ -15,10 +17,29 @@ fn make_json_parser() -> Result<Parser, GrammarError> {
     grammar.right_assoc();
     grammar.op("Keyval", pattern!(_ ":" _))?;
     grammar.right_assoc();
-    grammar.op("Comma", pattern!(_ "," _))?;
+    grammar.list_op("Elems", ",")?;
     grammar.finish()
 }
 
+op_enum! {
+    enum JsonOp {
+        LexError,
+        Blank,
+        Juxtapose,
+        ChainError,
+        String,
+        Number,
+        Invalid,
+        Null,
+        True,
+        False,
+        Array,
+        Object,
+        Keyval,
+        Elems,
+    }
+}
+
 #[allow(dead_code)] // I'm not dead! I'm read only through fmt::Debug.
 #[derive(Debug)]
 enum Json {
@@ -31,12 +52,18 @@ enum Json {
 }
 
 struct Traverser<'s> {
+    source: &'s Source,
+    ops: OpMap<JsonOp>,
     errors: Vec<ParseError<'s>>,
 }
 
 impl<'s> Traverser<'s> {
-    fn new() -> Traverser<'s> {
-        Traverser { errors: vec![] }
+    fn new(source: &'s Source, parser: &Parser) -> Traverser<'s> {
+        Traverser {
+            source,
+            ops: parser.op_map().unwrap(),
+            errors: vec![],
+        }
     }
 
     fn parse(mut self, visitor: Visitor<'s, '_, '_>) -> Result<Json, Vec<ParseError<'s>>> {
@@ -49,27 +76,32 @@ impl<'s> Traverser<'s> {
     }
 
     fn parse_value(&mut self, visitor: Visitor<'s, '_, '_>) -> Json {
-        match visitor.name() {
-            "Error" => self.error_json(
+        match self.ops.get(visitor.op_id()) {
+            JsonOp::LexError => self.error_json(
                 visitor,
                 "unknown token",
                 "Unrecognized token in JSON value.",
             ),
-            "Blank" => self.error_json(visitor, "missing Json value", "Missing Json value."),
-            "Juxtapose" => self.error_json(
+            JsonOp::Blank => self.error_json(visitor, "missing Json value", "Missing Json value."),
+            JsonOp::Juxtapose => self.error_json(
                 visitor,
                 "too many values",
                 "Found two values next to each other.",
             ),
-            "Invalid" => self.error_json(visitor, "missing quotes", "Missing quotes."),
-            "Null" => Json::Null,
-            "True" => Json::Boolean(true),
-            "False" => Json::Boolean(false),
-            "String" => {
+            JsonOp::ChainError => self.error_json(
+                visitor,
+                "missing parentheses",
+                "These operators can't be combined without parentheses.",
+            ),
+            JsonOp::Invalid => self.error_json(visitor, "missing quotes", "Missing quotes."),
+            JsonOp::Null => Json::Null,
+            JsonOp::True => Json::Boolean(true),
+            JsonOp::False => Json::Boolean(false),
+            JsonOp::String => {
                 let src = visitor.source();
                 Json::String(src[1..src.len() - 1].to_owned())
             }
-            "Number" => match visitor.source().parse::<f64>() {
+            JsonOp::Number => match visitor.source().parse::<f64>() {
                 Ok(n) => Json::Number(n),
                 Err(err) => self.error_json(
                     visitor,
@@ -77,79 +109,63 @@ impl<'s> Traverser<'s> {
                     &format!("Invalid number '{}'", err),
                 ),
             },
-            "Array" => {
+            JsonOp::Array => {
                 let visitor = visitor.child(0);
-                if visitor.name() == "Blank" {
+                if visitor.op_id() == OpId::BLANK {
                     return Json::Array(vec![]);
                 }
                 let mut elems = vec![];
-                self.parse_list(visitor, &mut elems);
+                self.parse_list(visitor, |this, elem| elems.push(this.parse_value(elem)));
                 Json::Array(elems)
             }
-            "Object" => {
+            JsonOp::Object => {
                 let visitor = visitor.child(0);
-                if visitor.name() == "Blank" {
+                if visitor.op_id() == OpId::BLANK {
                     return Json::Object(HashMap::new());
                 }
                 let mut object = HashMap::new();
-                self.parse_object(visitor, &mut object);
+                self.parse_list(visitor, |this, keyval| {
+                    this.parse_keyval(keyval, &mut object)
+                });
                 Json::Object(object)
             }
-            "Comma" => self.error_json(
+            JsonOp::Elems => self.error_json(
                 visitor,
                 "unexpected list",
                 "Expected a single JSON value, not a list.",
             ),
-            "Keyval" => self.error_json(
+            JsonOp::Keyval => self.error_json(
                 visitor,
                 "unexpected key:value pair",
                 "Expected a JSON value here, not a key:value pair.",
             ),
-            op => panic!("Bug: missing parser handler for {}", op),
         }
     }
 
-    fn parse_list(&mut self, mut visitor: Visitor<'s, '_, '_>, elems: &mut Vec<Json>) {
-        while visitor.name() == "Comma" {
-            let [head, tail] = visitor.children();
-            let head = self.parse_value(head);
-            elems.push(head);
-            visitor = tail;
-        }
-        if visitor.name() == "Blank" {
-            self.error(
-                visitor,
-                "trailing comma",
-                "JSON does not allow trailing commas.",
-            );
-        } else {
-            elems.push(self.parse_value(visitor));
-        }
-    }
-
-    fn parse_object(
+    fn parse_list<'p, 't>(
         &mut self,
-        mut visitor: Visitor<'s, '_, '_>,
-        object: &mut HashMap<String, Json>,
+        visitor: Visitor<'s, 'p, 't>,
+        mut parse_elem: impl FnMut(&mut Self, Visitor<'s, 'p, 't>),
     ) {
-        while visitor.name() == "Comma" {
-            let [head, tail] = visitor.children();
-            self.parse_keyval(head, object);
-            visitor = tail;
+        if self.ops.get(visitor.op_id()) != JsonOp::Elems {
+            return parse_elem(self, visitor);
         }
-        if visitor.name() == "Blank" {
-            self.error(
-                visitor,
+        for elem in visitor.children_iter() {
+            parse_elem(self, elem);
+        }
+        if visitor.has_trailing_separator() {
+            let comma = *visitor.token_spans().last().unwrap();
+            self.errors.push(ParseError::custom_error(
+                self.source,
                 "trailing comma",
                 "JSON does not allow trailing commas.",
-            );
-        } else {
-            self.parse_keyval(visitor, object);
+                comma,
+            ));
         }
     }
 
     fn parse_keyval(&mut self, visitor: Visitor<'s, '_, '_>, object: &mut HashMap<String, Json>) {
-        if visitor.name() != "Keyval" {
+        if self.ops.get(visitor.op_id()) != JsonOp::Keyval {
             return self.error(visitor, "expected key:value", "Expected a key:value pair.");
         }
         let [key, val] = visitor.children();
@@ -162,7 +178,7 @@ impl<'s> Traverser<'s> {
     }
 
     fn parse_key(&mut self, visitor: Visitor<'s, '_, '_>) -> String {
-        if let "String" = visitor.name() {
+        if let JsonOp::String = self.ops.get(visitor.op_id()) {
             let src = visitor.source();
             src[1..src.len() - 1].to_owned()
         } else {
@@ -203,7 +219,7 @@ fn main() {
     match parser.parse(&source) {
         Ok(tree) => {
             println!("{}", tree.visitor());
-            let traverser = Traverser::new();
+            let traverser = Traverser::new(&source, &parser);
             match traverser.parse(tree.visitor()) {
                 Ok(expr) => println!("{:#?}", expr),
                 Err(errors) => {
@@ -0,0 +1,662 @@
+//! The `grammar!` macro: a whole grammar, checked at compile time.
+
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use std::collections::{HashMap, HashSet};
+use syn::parse::{Parse, ParseStream};
+use syn::{braced, parenthesized, token, Attribute, Error, Ident, LitStr, Token, Visibility};
+
+const LAYOUT_TOKENS: &[&str] = &["INDENT", "DEDENT", "NEWLINE"];
+
+/// `#[attrs] vis fn name() { statement; ... }`
+pub struct GrammarDef {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    ident: Ident,
+    statements: Vec<Statement>,
+}
+
+/// A name, written either as an identifier or as a string literal.
+struct Name {
+    value: String,
+    span: Span,
+}
+
+enum Statement {
+    Whitespace(LitStr),
+    LineComment(LitStr),
+    BlockComment(LitStr, LitStr, bool),
+    Regex(Name, LitStr),
+    String(Name, LitStr),
+    OperandOnly(Name),
+    OperatorOnly(Name),
+    Juxtapose,
+    LeftAssoc,
+    RightAssoc,
+    NonAssoc,
+    Group(Name, Ident),
+    TighterThan(Name, Name),
+    Subgrammar(Name),
+    MainSubgrammar,
+    Op(Name, Pattern),
+    ListOp(Name, LitStr),
+}
+
+struct Pattern {
+    left_arg: bool,
+    right_arg: bool,
+    parts: Vec<Part>,
+}
+
+enum Part {
+    /// A token, with the sub-grammar written before it.
+    Token(Name, Option<Name>),
+    /// A group, and whether it's `*` (as opposed to `?`).
+    Group(Vec<Part>, bool),
+}
+
+impl Parse for GrammarDef {
+    fn parse(input: ParseStream) -> syn::Result<GrammarDef> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        input.parse::<Token![fn]>()?;
+        let ident = input.parse()?;
+        let params;
+        parenthesized!(params in input);
+        if !params.is_empty() {
+            return Err(params.error("the grammar function can't take parameters"));
+        }
+        let body;
+        braced!(body in input);
+        let mut statements = Vec::new();
+        while !body.is_empty() {
+            statements.push(body.parse()?);
+            body.parse::<Token![;]>()?;
+        }
+        Ok(GrammarDef {
+            attrs,
+            vis,
+            ident,
+            statements,
+        })
+    }
+}
+
+impl Parse for Name {
+    fn parse(input: ParseStream) -> syn::Result<Name> {
+        if input.peek(LitStr) {
+            let lit = input.parse::<LitStr>()?;
+            Ok(Name {
+                value: lit.value(),
+                span: lit.span(),
+            })
+        } else if input.peek(syn::Ident) {
+            let ident = input.parse::<Ident>()?;
+            Ok(Name {
+                value: ident.to_string(),
+                span: ident.span(),
+            })
+        } else {
+            Err(input.error("expected a name or a string"))
+        }
+    }
+}
+
+impl Parse for Statement {
+    fn parse(input: ParseStream) -> syn::Result<Statement> {
+        let keyword = input.parse::<Ident>()?;
+        Ok(match keyword.to_string().as_str() {
+            "whitespace" => Statement::Whitespace(input.parse()?),
+            "line_comment" => Statement::LineComment(input.parse()?),
+            "block_comment" => {
+                let (start, end) = (input.parse()?, input.parse()?);
+                let nested = if input.peek(Token![;]) {
+                    false
+                } else {
+                    let word = input.parse::<Ident>()?;
+                    if word != "nested" {
+                        return Err(Error::new(word.span(), "expected `nested` or `;`"));
+                    }
+                    true
+                };
+                Statement::BlockComment(start, end, nested)
+            }
+            "regex" => Statement::Regex(input.parse()?, input.parse()?),
+            "string" => Statement::String(input.parse()?, input.parse()?),
+            "operand_only" => Statement::OperandOnly(input.parse()?),
+            "operator_only" => Statement::OperatorOnly(input.parse()?),
+            "juxtapose" => Statement::Juxtapose,
+            "left_assoc" => Statement::LeftAssoc,
+            "right_assoc" => Statement::RightAssoc,
+            "non_assoc" => Statement::NonAssoc,
+            "group" => {
+                let name = input.parse()?;
+                let assoc = input.parse::<Ident>()?;
+                if !["left", "right", "non_assoc"].contains(&assoc.to_string().as_str()) {
+                    return Err(Error::new(
+                        assoc.span(),
+                        "expected `left`, `right`, or `non_assoc`",
+                    ));
+                }
+                Statement::Group(name, assoc)
+            }
+            "tighter_than" => Statement::TighterThan(input.parse()?, input.parse()?),
+            "subgrammar" => Statement::Subgrammar(input.parse()?),
+            "main_subgrammar" => Statement::MainSubgrammar,
+            "op" => Statement::Op(input.parse()?, input.parse()?),
+            "list_op" => Statement::ListOp(input.parse()?, input.parse()?),
+            _ => return Err(Error::new(keyword.span(), "unknown grammar statement")),
+        })
+    }
+}
+
+impl Parse for Pattern {
+    fn parse(input: ParseStream) -> syn::Result<Pattern> {
+        let left_arg = input.parse::<Option<Token![_]>>()?.is_some();
+        let parts = parse_parts(input, true)?;
+        let right_arg = input.parse::<Option<Token![_]>>()?.is_some();
+        match parts.first() {
+            None => return Err(input.error("expected a token")),
+            Some(Part::Group(_, _)) => {
+                return Err(input.error("a pattern can't start with a group"))
+            }
+            Some(Part::Token(_, Some(subgrammar))) => {
+                return Err(Error::new(
+                    subgrammar.span,
+                    "a pattern can't start with a sub-grammar",
+                ))
+            }
+            Some(Part::Token(_, None)) => (),
+        }
+        Ok(Pattern {
+            left_arg,
+            right_arg,
+            parts,
+        })
+    }
+}
+
+/// Parse the tokens and groups of a pattern, up to its end or its right argument `_`.
+fn parse_parts(input: ParseStream, allow_groups: bool) -> syn::Result<Vec<Part>> {
+    let mut parts = Vec::new();
+    while !input.is_empty() && !input.peek(Token![;]) && !input.peek(Token![_]) {
+        if input.peek(token::Paren) {
+            if !allow_groups {
+                return Err(input.error("groups can't be nested"));
+            }
+            let content;
+            parenthesized!(content in input);
+            let group = parse_parts(&content, false)?;
+            if !content.is_empty() {
+                return Err(content.error("expected a token"));
+            }
+            if group.is_empty() {
+                return Err(content.error("groups can't be empty"));
+            }
+            let many = if input.peek(Token![*]) {
+                input.parse::<Token![*]>()?;
+                true
+            } else if input.peek(Token![?]) {
+                input.parse::<Token![?]>()?;
+                false
+            } else {
+                return Err(input.error("expected `*` or `?` after a group"));
+            };
+            parts.push(Part::Group(group, many));
+            continue;
+        }
+        let mut subgrammar = None;
+        if input.peek(syn::Ident) && (input.peek2(LitStr) || input.peek2(syn::Ident)) {
+            let ident = input.parse::<Ident>()?;
+            if !LAYOUT_TOKENS.contains(&ident.to_string().as_str()) {
+                subgrammar = Some(Name {
+                    value: ident.to_string(),
+                    span: ident.span(),
+                });
+            } else {
+                parts.push(Part::Token(layout_token(&ident)?, None));
+                continue;
+            }
+        }
+        let token = if input.peek(LitStr) {
+            input.parse::<Name>()?
+        } else {
+            layout_token(&input.parse::<Ident>()?)?
+        };
+        parts.push(Part::Token(token, subgrammar));
+    }
+    Ok(parts)
+}
+
+fn layout_token(ident: &Ident) -> syn::Result<Name> {
+    if LAYOUT_TOKENS.contains(&ident.to_string().as_str()) {
+        Ok(Name {
+            value: ident.to_string(),
+            span: ident.span(),
+        })
+    } else {
+        Err(Error::new(
+            ident.span(),
+            "expected a token, or `INDENT`, `DEDENT`, or `NEWLINE`",
+        ))
+    }
+}
+
+/// Check `def` for the mistakes that `Grammar` would otherwise report at runtime, and produce the
+/// function that builds it.
+pub fn expand(def: GrammarDef) -> Result<TokenStream2, Error> {
+    check(&def)?;
+    let GrammarDef {
+        attrs,
+        vis,
+        ident,
+        statements,
+    } = def;
+    let mut calls = Vec::new();
+    let mut new_grammar = quote!(::panfix::Grammar::new_with_unicode_whitespace()?);
+    for statement in statements {
+        calls.push(match statement {
+            Statement::Whitespace(regex) => {
+                new_grammar = quote!(::panfix::Grammar::new(#regex)?);
+                continue;
+            }
+            Statement::LineComment(start) => quote!(grammar.line_comment(#start);),
+            Statement::BlockComment(start, end, nested) => {
+                quote!(grammar.block_comment(#start, #end, #nested);)
+            }
+            Statement::Regex(name, regex) => {
+                let name = name.value;
+                quote!(grammar.regex(#name, #regex)?;)
+            }
+            Statement::String(name, string) => {
+                let name = name.value;
+                quote!(grammar.string(#name, #string)?;)
+            }
+            Statement::OperandOnly(token) => {
+                let token = token.value;
+                quote!(grammar.operand_only(#token)?;)
+            }
+            Statement::OperatorOnly(token) => {
+                let token = token.value;
+                quote!(grammar.operator_only(#token)?;)
+            }
+            Statement::Juxtapose => quote!(grammar.juxtapose()?;),
+            Statement::LeftAssoc => quote!(grammar.left_assoc();),
+            Statement::RightAssoc => quote!(grammar.right_assoc();),
+            Statement::NonAssoc => quote!(grammar.non_assoc();),
+            Statement::Group(name, assoc) => {
+                let name = name.value;
+                let assoc = match assoc.to_string().as_str() {
+                    "left" => quote!(::panfix::Assoc::Left),
+                    "right" => quote!(::panfix::Assoc::Right),
+                    _ => quote!(::panfix::Assoc::NonAssoc),
+                };
+                quote!(grammar.group(#name, #assoc)?;)
+            }
+            Statement::TighterThan(tighter, looser) => {
+                let (tighter, looser) = (tighter.value, looser.value);
+                quote!(grammar.tighter_than(#tighter, #looser);)
+            }
+            Statement::Subgrammar(name) => {
+                let name = name.value;
+                quote!(grammar.subgrammar(#name);)
+            }
+            Statement::MainSubgrammar => quote!(grammar.main_subgrammar();),
+            Statement::Op(name, pattern) => {
+                let name = name.value;
+                let fixity = match (pattern.left_arg, pattern.right_arg) {
+                    (false, false) => quote!(::panfix::Fixity::Nilfix),
+                    (false, true) => quote!(::panfix::Fixity::Prefix),
+                    (true, false) => quote!(::panfix::Fixity::Suffix),
+                    (true, true) => quote!(::panfix::Fixity::Infix),
+                };
+                let parts = pattern.parts.iter().map(expand_part);
+                quote! {
+                    grammar.op(
+                        #name,
+                        ::panfix::Pattern::from_parts(#fixity, vec![#(#parts),*]),
+                    )?;
+                }
+            }
+            Statement::ListOp(name, separator) => {
+                let name = name.value;
+                quote!(grammar.list_op(#name, #separator)?;)
+            }
+        });
+    }
+    let fn_name = ident.to_string();
+    Ok(quote! {
+        #(#attrs)*
+        #vis fn #ident() -> ::panfix::Parser {
+            fn build() -> ::std::result::Result<::panfix::Parser, ::panfix::GrammarError> {
+                #[allow(unused_mut)]
+                let mut grammar = #new_grammar;
+                #(#calls)*
+                grammar.finish()
+            }
+            match build() {
+                ::std::result::Result::Ok(parser) => parser,
+                ::std::result::Result::Err(err) => {
+                    panic!("The grammar in `{}` is invalid. {}", #fn_name, err)
+                }
+            }
+        }
+    })
+}
+
+fn expand_part(part: &Part) -> TokenStream2 {
+    match part {
+        Part::Token(token, subgrammar) => {
+            let (token, subgrammar) = expand_token(token, subgrammar);
+            quote!(::panfix::PatternPart::Token(#token, #subgrammar))
+        }
+        Part::Group(parts, many) => {
+            // Groups can't be nested, which `parse_parts` has already checked.
+            let parts = parts.iter().filter_map(|part| match part {
+                Part::Token(token, subgrammar) => {
+                    let (token, subgrammar) = expand_token(token, subgrammar);
+                    Some(quote!((#token, #subgrammar)))
+                }
+                Part::Group(_, _) => None,
+            });
+            let repetition = if *many {
+                quote!(::panfix::Repetition::Many)
+            } else {
+                quote!(::panfix::Repetition::Optional)
+            };
+            quote!(::panfix::PatternPart::Group(vec![#(#parts),*], #repetition))
+        }
+    }
+}
+
+fn expand_token(token: &Name, subgrammar: &Option<Name>) -> (TokenStream2, TokenStream2) {
+    let token = &token.value;
+    let subgrammar = match subgrammar {
+        None => quote!(::std::option::Option::None),
+        Some(name) => {
+            let name = &name.value;
+            quote!(::std::option::Option::Some(#name.to_owned()))
+        }
+    };
+    (quote!(#token.to_owned()), subgrammar)
+}
+
+/// A token of the lexer: string tokens are identified by their string, and regex tokens by their
+/// regex.
+#[derive(PartialEq, Eq, Hash)]
+enum TokenKey {
+    String(String),
+    Regex(String),
+}
+
+/// Report the errors that `Grammar` would, short of `AmbiguousPattern`: bad regexes, conflicting
+/// uses of a token, and references to undefined groups, sub-grammars, and tokens.
+fn check(def: &GrammarDef) -> Result<(), Error> {
+    let mut token_names = LAYOUT_TOKENS
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<HashSet<_>>();
+    // (sub-grammar, token, has left arg) -> whether it has been used yet
+    let mut uses = HashSet::new();
+    let mut subgrammar = "main".to_owned();
+    let mut defined_subgrammars = vec!["main".to_owned()];
+    let mut used_subgrammars = Vec::new();
+    let mut groups = Vec::<String>::new();
+    let mut relations = Vec::new();
+    let mut errors = Vec::new();
+
+    let mut use_token = |subgrammar: &str, name: &Name, key: TokenKey, has_left_arg: bool| {
+        if !uses.insert((subgrammar.to_owned(), key, has_left_arg)) {
+            let message = format!(
+                "the token {} is already used {} a left argument{}",
+                name.value,
+                if has_left_arg { "with" } else { "without" },
+                if subgrammar == "main" {
+                    String::new()
+                } else {
+                    format!(" in the sub-grammar {}", subgrammar)
+                }
+            );
+            Err(Error::new(name.span, message))
+        } else {
+            Ok(())
+        }
+    };
+
+    for (i, statement) in def.statements.iter().enumerate() {
+        let result = match statement {
+            Statement::Whitespace(regex) if i == 0 => check_regex(regex),
+            Statement::Whitespace(regex) => Err(Error::new(
+                regex.span(),
+                "`whitespace` must be the first statement",
+            )),
+            Statement::Regex(name, regex) => {
+                token_names.insert(name.value.clone());
+                check_regex(regex).and_then(|()| {
+                    let key = TokenKey::Regex(regex.value());
+                    use_token(&subgrammar, name, key, false)
+                })
+            }
+            Statement::String(_, string) => {
+                token_names.insert(string.value());
+                let name = Name {
+                    value: format!("{:?}", string.value()),
+                    span: string.span(),
+                };
+                use_token(&subgrammar, &name, TokenKey::String(string.value()), false)
+            }
+            Statement::Op(_, pattern) => {
+                let mut result = Ok(());
+                for_each_token(&pattern.parts, &mut |token, token_subgrammar| {
+                    token_names.insert(token.value.clone());
+                    if let Some(name) = token_subgrammar {
+                        used_subgrammars.push((name.value.clone(), name.span));
+                    }
+                });
+                if let Some(Part::Token(first, _)) = pattern.parts.first() {
+                    let name = Name {
+                        value: format!("{:?}", first.value),
+                        span: first.span,
+                    };
+                    let key = TokenKey::String(first.value.clone());
+                    result = use_token(&subgrammar, &name, key, pattern.left_arg);
+                }
+                result
+            }
+            Statement::ListOp(_, separator) => {
+                token_names.insert(separator.value());
+                let name = Name {
+                    value: format!("{:?}", separator.value()),
+                    span: separator.span(),
+                };
+                use_token(
+                    &subgrammar,
+                    &name,
+                    TokenKey::String(separator.value()),
+                    true,
+                )
+            }
+            Statement::OperandOnly(token) | Statement::OperatorOnly(token) => {
+                if token_names.contains(&token.value) {
+                    Ok(())
+                } else {
+                    let message = format!("there is no token named {} (yet)", token.value);
+                    Err(Error::new(token.span, message))
+                }
+            }
+            Statement::Group(name, _) => {
+                if groups.contains(&name.value) {
+                    let message = format!("there are two precedence groups named {}", name.value);
+                    Err(Error::new(name.span, message))
+                } else {
+                    groups.push(name.value.clone());
+                    Ok(())
+                }
+            }
+            Statement::TighterThan(tighter, looser) => {
+                relations.push((tighter, looser));
+                Ok(())
+            }
+            Statement::Subgrammar(name) => {
+                subgrammar = name.value.clone();
+                defined_subgrammars.push(name.value.clone());
+                Ok(())
+            }
+            Statement::MainSubgrammar => {
+                subgrammar = "main".to_owned();
+                Ok(())
+            }
+            Statement::LineComment(_)
+            | Statement::BlockComment(_, _, _)
+            | Statement::Juxtapose
+            | Statement::LeftAssoc
+            | Statement::RightAssoc
+            | Statement::NonAssoc => Ok(()),
+        };
+        if let Err(err) = result {
+            errors.push(err);
+        }
+    }
+
+    for (name, span) in used_subgrammars {
+        if !defined_subgrammars.contains(&name) {
+            let message = format!("the sub-grammar {} is never defined", name);
+            errors.push(Error::new(span, message));
+        }
+    }
+    for (tighter, looser) in &relations {
+        for name in [tighter, looser] {
+            if !groups.contains(&name.value) {
+                let message = format!("the precedence group {} is never defined", name.value);
+                errors.push(Error::new(name.span, message));
+            }
+        }
+    }
+    if let Some(name) = find_cycle(&relations) {
+        let message = format!(
+            "the precedence group {} is (indirectly) declared to be tighter than itself",
+            name.value
+        );
+        errors.push(Error::new(name.span, message));
+    }
+
+    let mut errors = errors.into_iter();
+    match errors.next() {
+        None => Ok(()),
+        Some(mut first) => {
+            for err in errors {
+                first.combine(err);
+            }
+            Err(first)
+        }
+    }
+}
+
+fn check_regex(regex: &LitStr) -> Result<(), Error> {
+    match regex::Regex::new(&format!("^({})", regex.value())) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Error::new(regex.span(), format!("invalid regex: {}", err))),
+    }
+}
+
+fn for_each_token<'a>(parts: &'a [Part], f: &mut impl FnMut(&'a Name, Option<&'a Name>)) {
+    for part in parts {
+        match part {
+            Part::Token(token, subgrammar) => f(token, subgrammar.as_ref()),
+            Part::Group(parts, _) => for_each_token(parts, f),
+        }
+    }
+}
+
+/// A group in a cycle of `tighter_than` relations, if there is one.
+fn find_cycle<'a>(relations: &[(&'a Name, &'a Name)]) -> Option<&'a Name> {
+    let mut looser = HashMap::<&str, Vec<&str>>::new();
+    for (tighter, looser_name) in relations {
+        looser
+            .entry(&tighter.value)
+            .or_default()
+            .push(&looser_name.value);
+    }
+    for (start, _) in relations {
+        let mut stack = vec![start.value.as_str()];
+        let mut seen = HashSet::new();
+        while let Some(group) = stack.pop() {
+            for next in looser.get(group).into_iter().flatten() {
+                if *next == start.value {
+                    return Some(start);
+                }
+                if seen.insert(*next) {
+                    stack.push(next);
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use panfix::{Grammar, GrammarFileError};
+
+    /// Check `grammar`, written as a grammar file (which `grammar!` has the same syntax as, but
+    /// with a `;` after each statement), both with `check` and by building it with `Grammar`.
+    /// Returns whether `check` accepted it, and the runtime error if there was one.
+    fn check_both(grammar: &str) -> (bool, Option<String>) {
+        let statements = grammar
+            .lines()
+            .map(|line| format!("{};", line))
+            .collect::<String>();
+        let def = syn::parse_str::<GrammarDef>(&format!("fn grammar() {{ {} }}", statements))
+            .expect("grammar! syntax");
+        let checked = check(&def).is_ok();
+        let error = match grammar.parse::<Grammar>() {
+            Ok(grammar) => grammar.finish().err().map(|err| format!("{:?}", err)),
+            Err(GrammarFileError::GrammarError { error, .. }) => Some(format!("{:?}", error)),
+            Err(err) => Some(format!("{:?}", err)),
+        };
+        (checked, error)
+    }
+
+    #[test]
+    fn test_check_matches_grammar() {
+        // Each grammar, and the error that `Grammar` gives for it (by variant name). `check`
+        // should reject exactly the grammars that `Grammar` does, except that it leaves
+        // `AmbiguousPattern` to runtime. (A `MalformedPattern` can't be written in either syntax.)
+        let cases = [
+            ("regex Number \"[0-9]+\"\nop Plus _ \"+\" _", None),
+            ("regex Number \"[0-9\"", Some("RegexError")),
+            ("whitespace \"[ \"", Some("RegexError")),
+            ("regex Number \"[0-9]+\"\nwhitespace \" +\"", Some("SyntaxError")),
+            ("op Neg \"-\" _\nop Pos \"-\" _", Some("PrefixyConflict")),
+            ("op Minus _ \"-\" _\nop Decr _ \"-\"", Some("SuffixyConflict")),
+            ("op Neg \"-\" _\nop Minus _ \"-\" _", None),
+            ("string Null \"null\"\nop Nil \"null\"", Some("PrefixyConflict")),
+            ("op Comma _ \",\" _\nlist_op Elems \",\"", Some("SuffixyConflict")),
+            (
+                "op Call _ \"(\" Args \")\"\nop Neg \"-\" _\nsubgrammar Args\nop Neg \"-\" _",
+                None,
+            ),
+            ("operand_only Number\nregex Number \"[0-9]+\"", Some("UnknownToken")),
+            ("regex Number \"[0-9]+\"\noperator_only Number", None),
+            ("op Func \"fn\" Param \"(\" \")\"", Some("UndefinedSubgrammar")),
+            ("group Sum left\ngroup Sum right", Some("DuplicateGroup")),
+            ("group Sum left\ntighter_than Product Sum", Some("UndefinedGroup")),
+            (
+                "group Sum left\ngroup Product left\ntighter_than Product Sum\ntighter_than Sum Product",
+                Some("PrecedenceCycle"),
+            ),
+            ("op If \"a\" ( \"b\" )* ( \"b\" \"c\" )?", Some("AmbiguousPattern")),
+        ];
+        for (grammar, expected) in cases {
+            let (checked, error) = check_both(grammar);
+            match (expected, &error) {
+                (None, None) => (),
+                (Some(expected), Some(error)) if error.starts_with(expected) => (),
+                _ => panic!("Grammar error for {:?}: {:?}", grammar, error),
+            }
+            let expected_checked = matches!(expected, None | Some("AmbiguousPattern"));
+            assert_eq!(checked, expected_checked, "check for {:?}", grammar);
+        }
+    }
+}
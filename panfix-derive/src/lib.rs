@@ -0,0 +1,363 @@
+//! `#[derive(FromParseTree)]`, for converting panfix parse trees into your own AST types, and
+//! `grammar!`, for defining a whole grammar at compile time. See the docs of
+//! `panfix::FromParseTree` and `panfix::grammar!` for how to use them.
+
+mod grammar;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Fields, Lifetime, Lit,
+    Meta, NestedMeta,
+};
+
+#[proc_macro_derive(FromParseTree, attributes(panfix))]
+pub fn derive_from_parse_tree(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Define a whole grammar at once, as a function that builds its `Parser`. Most mistakes in the
+/// grammar are compile errors, instead of `GrammarError`s when the function is called.
+///
+/// There's one statement per `Grammar` method, each ending in a `;`. They're written the same way
+/// as in a grammar file (see the README), except that names may be identifiers or strings:
+///
+/// ```
+/// use panfix::{grammar, Source};
+///
+/// grammar! {
+///     pub fn json_parser() {
+///         whitespace "[ \n\r\t]+";
+///         regex String r#""([^\\"]|(\\.))*""#;
+///         regex Number r"-?(?:0|[1-9]\d*)(?:\.\d+)?(?:[eE][+-]?\d+)?";
+///         string Null "null";
+///         op Array "[" "]";
+///         op Object "{" "}";
+///         right_assoc;
+///         op Keyval _ ":" _;
+///         right_assoc;
+///         list_op Elems ",";
+///     }
+/// }
+///
+/// let parser = json_parser();
+/// let source = Source::new("example", r#"{"a": [1, null]}"#.to_owned());
+/// let tree = parser.parse(&source).unwrap();
+/// assert_eq!(
+///     tree.visitor().to_string(),
+///     r#"(Object (Keyval "a" (Array (Elems 1 null))))"#
+/// );
+/// ```
+///
+/// These are checked at compile time: that every regex is valid; that no token is used to start
+/// two ops with a left argument, or two without one, in the same sub-grammar (a `SuffixyConflict`
+/// or `PrefixyConflict`); that the sub-grammars, precedence groups, and tokens that are referred
+/// to are defined; and that the `tighter_than` relations don't have a cycle. Any other error
+/// (like an `AmbiguousPattern`) makes the function panic. For example, none of these compile:
+///
+/// ```compile_fail
+/// panfix::grammar! {
+///     fn bad_regex() {
+///         regex Number "[0-9";
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail
+/// panfix::grammar! {
+///     fn bad_whitespace_regex() {
+///         whitespace "[ \n";
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail
+/// panfix::grammar! {
+///     fn late_whitespace() {
+///         regex Number "[0-9]+";
+///         whitespace "[ \n]+";
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail
+/// panfix::grammar! {
+///     fn prefixy_conflict() {
+///         op Neg "-" _;
+///         op Pos "-" _;
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail
+/// panfix::grammar! {
+///     fn suffixy_conflict() {
+///         op Minus _ "-" _;
+///         op Decr _ "-";
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail
+/// panfix::grammar! {
+///     fn unknown_token() {
+///         operand_only Number;
+///         regex Number "[0-9]+";
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail
+/// panfix::grammar! {
+///     fn undefined_subgrammar() {
+///         op Func "fn" Param "(" ")";
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail
+/// panfix::grammar! {
+///     fn duplicate_group() {
+///         group Sum left;
+///         group Sum right;
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail
+/// panfix::grammar! {
+///     fn undefined_group() {
+///         group Sum left;
+///         tighter_than Product Sum;
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail
+/// panfix::grammar! {
+///     fn precedence_cycle() {
+///         group Sum left;
+///         group Product left;
+///         tighter_than Product Sum;
+///         tighter_than Sum Product;
+///     }
+/// }
+/// ```
+#[proc_macro]
+pub fn grammar(input: TokenStream) -> TokenStream {
+    let def = parse_macro_input!(input as grammar::GrammarDef);
+    match grammar::expand(def) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// The contents of the `#[panfix(...)]` attributes on one item.
+#[derive(Default)]
+struct Attrs {
+    name: Option<String>,
+    expected: Option<String>,
+    from_str: bool,
+}
+
+impl Attrs {
+    /// Parse the `#[panfix(...)]` attributes, allowing only the keys in `allowed`.
+    fn parse(attrs: &[Attribute], allowed: &[&str]) -> Result<Attrs, Error> {
+        let mut result = Attrs::default();
+        for attr in attrs.iter().filter(|attr| attr.path.is_ident("panfix")) {
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                meta => return Err(Error::new_spanned(meta, "expected #[panfix(...)]")),
+            };
+            for nested in list.nested {
+                let (key, value) = match &nested {
+                    NestedMeta::Meta(Meta::Path(path)) => (path.get_ident(), None),
+                    NestedMeta::Meta(Meta::NameValue(pair)) => match &pair.lit {
+                        Lit::Str(string) => (pair.path.get_ident(), Some(string.value())),
+                        _ => return Err(Error::new_spanned(&pair.lit, "expected a string")),
+                    },
+                    _ => (None, None),
+                };
+                let key = key.map(|key| key.to_string()).unwrap_or_default();
+                if !allowed.contains(&key.as_str()) {
+                    let message = format!("expected one of: {}", allowed.join(", "));
+                    return Err(Error::new_spanned(nested, message));
+                }
+                match (key.as_str(), value) {
+                    ("name", Some(value)) => result.name = Some(value),
+                    ("expected", Some(value)) => result.expected = Some(value),
+                    ("from_str", None) => result.from_str = true,
+                    (key, _) => {
+                        let message = match key {
+                            "from_str" => "expected `from_str`".to_owned(),
+                            key => format!("expected `{} = \"...\"`", key),
+                        };
+                        return Err(Error::new_spanned(nested, message));
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn expand(mut input: DeriveInput) -> Result<TokenStream2, Error> {
+    let attrs = Attrs::parse(&input.attrs, &["name", "expected"])?;
+    let ident = &input.ident;
+    let expected = attrs.expected.unwrap_or_else(|| ident.to_string());
+
+    // The source lifetime: the type's own lifetime parameter if it has one, or a new one if not.
+    let lifetimes = input.generics.lifetimes().collect::<Vec<_>>();
+    let has_lifetime = !lifetimes.is_empty();
+    let source_lifetime =
+        match lifetimes.as_slice() {
+            [] => Lifetime::new("'__s", Span::call_site()),
+            [def] => def.lifetime.clone(),
+            [_, def, ..] => return Err(Error::new_spanned(
+                def,
+                "FromParseTree can only be derived for types with at most one lifetime parameter",
+            )),
+        };
+    let type_params = input
+        .generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect::<Vec<_>>();
+    {
+        let where_clause = input.generics.make_where_clause();
+        for param in type_params {
+            where_clause
+                .predicates
+                .push(parse_quote!(#param: ::panfix::FromParseTree<#source_lifetime>));
+        }
+    }
+    let mut impl_generics = input.generics.clone();
+    if !has_lifetime {
+        impl_generics
+            .params
+            .insert(0, parse_quote!(#source_lifetime));
+    }
+    let (impl_generics, _, _) = impl_generics.split_for_impl();
+    let (_, type_generics, where_clause) = input.generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Enum(data) => {
+            let mut names = Vec::new();
+            let mut arms = Vec::new();
+            for variant in &data.variants {
+                let variant_attrs = Attrs::parse(&variant.attrs, &["name"])?;
+                let variant_ident = &variant.ident;
+                let name = variant_attrs
+                    .name
+                    .unwrap_or_else(|| variant_ident.to_string());
+                if names.contains(&name) {
+                    let message = format!("two variants are for the op '{}'", name);
+                    return Err(Error::new_spanned(variant, message));
+                }
+                let construct = construct(
+                    quote!(Self::#variant_ident),
+                    &variant.fields,
+                    &source_lifetime,
+                )?;
+                arms.push(quote!(#name => { #construct }));
+                names.push(name);
+            }
+            quote! {
+                ::panfix::derive_helpers::check_node(visitor, #expected)?;
+                match visitor.name() {
+                    #(#arms)*
+                    _ => Err(::panfix::derive_helpers::unexpected_op(visitor, #expected)),
+                }
+            }
+        }
+        Data::Struct(data) => {
+            let name = attrs.name.unwrap_or_else(|| ident.to_string());
+            let construct = construct(quote!(Self), &data.fields, &source_lifetime)?;
+            quote! {
+                ::panfix::derive_helpers::check_node(visitor, #expected)?;
+                if visitor.name() != #name {
+                    return Err(::panfix::derive_helpers::unexpected_op(visitor, #expected));
+                }
+                #construct
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "FromParseTree can't be derived for unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::panfix::FromParseTree<#source_lifetime> for #ident #type_generics
+        #where_clause
+        {
+            fn from_parse_tree(
+                visitor: ::panfix::Visitor<#source_lifetime, '_, '_>,
+            ) -> ::std::result::Result<Self, ::panfix::ParseError<#source_lifetime>> {
+                #body
+            }
+        }
+    })
+}
+
+/// Code that constructs `path` with `fields` from the children of `visitor`, or if there is one
+/// field and no children, from `visitor` itself.
+fn construct(
+    path: TokenStream2,
+    fields: &Fields,
+    source_lifetime: &Lifetime,
+) -> Result<TokenStream2, Error> {
+    let mut conversions = Vec::new();
+    for field in fields {
+        let attrs = Attrs::parse(&field.attrs, &["from_str"])?;
+        let ty = &field.ty;
+        conversions.push(if attrs.from_str {
+            let expected = quote!(#ty).to_string();
+            quote!(::panfix::derive_helpers::from_str::<#ty>(child, #expected)?)
+        } else {
+            quote!(<#ty as ::panfix::FromParseTree<#source_lifetime>>::from_parse_tree(child)?)
+        });
+    }
+    let num_fields = conversions.len();
+    let children = (0..num_fields)
+        .map(|i| format_ident!("child_{}", i))
+        .collect::<Vec<_>>();
+    let values = children
+        .iter()
+        .zip(&conversions)
+        .map(|(child, conversion)| quote!({ let child = #child; #conversion }))
+        .collect::<Vec<_>>();
+    let value = match fields {
+        Fields::Unit => quote!(#path),
+        Fields::Unnamed(_) => quote!(#path(#(#values),*)),
+        Fields::Named(_) => {
+            let names = fields.iter().map(|field| &field.ident);
+            quote!(#path { #(#names: #values),* })
+        }
+    };
+    let indices = 0..num_fields;
+    let from_children = quote! {
+        ::panfix::derive_helpers::check_arity(visitor, #num_fields)?;
+        #(let #children = visitor.child(#indices);)*
+        Ok(#value)
+    };
+    Ok(match num_fields {
+        0 => quote!(Ok(#value)),
+        1 => quote! {
+            if visitor.num_children() == 0 {
+                let child_0 = visitor;
+                Ok(#value)
+            } else {
+                #from_children
+            }
+        },
+        _ => from_children,
+    })
+}
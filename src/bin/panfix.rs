@@ -0,0 +1,213 @@
+//! Parse a file using a grammar file, and print the parse tree or each stage of parsing. Run with
+//! no arguments for usage.
+
+use panfix::{Grammar, Offset, ParseError, Parser, Source, Span, Visitor};
+use std::fmt::Write;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: panfix COMMAND GRAMMAR_FILE [INPUT_FILE]
+
+Parse INPUT_FILE (or stdin, if it's missing or '-') using the grammar in GRAMMAR_FILE.
+
+Commands:
+    sexpr      Print the parse tree as an s-expression
+    json       Print the parse tree as JSON
+    outline    Print the parse tree as an indented outline
+    lex        Print each lexeme, named by token
+    resolve    Print each lexeme after resolving which operator it belongs to
+    shunt      Print the resolved lexemes in reverse polish notation
+    filter     Print the shunted lexemes that start an operator: one per tree node
+
+Spans are printed as LINE:COL-LINE:COL, counting from 0. If there are parse errors, they're
+printed to stderr and the exit code is 1 (the stages are printed regardless). Any other error
+has exit code 2.";
+
+#[derive(Debug, Clone, Copy)]
+enum Command {
+    Sexpr,
+    Json,
+    Outline,
+    Lex,
+    Resolve,
+    Shunt,
+    Filter,
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (command, grammar_path, input_path) = match args.as_slice() {
+        [command, grammar] => (command, grammar, None),
+        [command, grammar, input] if input != "-" => (command, grammar, Some(input)),
+        [command, grammar, _] => (command, grammar, None),
+        _ => return usage_error(),
+    };
+    let command = match command.as_str() {
+        "sexpr" => Command::Sexpr,
+        "json" => Command::Json,
+        "outline" => Command::Outline,
+        "lex" => Command::Lex,
+        "resolve" => Command::Resolve,
+        "shunt" => Command::Shunt,
+        "filter" => Command::Filter,
+        _ => return usage_error(),
+    };
+
+    let parser = match Grammar::load(grammar_path).map(|grammar| grammar.finish()) {
+        Ok(Ok(parser)) => parser,
+        Ok(Err(err)) => return failure(&format!("Error in grammar '{}'. {}", grammar_path, err)),
+        Err(err) => return failure(&err.to_string()),
+    };
+    let source = match input_path {
+        Some(path) => Source::open(path),
+        None => Source::from_stdin(),
+    };
+    let source = match source {
+        Ok(source) => source,
+        Err(err) => return failure(&format!("Could not read input. {}", err)),
+    };
+
+    // The stages are printed even if there are errors, since they're meant for finding them.
+    let (output, errors) = run(&parser, &source, command);
+    let is_stage = matches!(
+        command,
+        Command::Lex | Command::Resolve | Command::Shunt | Command::Filter
+    );
+    if errors.is_empty() || is_stage {
+        print!("{}", output);
+    }
+    for error in &errors {
+        eprintln!("{}", error);
+    }
+    if errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn run<'s>(parser: &Parser, source: &'s Source, command: Command) -> (String, Vec<ParseError<'s>>) {
+    let mut output = String::new();
+    match command {
+        Command::Sexpr | Command::Json | Command::Outline => {
+            let (tree, errors) = parser.parse_recovering(source);
+            let visitor = tree.visitor();
+            match command {
+                Command::Sexpr => writeln!(output, "{}", visitor).unwrap(),
+                Command::Json => {
+                    write_json(&mut output, source, visitor);
+                    output.push('\n');
+                }
+                _ => write_outline(&mut output, source, visitor, 0),
+            }
+            (output, errors)
+        }
+        Command::Lex | Command::Resolve | Command::Shunt | Command::Filter => {
+            let (_, errors, trace) = parser.parse_traced(source);
+            let entries = match command {
+                Command::Lex => trace.lexed,
+                Command::Resolve => trace.resolved,
+                Command::Shunt => trace.shunted,
+                _ => trace.filtered,
+            };
+            for entry in entries {
+                let span = show_span(source, entry.span);
+                let text = format!("{:?}", source.substr(entry.span));
+                write!(output, "{:<15} {:<11} {}", span, entry.name, text).unwrap();
+                if entry.inserted {
+                    output.push_str(" (inserted)");
+                }
+                output.push('\n');
+            }
+            (output, errors)
+        }
+    }
+}
+
+/// Show a span as the lines and columns of its ends, like `0:4-1:2`.
+fn show_span(source: &Source, span: Span) -> String {
+    format!(
+        "{}-{}",
+        source.position_at(span.start),
+        source.position_at(span.end)
+    )
+}
+
+fn write_outline(output: &mut String, source: &Source, visitor: Visitor, indent: usize) {
+    write!(
+        output,
+        "{:indent$}{} {}",
+        "",
+        visitor.name(),
+        show_span(source, visitor.span())
+    )
+    .unwrap();
+    if visitor.num_children() == 0 {
+        write!(output, " {:?}", visitor.source()).unwrap();
+    }
+    output.push('\n');
+    for i in 0..visitor.num_children() {
+        write_outline(output, source, visitor.child(i), indent + 2);
+    }
+}
+
+fn write_json(output: &mut String, source: &Source, visitor: Visitor) {
+    output.push_str("{\"name\": ");
+    write_json_string(output, visitor.name());
+    output.push_str(", \"span\": ");
+    write_json_span(output, source, visitor.span());
+    if visitor.num_children() == 0 {
+        output.push_str(", \"text\": ");
+        write_json_string(output, visitor.source());
+    } else {
+        output.push_str(", \"children\": [");
+        for i in 0..visitor.num_children() {
+            if i > 0 {
+                output.push_str(", ");
+            }
+            write_json(output, source, visitor.child(i));
+        }
+        output.push(']');
+    }
+    output.push('}');
+}
+
+fn write_json_span(output: &mut String, source: &Source, span: Span) {
+    let pos = |offset: Offset| {
+        let pos = source.position_at(offset);
+        format!("{{\"line\": {}, \"col\": {}}}", pos.line, pos.utf8_col)
+    };
+    write!(
+        output,
+        "{{\"start\": {}, \"end\": {}}}",
+        pos(span.start),
+        pos(span.end)
+    )
+    .unwrap();
+}
+
+fn write_json_string(output: &mut String, string: &str) {
+    output.push('"');
+    for ch in string.chars() {
+        match ch {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => write!(output, "\\u{:04x}", ch as u32).unwrap(),
+            ch => output.push(ch),
+        }
+    }
+    output.push('"');
+}
+
+fn usage_error() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::from(2)
+}
+
+fn failure(message: &str) -> ExitCode {
+    eprintln!("{}", message);
+    ExitCode::from(2)
+}
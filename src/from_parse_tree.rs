@@ -0,0 +1,201 @@
+//! Converting parse trees into your own AST types. See `FromParseTree`.
+
+use crate::{ParseError, Visitor, NAME_BLANK, NAME_CHAIN_ERROR, NAME_ERROR, NAME_JUXTAPOSE};
+
+/// A type that can be built from a node of a parse tree, like an AST type. Usually derived with
+/// `#[derive(FromParseTree)]`:
+///
+/// - On an enum, each variant matches the op of the same name, or the name given by
+///   `#[panfix(name = "...")]`. The variant's fields are made from the node's children, in order.
+///   (Except that if the node has no children and the variant has one field, that field is made
+///   from the node itself, so that an atom like `Number(f64)` is parsed from its source.)
+/// - On a struct, the struct matches the op of the same name (or `#[panfix(name = "...")]`), and
+///   its fields are made from the node's children, in order.
+///
+/// A field marked `#[panfix(from_str)]` is parsed from its node's source with `FromStr` instead.
+/// `Blank`, `Juxtapose`, `LexError`, and `ChainError` nodes, and ops that aren't expected, become
+/// `ParseError`s. Their messages describe what was expected by the type's name, or by
+/// `#[panfix(expected = "...")]` on the type.
+///
+/// ```
+/// use panfix::{pattern, FromParseTree, Grammar, Source};
+///
+/// #[derive(Debug, PartialEq, FromParseTree)]
+/// #[panfix(expected = "an expression")]
+/// enum Expr {
+///     Number(f64),
+///     Parens(Box<Expr>),
+///     Plus(Box<Expr>, Box<Expr>),
+/// }
+///
+/// let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+/// grammar.regex("Number", "[0-9]+").unwrap();
+/// grammar.op("Parens", pattern!("(" ")")).unwrap();
+/// grammar.op("Plus", pattern!(_ "+" _)).unwrap();
+/// let parser = grammar.finish().unwrap();
+///
+/// let source = Source::new("example", "(1 + 2)".to_owned());
+/// let tree = parser.parse(&source).unwrap();
+/// let expr = Expr::from_parse_tree(tree.visitor()).unwrap();
+/// assert_eq!(
+///     expr,
+///     Expr::Parens(Box::new(Expr::Plus(
+///         Box::new(Expr::Number(1.0)),
+///         Box::new(Expr::Number(2.0))
+///     )))
+/// );
+///
+/// let source = Source::new("example", "1 +".to_owned());
+/// let tree = parser.parse(&source).unwrap();
+/// let err = Expr::from_parse_tree(tree.visitor()).unwrap_err();
+/// assert_eq!(err.message, "Expected an expression, but found nothing.");
+/// ```
+///
+/// This is implemented for `String`, `&str`, `bool`, `char`, and the numeric types, by parsing the
+/// node's source. It's also implemented for `Box<T>`; for `Option<T>`, which is `None` for a
+/// `Blank` node; and for `Vec<T>`, which has one element per child of a list op (see
+/// `Grammar::list_op`), one element for any other node, and no elements for a `Blank` node.
+pub trait FromParseTree<'s>: Sized {
+    fn from_parse_tree(visitor: Visitor<'s, '_, '_>) -> Result<Self, ParseError<'s>>;
+}
+
+impl<'s, T: FromParseTree<'s>> FromParseTree<'s> for Box<T> {
+    fn from_parse_tree(visitor: Visitor<'s, '_, '_>) -> Result<Self, ParseError<'s>> {
+        T::from_parse_tree(visitor).map(Box::new)
+    }
+}
+
+impl<'s, T: FromParseTree<'s>> FromParseTree<'s> for Option<T> {
+    fn from_parse_tree(visitor: Visitor<'s, '_, '_>) -> Result<Self, ParseError<'s>> {
+        if visitor.name() == NAME_BLANK {
+            Ok(None)
+        } else {
+            T::from_parse_tree(visitor).map(Some)
+        }
+    }
+}
+
+impl<'s, T: FromParseTree<'s>> FromParseTree<'s> for Vec<T> {
+    fn from_parse_tree(visitor: Visitor<'s, '_, '_>) -> Result<Self, ParseError<'s>> {
+        if visitor.name() == NAME_BLANK {
+            Ok(Vec::new())
+        } else if visitor.is_list() {
+            visitor.children_iter().map(T::from_parse_tree).collect()
+        } else {
+            Ok(vec![T::from_parse_tree(visitor)?])
+        }
+    }
+}
+
+impl<'s> FromParseTree<'s> for &'s str {
+    fn from_parse_tree(visitor: Visitor<'s, '_, '_>) -> Result<Self, ParseError<'s>> {
+        derive_helpers::check_leaf(visitor, "a token")?;
+        Ok(visitor.source())
+    }
+}
+
+macro_rules! from_parse_tree_via_from_str {
+    ($($ty:ty),*) => {
+        $(
+            impl<'s> FromParseTree<'s> for $ty {
+                fn from_parse_tree(visitor: Visitor<'s, '_, '_>) -> Result<Self, ParseError<'s>> {
+                    derive_helpers::from_str(visitor, stringify!($ty))
+                }
+            }
+        )*
+    };
+}
+
+from_parse_tree_via_from_str!(
+    String, bool, char, i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64
+);
+
+/// Used by `#[derive(FromParseTree)]`.
+#[doc(hidden)]
+pub mod derive_helpers {
+    use super::*;
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    /// Error if `visitor` is a `Blank`, `Juxtapose`, `LexError`, or `ChainError` node.
+    pub fn check_node<'s>(
+        visitor: Visitor<'s, '_, '_>,
+        expected: &str,
+    ) -> Result<(), ParseError<'s>> {
+        match visitor.name() {
+            NAME_BLANK => Err(visitor.error(
+                &format!("missing {}", expected),
+                &format!("Expected {}, but found nothing.", expected),
+            )),
+            NAME_JUXTAPOSE => Err(visitor.error(
+                "missing operator",
+                "Found two expressions next to each other, with nothing to join them.",
+            )),
+            NAME_ERROR => Err(visitor.error("invalid syntax", "Invalid syntax.")),
+            NAME_CHAIN_ERROR => Err(visitor.error(
+                "missing parentheses",
+                "These operators can't be chained or mixed without parentheses.",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Like `check_node`, but also error if `visitor` isn't an atom.
+    pub fn check_leaf<'s>(
+        visitor: Visitor<'s, '_, '_>,
+        expected: &str,
+    ) -> Result<(), ParseError<'s>> {
+        check_node(visitor, expected)?;
+        if visitor.num_children() == 0 {
+            Ok(())
+        } else {
+            Err(unexpected_op(visitor, expected))
+        }
+    }
+
+    /// The error for a node whose op isn't one of the expected ones.
+    pub fn unexpected_op<'s>(visitor: Visitor<'s, '_, '_>, expected: &str) -> ParseError<'s> {
+        visitor.error(
+            &format!("unexpected {}", visitor.name()),
+            &format!("Expected {}, but found {}.", expected, visitor.name()),
+        )
+    }
+
+    /// Error if `visitor` doesn't have `num_fields` children.
+    pub fn check_arity<'s>(
+        visitor: Visitor<'s, '_, '_>,
+        num_fields: usize,
+    ) -> Result<(), ParseError<'s>> {
+        if visitor.num_children() == num_fields {
+            Ok(())
+        } else {
+            Err(visitor.error(
+                &format!("unexpected {}", visitor.name()),
+                &format!(
+                    "Expected {} to have {} parts, but it has {}.",
+                    visitor.name(),
+                    num_fields,
+                    visitor.num_children()
+                ),
+            ))
+        }
+    }
+
+    /// Parse the source of an atom with `FromStr`.
+    pub fn from_str<'s, T>(
+        visitor: Visitor<'s, '_, '_>,
+        expected: &str,
+    ) -> Result<T, ParseError<'s>>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        check_leaf(visitor, expected)?;
+        visitor.source().parse().map_err(|err| {
+            visitor.error(
+                &format!("invalid {}", expected),
+                &format!("Invalid {}: {}", expected, err),
+            )
+        })
+    }
+}
@@ -1,8 +1,12 @@
-use crate::lexer::{LexerBuilder, RegexError, UNICODE_WHITESPACE_REGEX};
-use crate::op::{Assoc, Fixity, Op, Prec};
+use crate::lexer::{LexerBuilder, RegexError, TokenMode, UNICODE_WHITESPACE_REGEX};
+use crate::op::{Assoc, Fixity, Op, OpId, Prec, PrecOrder};
 use crate::{
-    OpToken, Parser, Token, NAME_BLANK, NAME_ERROR, NAME_JUXTAPOSE, TOKEN_ERROR, TOKEN_JUXTAPOSE,
+    OpToken, Parser, SubgrammarId, Token, MAIN_SUBGRAMMAR, NAME_BAD_INDENT, NAME_BLANK,
+    NAME_CHAIN_ERROR, NAME_DEDENT, NAME_ERROR, NAME_INDENT, NAME_JUXTAPOSE, NAME_NEWLINE,
+    TOKEN_DEDENT, TOKEN_ERROR, TOKEN_INDENT, TOKEN_JUXTAPOSE, TOKEN_NEWLINE,
 };
+use std::collections::HashMap;
+use std::ops::Range;
 use thiserror::Error;
 
 const PREC_DELTA: Prec = 10;
@@ -17,15 +21,30 @@ pub struct Grammar {
     token_table: Vec<TokenInfo>,
     // OpToken -> info about that optoken
     op_token_table: Vec<OpTokenInfo>,
+    // SubgrammarId -> info about that sub-grammar
+    subgrammar_table: Vec<SubgrammarInfo>,
+    current_subgrammar: SubgrammarId,
     current_prec: Prec,
     current_assoc: Assoc,
+    // Every precedence group, in the order they were added
+    groups: Vec<GroupInfo>,
+    // (tighter, looser) pairs of named groups
+    group_relations: Vec<(String, String)>,
+    // OpId -> the name of the op(s) with that id
+    op_names: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct GroupInfo {
+    // `None` for the groups made by `left_assoc`, `right_assoc`, and `non_assoc`.
+    name: Option<String>,
+    // Only provisional for named groups, until `finish` puts the groups in order.
+    prec: Prec,
 }
 
 #[derive(Debug, Clone)]
 struct TokenInfo {
     name: String,
-    as_prefix: Option<(OpToken, bool)>,
-    as_suffix: Option<(OpToken, bool)>,
 }
 
 #[derive(Debug, Clone)]
@@ -34,7 +53,26 @@ struct OpTokenInfo {
     op: Option<Op>,
     lprec: Prec,
     rprec: Prec,
-    follower: Option<(Token, OpToken, bool)>,
+    // The tokens that may come next in the op, with their op tokens and whether they're followed
+    // by an argument. The last one is the one to make up, when recovering from a missing token.
+    followers: Vec<(Token, OpToken, bool)>,
+    // If the op may also end here, the op token to use instead if it does.
+    final_optok: Option<OpToken>,
+    // The index of this token in the op's pattern.
+    position: usize,
+    // The sub-grammar of the argument between this token and its follower.
+    subgrammar: SubgrammarId,
+}
+
+#[derive(Debug, Clone)]
+struct SubgrammarInfo {
+    name: String,
+    // Whether `Grammar::subgrammar` has been called for it, or it's only been referenced.
+    defined: bool,
+    // Token -> op token, if the token starts an op with/without a left argument.
+    // May be shorter than the token table.
+    as_prefix: Vec<Option<(OpToken, bool)>>,
+    as_suffix: Vec<Option<(OpToken, bool)>>,
 }
 
 /// An error while constructing a grammar.
@@ -59,6 +97,29 @@ pub enum GrammarError {
     /// Bad regex.
     #[error("Regex error in grammar. {0}")]
     RegexError(RegexError),
+    /// `tighter_than` referred to a group that was never added.
+    #[error("The precedence group {0} was used in `tighter_than`, but never defined.")]
+    UndefinedGroup(String),
+    /// Two groups were added with the same name.
+    #[error("There are two precedence groups named {0}.")]
+    DuplicateGroup(String),
+    /// `tighter_than` said that a group is tighter than itself, directly or indirectly.
+    #[error("The precedence group {0} is (indirectly) declared to be tighter than itself.")]
+    PrecedenceCycle(String),
+    /// After some token in an operator's pattern, the same token could start two different parts
+    /// of it.
+    #[error("The pattern for {0} is ambiguous: after one of its tokens, {1} could start two different parts of it.")]
+    AmbiguousPattern(String, String),
+    /// An operator pattern can't be used, because it has no tokens, or its groups or sub-grammars
+    /// don't fit its tokens.
+    #[error("The pattern for {0} is malformed: {1}.")]
+    MalformedPattern(String, String),
+    /// An operator pattern referred to a sub-grammar that was never defined.
+    #[error("The sub-grammar {0} was used in a pattern, but never defined.")]
+    UndefinedSubgrammar(String),
+    /// Tried to restrict the mode of a token that isn't in the grammar.
+    #[error("There is no token named {0} in the grammar.")]
+    UnknownToken(String),
 }
 
 /// Describe the syntax of an operator. You typically want to construct this with the `pattern!`
@@ -67,6 +128,98 @@ pub enum GrammarError {
 pub struct Pattern {
     pub fixity: Fixity,
     pub tokens: Vec<String>,
+    // The sub-grammar to parse each argument _between_ two tokens with, so this has one fewer
+    // element than `tokens`. `None` means to use the same sub-grammar the operator is in.
+    pub(crate) subgrammars: Vec<Option<String>>,
+    // The parts of `tokens` that are optional or may be repeated, in order. They can't overlap,
+    // and can't include the first token.
+    pub(crate) groups: Vec<PatternGroup>,
+}
+
+/// An optional or repeated part of a `Pattern`, like `("else" "{" "}")?`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternGroup {
+    /// The indices in `Pattern::tokens` of this group's tokens.
+    pub tokens: Range<usize>,
+    pub repetition: Repetition,
+}
+
+/// How many times a `PatternGroup` may appear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Repetition {
+    /// `( ... )?`: at most once.
+    Optional,
+    /// `( ... )*`: any number of times.
+    Many,
+}
+
+/// A piece of a pattern, as written in the `pattern!` macro.
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+pub enum PatternPart {
+    /// A token, with the sub-grammar written before it.
+    Token(String, Option<String>),
+    /// A group of tokens, each with the sub-grammar written before it. Groups can't be nested.
+    Group(Vec<(String, Option<String>)>, Repetition),
+}
+
+impl Pattern {
+    /// A pattern with the given tokens, whose arguments are all in the operator's own
+    /// sub-grammar, and that has no optional or repeated parts. Use `pattern!` for the rest.
+    pub fn new(fixity: Fixity, tokens: Vec<String>) -> Pattern {
+        Pattern {
+            fixity,
+            subgrammars: vec![None; tokens.len().saturating_sub(1)],
+            tokens,
+            groups: vec![],
+        }
+    }
+
+    /// The sub-grammar to parse each argument _between_ two tokens with, so this has one fewer
+    /// element than `tokens`. `None` means to use the same sub-grammar the operator is in.
+    pub fn subgrammars(&self) -> &[Option<String>] {
+        &self.subgrammars
+    }
+
+    /// The parts of `tokens` that are optional or may be repeated, in order. They can't overlap,
+    /// and can't include the first token.
+    pub fn groups(&self) -> &[PatternGroup] {
+        &self.groups
+    }
+
+    /// Used by the `pattern!` macro.
+    #[doc(hidden)]
+    pub fn from_parts(fixity: Fixity, parts: Vec<PatternPart>) -> Pattern {
+        let mut pattern = Pattern {
+            fixity,
+            tokens: vec![],
+            subgrammars: vec![],
+            groups: vec![],
+        };
+        for part in parts {
+            match part {
+                PatternPart::Token(token, subgrammar) => pattern.push_token(token, subgrammar),
+                PatternPart::Group(tokens, repetition) => {
+                    let start = pattern.tokens.len();
+                    for (token, subgrammar) in tokens {
+                        pattern.push_token(token, subgrammar);
+                    }
+                    pattern.groups.push(PatternGroup {
+                        tokens: start..pattern.tokens.len(),
+                        repetition,
+                    });
+                }
+            }
+        }
+        pattern
+    }
+
+    fn push_token(&mut self, token: String, subgrammar: Option<String>) {
+        if !self.tokens.is_empty() {
+            self.subgrammars.push(subgrammar);
+        }
+        self.tokens.push(token);
+    }
 }
 
 impl Grammar {
@@ -83,50 +236,67 @@ impl Grammar {
         let lexer_builder = LexerBuilder::new(whitespace_regex).map_err(RegexError)?;
         let juxt_op = Op::new_juxtapose(Assoc::Left, JUXTAPOSE_PREC);
         Ok(Grammar {
-            // First three tokens: ERROR, BLANK, JUXTAPOSE
+            // First three tokens and op tokens: ERROR, BLANK, JUXTAPOSE. Then the layout tokens.
             lexer_builder,
-            token_table: vec![
-                TokenInfo {
-                    name: NAME_ERROR.to_owned(),
-                    as_prefix: Some((TOKEN_ERROR, false)),
-                    as_suffix: None,
-                },
-                TokenInfo {
-                    name: NAME_BLANK.to_owned(),
-                    as_prefix: None,
-                    as_suffix: None,
-                },
-                TokenInfo {
-                    name: NAME_JUXTAPOSE.to_owned(),
-                    as_prefix: None,
-                    as_suffix: None,
-                },
-            ],
+            token_table: [
+                NAME_ERROR,
+                NAME_BLANK,
+                NAME_JUXTAPOSE,
+                NAME_INDENT,
+                NAME_DEDENT,
+                NAME_NEWLINE,
+                NAME_BAD_INDENT,
+            ]
+            .into_iter()
+            .map(|name| TokenInfo {
+                name: name.to_owned(),
+            })
+            .collect(),
             op_token_table: vec![
                 OpTokenInfo {
                     name: NAME_ERROR.to_owned(),
                     op: Some(Op::new_error()),
                     lprec: 0,
                     rprec: 0,
-                    follower: None,
+                    followers: vec![],
+                    final_optok: None,
+                    position: 0,
+                    subgrammar: MAIN_SUBGRAMMAR,
                 },
                 OpTokenInfo {
                     name: NAME_BLANK.to_owned(),
                     op: Some(Op::new_blank()),
                     lprec: 0,
                     rprec: 0,
-                    follower: None,
+                    followers: vec![],
+                    final_optok: None,
+                    position: 0,
+                    subgrammar: MAIN_SUBGRAMMAR,
                 },
                 OpTokenInfo {
                     name: NAME_JUXTAPOSE.to_owned(),
                     lprec: juxt_op.left_prec.unwrap_or(0),
                     rprec: juxt_op.right_prec.unwrap_or(0),
                     op: Some(juxt_op),
-                    follower: None,
+                    followers: vec![],
+                    final_optok: None,
+                    position: 0,
+                    subgrammar: MAIN_SUBGRAMMAR,
                 },
             ],
+            subgrammar_table: vec![SubgrammarInfo::new("main", true)],
+            current_subgrammar: MAIN_SUBGRAMMAR,
             current_prec: 10,
             current_assoc: Assoc::Left,
+            groups: vec![GroupInfo {
+                name: None,
+                prec: 10,
+            }],
+            group_relations: Vec::new(),
+            op_names: [NAME_ERROR, NAME_BLANK, NAME_JUXTAPOSE, NAME_CHAIN_ERROR]
+                .into_iter()
+                .map(|name| name.to_owned())
+                .collect(),
         })
     }
 
@@ -134,27 +304,91 @@ impl Grammar {
     /// any of the groups added so far. Any infix operators in this group will be _left
     /// associative_.
     pub fn left_assoc(&mut self) {
-        self.current_prec += PREC_DELTA;
-        self.current_assoc = Assoc::Left;
+        self.add_group(None, Assoc::Left);
     }
 
     /// Add a new group of operators. They will have higher precedence (i.e.  bind _looser_) than
     /// any of the groups added so far. Any infix operators in this group will be _left
     /// associative_.
     pub fn right_assoc(&mut self) {
-        self.current_prec += PREC_DELTA;
-        self.current_assoc = Assoc::Right;
+        self.add_group(None, Assoc::Right);
+    }
+
+    /// Add a new group of operators. They will have higher precedence (i.e.  bind _looser_) than
+    /// any of the groups added so far. The operators in this group will be _non-associative_:
+    /// they can't be chained with each other, so with comparison operators in this group, `a < b <
+    /// c` is a parse error. (Parenthesize it, as `(a < b) < c`, if that's what you meant.)
+    ///
+    /// `parse_recovering` reports the error and replaces the outer operator with a `"ChainError"`
+    /// node. That node still has the same tokens and children as the operator it replaced.
+    pub fn non_assoc(&mut self) {
+        self.add_group(None, Assoc::NonAssoc);
+    }
+
+    /// Add a new _named_ group of operators, with the given associativity. Unlike the groups made
+    /// by `left_assoc`, `right_assoc`, and `non_assoc` (which are only ordered relative to each
+    /// other), a named group's precedence is given only by `tighter_than`. Two groups that aren't
+    /// ordered, directly or indirectly, are _incomparable_: their operators can't be mixed without
+    /// parentheses. For example, if `&` and `==` are in incomparable groups then `a & b == c` is a
+    /// parse error, and `parse_recovering` reports it and replaces the outer operator with a
+    /// `"ChainError"` node (that still has the same tokens and children).
+    ///
+    /// Groups from `left_assoc`, `right_assoc`, and `non_assoc` are incomparable with every named
+    /// group, so if you use named groups, you'll want to name every group whose operators can mix.
+    pub fn group(&mut self, name: &str, assoc: Assoc) -> Result<(), GrammarError> {
+        if self
+            .groups
+            .iter()
+            .any(|group| group.name.as_deref() == Some(name))
+        {
+            return Err(GrammarError::DuplicateGroup(name.to_owned()));
+        }
+        self.add_group(Some(name.to_owned()), assoc);
+        Ok(())
+    }
+
+    /// Say that the named group `tighter` has lower precedence (binds _tighter_) than the named
+    /// group `looser`. This is transitive. The groups don't need to have been added yet, but must
+    /// be by the time `finish` is called, and there mustn't be any cycles.
+    pub fn tighter_than(&mut self, tighter: &str, looser: &str) {
+        self.group_relations
+            .push((tighter.to_owned(), looser.to_owned()));
+    }
+
+    /// Start adding operators to the sub-grammar called `name`, creating it if it doesn't exist yet.
+    /// Every operator (including atoms) added after this call, up until the next call to
+    /// `subgrammar` or `main_subgrammar`, belongs to this sub-grammar.
+    ///
+    /// A sub-grammar is used to parse an argument between two tokens of an operator, when that
+    /// operator's pattern says so. For example, with `pattern!("function" FuncName "(" ")" "end")`
+    /// the function's name will be parsed with the `FuncName` sub-grammar, while its parameters
+    /// and body are parsed with the same sub-grammar that the `function` operator belongs to.
+    ///
+    /// Each sub-grammar has its own operators, so the same token may be used in different ways in
+    /// different sub-grammars. Tokens and the juxtapose operator are shared by all of them.
+    pub fn subgrammar(&mut self, name: &str) {
+        let id = self.subgrammar_id(name);
+        self.subgrammar_table[id].defined = true;
+        self.current_subgrammar = id;
+    }
+
+    /// Go back to adding operators to the main sub-grammar: the one that top-level source code is
+    /// parsed with. This is where operators go if you never call `subgrammar`.
+    pub fn main_subgrammar(&mut self) {
+        self.current_subgrammar = MAIN_SUBGRAMMAR;
     }
 
     /// Extend the grammar with an atom: when parsing, if `string_pattern` is found exactly, parse
     /// it as an operator that takes no arguments.
     ///
     /// For example, a JSON grammar might have `.string("value", "null")`.
-    pub fn string(&mut self, name: &str, string_pattern: &str) -> Result<(), GrammarError> {
+    pub fn string(&mut self, name: &str, string_pattern: &str) -> Result<OpId, GrammarError> {
         let token = self.add_string_token(string_pattern)?;
-        let op = Op::new_atom(name, token);
-        self.add_op_token(Some(op), name, token, None, None, None)?;
-        Ok(())
+        let id = self.op_id(name);
+        let op = Op::new_atom(id, name, token);
+        let optok = self.add_op_token(Some(op), name, None, None);
+        self.register_op_token(token, optok, false, false)?;
+        Ok(id)
     }
 
     /// Extend the grammar with an atom: when parsing, if `regex_pattern` is matched, parse it as
@@ -162,11 +396,43 @@ impl Grammar {
     ///
     /// For example, a JSON grammar might have `.atom_regex("value", "[0-9]*")` (though with
     /// a better regex).
-    pub fn regex(&mut self, name: &str, regex_pattern: &str) -> Result<(), GrammarError> {
+    pub fn regex(&mut self, name: &str, regex_pattern: &str) -> Result<OpId, GrammarError> {
         let token = self.add_regex_token(regex_pattern, name)?;
-        let op = Op::new_atom(name, token);
-        self.add_op_token(Some(op), name, token, None, None, None)?;
-        Ok(())
+        let id = self.op_id(name);
+        let op = Op::new_atom(id, name, token);
+        let optok = self.add_op_token(Some(op), name, None, None);
+        self.register_op_token(token, optok, false, false)?;
+        Ok(id)
+    }
+
+    /// Only lex `token` where an operand (an atom or prefix operator) is expected. `token` is either
+    /// the string of a string pattern, or the name of a regex atom. Elsewhere, other tokens that
+    /// match are preferred.
+    ///
+    /// For example, if `-1` is matched by a number regex that is operand-only, then `x -1` is a
+    /// subtraction while `x * -1` is a multiplication by a negative number.
+    pub fn operand_only(&mut self, token: &str) -> Result<(), GrammarError> {
+        self.set_token_mode(token, TokenMode::OperandOnly)
+    }
+
+    /// Only lex `token` where an operator (an infix or suffix operator) is expected. `token` is
+    /// either the string of a string pattern, or the name of a regex atom. Elsewhere, other tokens
+    /// that match are preferred.
+    pub fn operator_only(&mut self, token: &str) -> Result<(), GrammarError> {
+        self.set_token_mode(token, TokenMode::OperatorOnly)
+    }
+
+    /// Skip comments that start with `start` and go until the end of the line, like `//` or `#`.
+    /// Comments are recorded in the parse tree: see `ParseTree::comments`.
+    pub fn line_comment(&mut self, start: &str) {
+        self.lexer_builder.line_comment(start);
+    }
+
+    /// Skip comments that start with `start` and end with `end`, like `/*` and `*/`. If `nested`
+    /// is true, comments can be nested inside each other, like `/* a /* b */ c */`. Comments are
+    /// recorded in the parse tree: see `ParseTree::comments`.
+    pub fn block_comment(&mut self, start: &str, end: &str, nested: bool) {
+        self.lexer_builder.block_comment(start, end, nested);
     }
 
     /// Extend the grammar with a "juxtapose" operator that is applied whenever two expressions are
@@ -201,15 +467,97 @@ impl Grammar {
     /// grammar.left_assoc();
     /// grammar.op("colon", pattern!(_ ":" _));
     /// ```
-    pub fn op(&mut self, name: &str, pattern: Pattern) -> Result<(), GrammarError> {
+    ///
+    /// The tokens `"INDENT"`, `"DEDENT"`, and `"NEWLINE"` are reserved for layout: if a pattern
+    /// uses them, the grammar becomes indentation sensitive, and they match changes in
+    /// indentation at the start of a line instead of literal text. For example, a Python-like
+    /// block could be `pattern!(_ ":" INDENT DEDENT)`, and statements could be separated by
+    /// `pattern!(_ NEWLINE _)`.
+    ///
+    /// Part of a pattern can be made optional with `( ... )?`, or repeatable with `( ... )*`. For
+    /// example, `pattern!("if" "{" "}" ("elif" "{" "}")* ("else" "{" "}")?)` matches an `if`
+    /// with any number of `elif`s and an optional `else`, and `pattern!("match" "{" ("case"
+    /// "=>")* "}")` matches a `match` with any number of cases. `Visitor::clauses` says which
+    /// parts appeared. If the op could end right after a token, and the op has no right argument,
+    /// then there's no argument between that token and a group that follows it (so there's no
+    /// `Blank` child between `}` and `elif` above).
+    ///
+    /// Returns the op's id, for matching on it in the parse tree (see `Visitor::op_id`).
+    pub fn op(&mut self, name: &str, pattern: Pattern) -> Result<OpId, GrammarError> {
+        use GrammarError::MalformedPattern;
+
+        if pattern.tokens.is_empty() {
+            return Err(MalformedPattern(
+                name.to_owned(),
+                "it has no tokens".to_owned(),
+            ));
+        }
+        if pattern.subgrammars.len() + 1 != pattern.tokens.len() {
+            let message = "it doesn't have one sub-grammar between each pair of tokens";
+            return Err(MalformedPattern(name.to_owned(), message.to_owned()));
+        }
+        let mut prev_end = 1;
+        for group in &pattern.groups {
+            if !(prev_end <= group.tokens.start
+                && group.tokens.start < group.tokens.end
+                && group.tokens.end <= pattern.tokens.len())
+            {
+                let message = "its groups must be non-empty, non-overlapping, in order, and after \
+                               the first token";
+                return Err(MalformedPattern(name.to_owned(), message.to_owned()));
+            }
+            prev_end = group.tokens.end;
+        }
+        let subgrammars = pattern
+            .subgrammars
+            .iter()
+            .map(|subgrammar| match subgrammar {
+                None => self.current_subgrammar,
+                Some(name) => self.subgrammar_id(name),
+            })
+            .collect::<Vec<_>>();
         if pattern.fixity == Fixity::Nilfix {
-            self.add_op(name, Assoc::Left, 0, pattern.fixity, pattern.tokens)
+            self.add_op(
+                name,
+                Assoc::Left,
+                0,
+                pattern.fixity,
+                pattern.tokens,
+                subgrammars,
+                &pattern.groups,
+            )
         } else {
             let (prec, assoc) = (self.current_prec, self.current_assoc);
-            self.add_op(name, assoc, prec, pattern.fixity, pattern.tokens)
+            self.add_op(
+                name,
+                assoc,
+                prec,
+                pattern.fixity,
+                pattern.tokens,
+                subgrammars,
+                &pattern.groups,
+            )
         }
     }
 
+    /// Extend the grammar with a _list_ operator: an infix operator whose chains are flattened
+    /// into a single node with one child per element. For example, with
+    /// `grammar.list_op("Elems", ",")`, `1, 2, 3` parses as `(Elems 1 2 3)` instead of as nested
+    /// binary nodes. A single element with no separator is not a list: `1` is just `1`.
+    ///
+    /// A trailing separator, as in `1, 2,`, doesn't produce a `Blank` last element. Instead, it's
+    /// recorded by `Visitor::has_trailing_separator`. The operator goes in the current group, but
+    /// is always treated as right associative.
+    pub fn list_op(&mut self, name: &str, separator: &str) -> Result<OpId, GrammarError> {
+        let token = self.add_pattern_token(separator)?;
+        let id = self.op_id(name);
+        let op = Op::new_list(id, name, self.current_prec, separator);
+        let (lprec, rprec) = (op.left_prec, op.right_prec);
+        let optok = self.add_op_token(Some(op), name, lprec, rprec);
+        self.register_op_token(token, optok, true, true)?;
+        Ok(id)
+    }
+
     /// Ignore the builder pattern and nice abstractions that `Grammar` otherwise uses, and add
     /// an op into the table exactly as specified.
     pub fn add_raw_op(
@@ -219,30 +567,57 @@ impl Grammar {
         assoc: Assoc,
         fixity: Fixity,
         tokens: Vec<String>,
-    ) -> Result<(), GrammarError> {
-        self.add_op(name, assoc, prec, fixity, tokens)
+    ) -> Result<OpId, GrammarError> {
+        if tokens.is_empty() {
+            return Err(GrammarError::MalformedPattern(
+                name.to_owned(),
+                "it has no tokens".to_owned(),
+            ));
+        }
+        let subgrammars = vec![self.current_subgrammar; tokens.len() - 1];
+        self.add_op(name, assoc, prec, fixity, tokens, subgrammars, &[])
     }
 
-    pub fn finish(self) -> Result<Parser, GrammarError> {
+    pub fn finish(mut self) -> Result<Parser, GrammarError> {
+        let prec_order = self.order_groups()?;
         let lexer = self
             .lexer_builder
             .finish()
             .map_err(GrammarError::RegexError)?;
+        let num_tokens = self.token_table.len();
         let mut tok_to_name = vec![];
         let mut tok_to_prefix = vec![];
         let mut tok_to_suffix = vec![];
         let mut optok_to_name = vec![];
-        let mut optok_to_follower = vec![];
+        let mut optok_to_followers = vec![];
+        let mut optok_to_final = vec![];
+        let mut optok_to_position = vec![];
+        let mut optok_to_subgrammar = vec![];
         let mut optok_to_op = vec![];
+        let mut optok_to_chain_error = vec![];
         let mut optok_to_prec = vec![];
         for row in self.token_table {
             tok_to_name.push(row.name);
+        }
+        for mut row in self.subgrammar_table {
+            if !row.defined {
+                return Err(GrammarError::UndefinedSubgrammar(row.name));
+            }
+            row.as_prefix.resize(num_tokens, None);
+            row.as_suffix.resize(num_tokens, None);
             tok_to_prefix.push(row.as_prefix);
             tok_to_suffix.push(row.as_suffix);
         }
         for row in self.op_token_table {
             optok_to_name.push(row.name);
-            optok_to_follower.push(row.follower);
+            optok_to_followers.push(row.followers);
+            optok_to_final.push(row.final_optok);
+            optok_to_position.push(row.position);
+            optok_to_subgrammar.push(row.subgrammar);
+            optok_to_chain_error.push(match &row.op {
+                Some(op) if op.fixity != Fixity::Nilfix => Some(op.new_chain_error()),
+                _ => None,
+            });
             optok_to_op.push(row.op);
             optok_to_prec.push((row.lprec, row.rprec));
         }
@@ -252,12 +627,19 @@ impl Grammar {
             tok_to_prefix,
             tok_to_suffix,
             optok_to_name,
-            optok_to_follower,
+            optok_to_followers,
+            optok_to_final,
+            optok_to_position,
+            optok_to_subgrammar,
             optok_to_op,
+            optok_to_chain_error,
             optok_to_prec,
+            prec_order,
+            op_names: self.op_names,
         })
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn add_op(
         &mut self,
         name: &str,
@@ -265,30 +647,191 @@ impl Grammar {
         prec: Prec,
         fixity: Fixity,
         tokens: Vec<String>,
-    ) -> Result<(), GrammarError> {
+        // The sub-grammar of the argument after each token but the last
+        subgrammars: Vec<SubgrammarId>,
+        groups: &[PatternGroup],
+    ) -> Result<OpId, GrammarError> {
         let maxprec = Some(Prec::MAX);
 
-        let token = self.add_string_token(&tokens[0])?;
-        let op = Op::new(name, fixity, assoc, prec, tokens.clone());
+        let id = self.op_id(name);
+        let mut op = Op::new(id, name, fixity, assoc, prec, tokens.clone());
         let (lprec, rprec) = (op.left_prec, op.right_prec);
-        if tokens.len() == 1 {
-            self.add_op_token(Some(op), name, token, lprec, rprec, None)?;
-        } else {
-            let patt = tokens.last().unwrap();
-            let token = self.add_string_token(patt)?;
-            let optok = self.add_op_token(None, name, token, Some(Prec::MAX), rprec, None)?;
-            let mut follower = (token, optok, rprec.is_some());
-            for patt in tokens.iter().skip(1).rev().skip(1) {
-                let token = self.add_string_token(patt)?;
-                let optok =
-                    self.add_op_token(None, name, token, maxprec, maxprec, Some(follower))?;
-                follower = (token, optok, true);
+        let followers = pattern_followers(tokens.len(), groups);
+        // If the op may end after a token, that token is only followed by an argument if the op
+        // takes a right argument (since otherwise it would be ambiguous whether it ended).
+        op.has_arg_after = followers
+            .iter()
+            .map(|(_, may_end)| !may_end || rprec.is_some())
+            .collect();
+        op.clause_starts = (0..tokens.len())
+            .map(|i| {
+                let bounds_group =
+                    |group: &PatternGroup| group.tokens.start == i || group.tokens.end == i;
+                i == 0 || groups.iter().any(bounds_group)
+            })
+            .collect();
+
+        // The token and op token of each token in the pattern
+        let mut optoks = Vec::new();
+        for (i, patt) in tokens.iter().enumerate() {
+            let token = self.add_pattern_token(patt)?;
+            let (next, may_end) = &followers[i];
+            let (head_op, lprec) = if i == 0 {
+                (Some(op.clone()), lprec)
+            } else {
+                (None, maxprec)
+            };
+            let optok = if next.is_empty() {
+                self.add_op_token(head_op.clone(), name, lprec, rprec)
+            } else {
+                self.add_op_token(head_op.clone(), name, lprec, maxprec)
+            };
+            if i == 0 {
+                self.register_op_token(token, optok, lprec.is_some(), op.has_arg_after[0])?;
+            }
+            if *may_end && !next.is_empty() {
+                let final_optok = self.add_op_token(head_op, name, lprec, rprec);
+                self.op_token_table[final_optok].position = i;
+                self.op_token_table[optok].final_optok = Some(final_optok);
             }
-            let patt = tokens.first().unwrap();
-            let token = self.add_string_token(patt)?;
-            self.add_op_token(Some(op), name, token, lprec, maxprec, Some(follower))?;
+            let row = &mut self.op_token_table[optok];
+            row.position = i;
+            if let Some(subgrammar) = subgrammars.get(i) {
+                row.subgrammar = *subgrammar;
+            }
+            optoks.push((token, optok));
         }
-        Ok(())
+        for (i, (next, _)) in followers.iter().enumerate() {
+            let mut row_followers: Vec<(Token, OpToken, bool)> = Vec::new();
+            for j in next {
+                let (token, optok) = optoks[*j];
+                if row_followers.iter().any(|(tok, _, _)| *tok == token) {
+                    let token_name = self.token_table[token].name.clone();
+                    return Err(GrammarError::AmbiguousPattern(name.to_owned(), token_name));
+                }
+                row_followers.push((token, optok, op.has_arg_after[*j]));
+            }
+            self.op_token_table[optoks[i].1].followers = row_followers;
+        }
+        Ok(id)
+    }
+
+    fn set_token_mode(&mut self, name: &str, mode: TokenMode) -> Result<(), GrammarError> {
+        match self.token_table.iter().position(|row| row.name == name) {
+            Some(token) => {
+                self.lexer_builder.set_mode(token, mode);
+                Ok(())
+            }
+            None => Err(GrammarError::UnknownToken(name.to_owned())),
+        }
+    }
+
+    fn add_group(&mut self, name: Option<String>, assoc: Assoc) {
+        self.current_prec += PREC_DELTA;
+        self.current_assoc = assoc;
+        self.groups.push(GroupInfo {
+            name,
+            prec: self.current_prec,
+        });
+    }
+
+    /// If there are named groups, give every group its final precedence, in an order consistent
+    /// with `tighter_than`, and return which groups are comparable.
+    fn order_groups(&mut self) -> Result<Option<PrecOrder>, GrammarError> {
+        if self.group_relations.is_empty() && self.groups.iter().all(|g| g.name.is_none()) {
+            return Ok(None);
+        }
+
+        // tighter[i][j]: whether group i is (transitively) tighter than group j
+        let n = self.groups.len();
+        let mut tighter = vec![vec![false; n]; n];
+        let unnamed = (0..n)
+            .filter(|i| self.groups[*i].name.is_none())
+            .collect::<Vec<_>>();
+        for pair in unnamed.windows(2) {
+            tighter[pair[0]][pair[1]] = true;
+        }
+        for (t, l) in &self.group_relations {
+            let group_index = |name: &str| {
+                self.groups
+                    .iter()
+                    .position(|g| g.name.as_deref() == Some(name))
+                    .ok_or_else(|| GrammarError::UndefinedGroup(name.to_owned()))
+            };
+            tighter[group_index(t)?][group_index(l)?] = true;
+        }
+        for k in 0..n {
+            for i in 0..n {
+                if tighter[i][k] {
+                    let through_k = tighter[k].clone();
+                    for (t, k_t) in tighter[i].iter_mut().zip(through_k) {
+                        *t |= k_t;
+                    }
+                }
+            }
+        }
+        if let Some(i) = (0..n).find(|i| tighter[*i][*i]) {
+            let name = self.groups[i].name.clone().unwrap_or_default();
+            return Err(GrammarError::PrecedenceCycle(name));
+        }
+
+        // A group is preceded by all the groups tighter than it, so sorting by how many groups
+        // are tighter gives an order consistent with `tighter`. Hand out the same set of
+        // precedences in that order.
+        let mut order = (0..n).collect::<Vec<_>>();
+        order.sort_by_key(|j| (0..n).filter(|i| tighter[*i][*j]).count());
+        let precs = self.groups.iter().map(|g| g.prec).collect::<Vec<_>>();
+        let mut remap = HashMap::new();
+        for (rank, group) in order.iter().enumerate() {
+            remap.insert(self.groups[*group].prec, precs[rank]);
+            remap.insert(self.groups[*group].prec + 1, precs[rank] + 1);
+        }
+        let remap = |prec: Prec| remap.get(&prec).copied().unwrap_or(prec);
+        for row in &mut self.op_token_table {
+            row.lprec = remap(row.lprec);
+            row.rprec = remap(row.rprec);
+            if let Some(op) = &mut row.op {
+                op.prec = remap(op.prec);
+                op.left_prec = op.left_prec.map(remap);
+                op.right_prec = op.right_prec.map(remap);
+            }
+        }
+
+        let comparable = order
+            .iter()
+            .map(|i| {
+                order
+                    .iter()
+                    .map(|j| i == j || tighter[*i][*j] || tighter[*j][*i])
+                    .collect()
+            })
+            .collect();
+        Ok(Some(PrecOrder { precs, comparable }))
+    }
+
+    fn subgrammar_id(&mut self, name: &str) -> SubgrammarId {
+        if let Some(id) = self
+            .subgrammar_table
+            .iter()
+            .position(|subgrammar| subgrammar.name == name)
+        {
+            return id;
+        }
+        self.subgrammar_table.push(SubgrammarInfo::new(name, false));
+        self.subgrammar_table.len() - 1
+    }
+
+    /// Like `add_string_token`, but the names of the layout tokens refer to them instead, and make
+    /// the grammar indentation sensitive.
+    fn add_pattern_token(&mut self, string: &str) -> Result<Token, GrammarError> {
+        let token = match string {
+            NAME_INDENT => TOKEN_INDENT,
+            NAME_DEDENT => TOKEN_DEDENT,
+            NAME_NEWLINE => TOKEN_NEWLINE,
+            _ => return self.add_string_token(string),
+        };
+        self.lexer_builder.layout();
+        Ok(token)
     }
 
     fn add_string_token(&mut self, string: &str) -> Result<Token, GrammarError> {
@@ -313,8 +856,6 @@ impl Grammar {
         if token == self.token_table.len() {
             self.token_table.push(TokenInfo {
                 name: name.to_owned(),
-                as_prefix: None,
-                as_suffix: None,
             });
         } else {
             let row = &self.token_table[token];
@@ -322,40 +863,100 @@ impl Grammar {
         }
     }
 
+    /// The id of the op(s) named `name`, making a new one if there aren't any yet.
+    fn op_id(&mut self, name: &str) -> OpId {
+        match self.op_names.iter().position(|op_name| op_name == name) {
+            Some(index) => OpId(index),
+            None => {
+                self.op_names.push(name.to_owned());
+                OpId(self.op_names.len() - 1)
+            }
+        }
+    }
+
     fn add_op_token(
         &mut self,
         op: Option<Op>,
         name: &str,
-        token: Token,
         lprec: Option<Prec>,
         rprec: Option<Prec>,
-        follower: Option<(Token, OpToken, bool)>,
-    ) -> Result<OpToken, GrammarError> {
-        let op_token = self.op_token_table.len();
-        if op.is_some() {
-            if lprec.is_none() {
-                if self.token_table[token].as_prefix.is_some() {
-                    return Err(GrammarError::PrefixyConflict(
-                        self.token_table[token].name.clone(),
-                    ));
-                }
-                self.token_table[token].as_prefix = Some((op_token, rprec.is_some()));
-            } else {
-                if self.token_table[token].as_suffix.is_some() {
-                    return Err(GrammarError::SuffixyConflict(
-                        self.token_table[token].name.clone(),
-                    ));
-                }
-                self.token_table[token].as_suffix = Some((op_token, rprec.is_some()));
-            }
-        }
+    ) -> OpToken {
         self.op_token_table.push(OpTokenInfo {
             name: name.to_owned(),
             op,
             lprec: lprec.unwrap_or(0),
             rprec: rprec.unwrap_or(0),
-            follower,
+            followers: vec![],
+            final_optok: None,
+            position: 0,
+            subgrammar: self.current_subgrammar,
         });
-        Ok(op_token)
+        self.op_token_table.len() - 1
+    }
+
+    /// Make `token` start the op of `op_token` in the current sub-grammar.
+    fn register_op_token(
+        &mut self,
+        token: Token,
+        op_token: OpToken,
+        has_left_arg: bool,
+        has_right_arg: bool,
+    ) -> Result<(), GrammarError> {
+        let table = &mut self.subgrammar_table[self.current_subgrammar];
+        let (entries, conflict): (_, fn(String) -> GrammarError) = if has_left_arg {
+            (&mut table.as_suffix, GrammarError::SuffixyConflict)
+        } else {
+            (&mut table.as_prefix, GrammarError::PrefixyConflict)
+        };
+        if entries.len() <= token {
+            entries.resize(token + 1, None);
+        }
+        if entries[token].is_some() {
+            return Err(conflict(self.token_table[token].name.clone()));
+        }
+        entries[token] = Some((op_token, has_right_arg));
+        Ok(())
+    }
+}
+
+/// For each token of a pattern with `num_tokens` tokens, the (indices of the) tokens that may come
+/// next, and whether the pattern may end after it instead. The token that continues past all of
+/// the optional groups comes last.
+fn pattern_followers(num_tokens: usize, groups: &[PatternGroup]) -> Vec<(Vec<usize>, bool)> {
+    // The tokens that may start the rest of the pattern, from token `i` on
+    let first_from = |mut i: usize| {
+        let mut firsts = Vec::new();
+        while i < num_tokens {
+            firsts.push(i);
+            match groups.iter().find(|group| group.tokens.start == i) {
+                Some(group) => i = group.tokens.end,
+                None => return (firsts, false),
+            }
+        }
+        (firsts, true)
+    };
+    (0..num_tokens)
+        .map(|i| {
+            let (mut next, may_end) = first_from(i + 1);
+            let repeat = groups
+                .iter()
+                .find(|group| group.tokens.end == i + 1 && group.repetition == Repetition::Many);
+            if let Some(group) = repeat {
+                next.insert(0, group.tokens.start);
+            }
+            (next, may_end)
+        })
+        .collect()
+}
+
+impl SubgrammarInfo {
+    fn new(name: &str, defined: bool) -> SubgrammarInfo {
+        SubgrammarInfo {
+            name: name.to_owned(),
+            defined,
+            // Lexing errors are atoms in every sub-grammar
+            as_prefix: vec![Some((TOKEN_ERROR, false))],
+            as_suffix: vec![],
+        }
     }
 }
@@ -0,0 +1,447 @@
+//! A text format for grammars, so that they can be written outside of Rust and loaded at runtime.
+//!
+//! A grammar file has one statement per line. Blank lines are ignored, and `#` starts a comment
+//! that runs to the end of the line. Each statement corresponds to one method of `Grammar`:
+//!
+//! ```text
+//! whitespace "[ \t\n\r]*"     # Grammar::new (only allowed as the first statement)
+//! line_comment "//"           # Grammar::line_comment
+//! block_comment "/*" "*/"     # Grammar::block_comment (add `nested` to allow nesting)
+//! regex Number "[0-9]+"       # Grammar::regex
+//! string Null "null"          # Grammar::string
+//! operand_only Number         # Grammar::operand_only (a regex name, or a quoted string)
+//! operator_only "-"           # Grammar::operator_only
+//! juxtapose                   # Grammar::juxtapose
+//! left_assoc                  # Grammar::left_assoc
+//! right_assoc                 # Grammar::right_assoc
+//! non_assoc                   # Grammar::non_assoc
+//! group Sum left              # Grammar::group (with `left`, `right`, or `non_assoc`)
+//! tighter_than Product Sum    # Grammar::tighter_than
+//! subgrammar FuncName         # Grammar::subgrammar
+//! main_subgrammar             # Grammar::main_subgrammar
+//! op Plus _ "+" _             # Grammar::op
+//! ```
+//!
+//! An `op`'s pattern is written the same way as in the `pattern!` macro: tokens in double quotes,
+//! an optional `_` at the start and end for a left and right argument, the names of sub-grammars
+//! between tokens, and the unquoted layout tokens `INDENT`, `DEDENT`, and `NEWLINE`. Groups are
+//! written the same way too: `op If "if" "{" "}" ("elif" "{" "}")* ("else" "{" "}")?`.
+//!
+//! Strings are written in double quotes or single quotes. In a double quoted string, `\"` stands
+//! for a double quote and `\\` for a backslash, and any other backslash is kept as is, so regexes
+//! like `"\d+"` can be written without doubling backslashes. A single quoted string has no escapes
+//! at all, which is handy for regexes that contain double quotes: `'"[^"]*"'`.
+//!
+//! If the first statement isn't `whitespace`, Unicode's Pattern_White_Space is used.
+
+use crate::grammar::{Grammar, GrammarError, Pattern, PatternGroup, Repetition};
+use crate::op::{Assoc, Fixity};
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// An error while reading a grammar file.
+#[derive(Error, Debug)]
+pub enum GrammarFileError {
+    /// The file could not be read.
+    #[error("Could not read grammar file '{0}'. {1}")]
+    IoError(String, std::io::Error),
+    /// A statement was malformed. Lines are numbered from 1.
+    #[error("Grammar file syntax error on line {line}. {message}")]
+    SyntaxError { line: usize, message: String },
+    /// A statement was well formed, but the grammar rejected it. Lines are numbered from 1.
+    #[error("Grammar error on line {line}. {error}")]
+    GrammarError { line: usize, error: GrammarError },
+}
+
+#[derive(Debug, Clone)]
+enum Word {
+    Quoted(String),
+    Bare(String),
+}
+
+impl Grammar {
+    /// Read a grammar from a grammar file. See the README for the file format.
+    pub fn load(path: impl AsRef<Path>) -> Result<Grammar, GrammarFileError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| GrammarFileError::IoError(path.to_string_lossy().into_owned(), err))?;
+        text.parse()
+    }
+}
+
+impl FromStr for Grammar {
+    type Err = GrammarFileError;
+
+    /// Parse a grammar from the contents of a grammar file. See the README for the file format.
+    fn from_str(text: &str) -> Result<Grammar, GrammarFileError> {
+        let mut statements = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let words = split_words(line).map_err(|message| syntax_error(i + 1, message))?;
+            if !words.is_empty() {
+                statements.push((i + 1, words));
+            }
+        }
+
+        let mut statements = statements.into_iter().peekable();
+        let mut grammar = match statements.next_if(|(_, words)| is_keyword(&words[0], "whitespace"))
+        {
+            Some((line, words)) => {
+                let [regex] = quoted_args(line, &words[1..])?;
+                Grammar::new(&regex)
+                    .map_err(|error| GrammarFileError::GrammarError { line, error })?
+            }
+            None => Grammar::new_with_unicode_whitespace().unwrap(),
+        };
+        for (line, words) in statements {
+            grammar.add_statement(line, &words)?;
+        }
+        Ok(grammar)
+    }
+}
+
+impl Grammar {
+    fn add_statement(&mut self, line: usize, words: &[Word]) -> Result<(), GrammarFileError> {
+        let keyword = match &words[0] {
+            Word::Bare(keyword) => keyword.as_str(),
+            Word::Quoted(_) => {
+                return Err(syntax_error(
+                    line,
+                    "Expected a statement, but found a quoted string.".to_owned(),
+                ))
+            }
+        };
+        let args = &words[1..];
+        let no_args = || {
+            if args.is_empty() {
+                Ok(())
+            } else {
+                Err(syntax_error(
+                    line,
+                    format!("'{}' does not take any arguments.", keyword),
+                ))
+            }
+        };
+        let result = match keyword {
+            "whitespace" => {
+                return Err(syntax_error(
+                    line,
+                    "'whitespace' must be the first statement.".to_owned(),
+                ))
+            }
+            "line_comment" => {
+                let [start] = quoted_args(line, args)?;
+                self.line_comment(&start);
+                Ok(())
+            }
+            "block_comment" => {
+                let nested = args.last().map(|w| is_keyword(w, "nested")) == Some(true);
+                let args = if nested {
+                    &args[..args.len() - 1]
+                } else {
+                    args
+                };
+                let [start, end] = quoted_args(line, args)?;
+                self.block_comment(&start, &end, nested);
+                Ok(())
+            }
+            "regex" => {
+                let (name, [regex]) = named_args(line, args)?;
+                self.regex(&name, &regex).map(|_| ())
+            }
+            "string" => {
+                let (name, [string]) = named_args(line, args)?;
+                self.string(&name, &string).map(|_| ())
+            }
+            "operand_only" | "operator_only" => {
+                let token = match args {
+                    [Word::Bare(token)] | [Word::Quoted(token)] => token,
+                    _ => {
+                        return Err(syntax_error(
+                            line,
+                            format!("'{}' expects one token name.", keyword),
+                        ))
+                    }
+                };
+                if keyword == "operand_only" {
+                    self.operand_only(token)
+                } else {
+                    self.operator_only(token)
+                }
+            }
+            "juxtapose" => {
+                no_args()?;
+                self.juxtapose()
+            }
+            "left_assoc" => {
+                no_args()?;
+                self.left_assoc();
+                Ok(())
+            }
+            "right_assoc" => {
+                no_args()?;
+                self.right_assoc();
+                Ok(())
+            }
+            "non_assoc" => {
+                no_args()?;
+                self.non_assoc();
+                Ok(())
+            }
+            "main_subgrammar" => {
+                no_args()?;
+                self.main_subgrammar();
+                Ok(())
+            }
+            "group" => match args {
+                [Word::Bare(name), Word::Bare(assoc)] => {
+                    let assoc = match assoc.as_str() {
+                        "left" => Assoc::Left,
+                        "right" => Assoc::Right,
+                        "non_assoc" => Assoc::NonAssoc,
+                        _ => {
+                            return Err(syntax_error(
+                                line,
+                                format!("Unknown associativity '{}'.", assoc),
+                            ))
+                        }
+                    };
+                    self.group(name, assoc)
+                }
+                _ => {
+                    return Err(syntax_error(
+                        line,
+                        "'group' expects an unquoted name and an associativity.".to_owned(),
+                    ))
+                }
+            },
+            "tighter_than" => match args {
+                [Word::Bare(tighter), Word::Bare(looser)] => {
+                    self.tighter_than(tighter, looser);
+                    Ok(())
+                }
+                _ => {
+                    return Err(syntax_error(
+                        line,
+                        "'tighter_than' expects two unquoted group names.".to_owned(),
+                    ))
+                }
+            },
+            "subgrammar" => match args {
+                [Word::Bare(name)] => {
+                    self.subgrammar(name);
+                    Ok(())
+                }
+                _ => {
+                    return Err(syntax_error(
+                        line,
+                        "'subgrammar' expects one unquoted name.".to_owned(),
+                    ))
+                }
+            },
+            "list_op" => {
+                let (name, [separator]) = named_args(line, args)?;
+                self.list_op(&name, &separator).map(|_| ())
+            }
+            "op" => match args {
+                [Word::Bare(name), pattern @ ..] => {
+                    let pattern =
+                        parse_pattern(pattern).map_err(|message| syntax_error(line, message))?;
+                    self.op(name, pattern).map(|_| ())
+                }
+                _ => {
+                    return Err(syntax_error(
+                        line,
+                        "'op' expects an unquoted name followed by a pattern.".to_owned(),
+                    ))
+                }
+            },
+            _ => {
+                return Err(syntax_error(
+                    line,
+                    format!("Unknown statement '{}'.", keyword),
+                ))
+            }
+        };
+        result.map_err(|error| GrammarFileError::GrammarError { line, error })
+    }
+}
+
+fn is_keyword(word: &Word, keyword: &str) -> bool {
+    matches!(word, Word::Bare(word) if word == keyword)
+}
+
+fn syntax_error(line: usize, message: String) -> GrammarFileError {
+    GrammarFileError::SyntaxError { line, message }
+}
+
+/// Expect exactly `N` quoted strings.
+fn quoted_args<const N: usize>(
+    line: usize,
+    args: &[Word],
+) -> Result<[String; N], GrammarFileError> {
+    let strings = args
+        .iter()
+        .filter_map(|word| match word {
+            Word::Quoted(string) => Some(string.clone()),
+            Word::Bare(_) => None,
+        })
+        .collect::<Vec<_>>();
+    if strings.len() != args.len() || strings.len() != N {
+        let plural = if N == 1 { "" } else { "s" };
+        return Err(syntax_error(
+            line,
+            format!("Expected {} quoted string{}.", N, plural),
+        ));
+    }
+    Ok(strings.try_into().unwrap())
+}
+
+/// Expect an unquoted name followed by exactly `N` quoted strings.
+fn named_args<const N: usize>(
+    line: usize,
+    args: &[Word],
+) -> Result<(String, [String; N]), GrammarFileError> {
+    match args {
+        [Word::Bare(name), rest @ ..] => Ok((name.clone(), quoted_args(line, rest)?)),
+        _ => Err(syntax_error(line, "Expected an unquoted name.".to_owned())),
+    }
+}
+
+/// Parse a pattern, written like in the `pattern!` macro.
+fn parse_pattern(words: &[Word]) -> Result<Pattern, String> {
+    let has_left_arg = words.first().map(|w| is_keyword(w, "_")) == Some(true);
+    let words = if has_left_arg { &words[1..] } else { words };
+    let has_right_arg = words.last().map(|w| is_keyword(w, "_")) == Some(true);
+    let words = if has_right_arg {
+        &words[..words.len() - 1]
+    } else {
+        words
+    };
+
+    let mut tokens = Vec::new();
+    let mut subgrammars = Vec::new();
+    let mut groups = Vec::new();
+    let mut group_start = None;
+    let mut subgrammar = None;
+    for word in words {
+        let token = match word {
+            Word::Quoted(token) => token,
+            Word::Bare(token) if ["INDENT", "DEDENT", "NEWLINE"].contains(&token.as_str()) => token,
+            Word::Bare(name) if name == "_" => {
+                return Err("An '_' may only be at the start or end of a pattern.".to_owned())
+            }
+            Word::Bare(paren) if paren == "(" => {
+                if tokens.is_empty() {
+                    return Err("A pattern can't start with a group.".to_owned());
+                }
+                if group_start.is_some() {
+                    return Err("Groups can't be nested.".to_owned());
+                }
+                group_start = Some(tokens.len());
+                continue;
+            }
+            Word::Bare(paren) if paren == ")*" || paren == ")?" => {
+                let start = match group_start.take() {
+                    Some(start) if start < tokens.len() => start,
+                    Some(_) => return Err("A group must have at least one token.".to_owned()),
+                    None => return Err(format!("Unmatched '{}'.", paren)),
+                };
+                let repetition = if paren == ")*" {
+                    Repetition::Many
+                } else {
+                    Repetition::Optional
+                };
+                groups.push(PatternGroup {
+                    tokens: start..tokens.len(),
+                    repetition,
+                });
+                continue;
+            }
+            Word::Bare(name) => {
+                if tokens.is_empty() || subgrammar.is_some() {
+                    return Err(format!(
+                        "The sub-grammar '{}' must be between two tokens.",
+                        name
+                    ));
+                }
+                subgrammar = Some(name.clone());
+                continue;
+            }
+        };
+        if !tokens.is_empty() {
+            subgrammars.push(subgrammar.take());
+        }
+        tokens.push(token.clone());
+    }
+    if let Some(name) = subgrammar {
+        return Err(format!(
+            "The sub-grammar '{}' must be between two tokens.",
+            name
+        ));
+    }
+    if group_start.is_some() {
+        return Err("A group is missing its ')*' or ')?'.".to_owned());
+    }
+    if tokens.is_empty() {
+        return Err("A pattern must have at least one token.".to_owned());
+    }
+
+    let fixity = match (has_left_arg, has_right_arg) {
+        (false, false) => Fixity::Nilfix,
+        (false, true) => Fixity::Prefix,
+        (true, false) => Fixity::Suffix,
+        (true, true) => Fixity::Infix,
+    };
+    Ok(Pattern {
+        fixity,
+        tokens,
+        subgrammars,
+        groups,
+    })
+}
+
+/// Split a line into words and quoted strings, dropping any comment.
+fn split_words(line: &str) -> Result<Vec<Word>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch.is_whitespace() {
+            continue;
+        } else if ch == '#' {
+            break;
+        } else if ch == '"' {
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    None => return Err("Unterminated string.".to_owned()),
+                    Some('"') => break,
+                    Some('\\') if matches!(chars.peek(), Some('"') | Some('\\')) => {
+                        string.push(chars.next().unwrap())
+                    }
+                    Some(ch) => string.push(ch),
+                }
+            }
+            words.push(Word::Quoted(string));
+        } else if ch == '\'' {
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    None => return Err("Unterminated string.".to_owned()),
+                    Some('\'') => break,
+                    Some(ch) => string.push(ch),
+                }
+            }
+            words.push(Word::Quoted(string));
+        } else {
+            let mut word = ch.to_string();
+            while let Some(ch) =
+                chars.next_if(|ch| !ch.is_whitespace() && *ch != '"' && *ch != '\'')
+            {
+                word.push(ch);
+            }
+            words.push(Word::Bare(word));
+        }
+    }
+    Ok(words)
+}
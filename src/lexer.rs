@@ -33,9 +33,31 @@
 //!   used.
 //! - If there is _still_ a tie, the regex that's first in the list provided to `Lexer::new()` will
 //!   be used.
+//!
+//! Tokens can also be restricted to only be produced when the parser expects an operand (an atom
+//! or prefix operator), or only when it expects an operator (an infix or suffix operator). This
+//! only takes effect when lexing with `Lexer::lex_modal`, which lets the parser say which it
+//! expects. For example, with `-1` marked as operand-only, `x -1` lexes as `x`, `-`, `1` while
+//! `x * -1` lexes as `x`, `*`, `-1`. If no allowed token matches, every token is considered.
+//!
+//! If layout is enabled (see `LexerBuilder::layout`), the lexer also produces empty lexemes
+//! describing the indentation at the start of each line that has a token on it: a
+//! `TOKEN_INDENT` if it's indented further than the line before it, one `TOKEN_DEDENT` for each
+//! enclosing block it ends followed by a `TOKEN_NEWLINE` if it's indented less, and just a
+//! `TOKEN_NEWLINE` if it's indented the same. Indentation that doesn't match any enclosing block
+//! produces a `TOKEN_BAD_INDENT`. Indentation is compared as strings, so tabs and spaces never
+//! match each other.
+//!
+//! Comments are skipped like whitespace, but their spans are recorded (see `LexemeIter::comments`).
+//! A comment start takes priority over any token. An unterminated block comment is a lexing error.
 
-use crate::{Lexeme, Offset, Position, Span, Token, TOKEN_ERROR};
+use crate::{
+    Lexeme, Offset, Span, Token, TOKEN_BAD_INDENT, TOKEN_DEDENT, TOKEN_ERROR, TOKEN_INDENT,
+    TOKEN_NEWLINE,
+};
 use regex::{escape, Regex, RegexSet};
+use regex_syntax::hir::{Class, Hir, HirKind, Literal};
+use std::collections::VecDeque;
 
 pub use regex::Error as RegexError;
 
@@ -57,22 +79,147 @@ impl PartialEq for Pattern {
 
 impl Eq for Pattern {}
 
+/// When a token may be produced by `Lexer::lex_modal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenMode {
+    /// Whether or not an operand is expected.
+    Any,
+    /// Only where an operand (an atom or prefix operator) is expected.
+    OperandOnly,
+    /// Only where an operator (an infix or suffix operator) is expected.
+    OperatorOnly,
+}
+
+impl TokenMode {
+    fn allows(self, arg_mode: bool) -> bool {
+        match self {
+            TokenMode::Any => true,
+            TokenMode::OperandOnly => arg_mode,
+            TokenMode::OperatorOnly => !arg_mode,
+        }
+    }
+}
+
+/// A source of lexemes for the parser, that may depend on whether the parser expects an operand
+/// or an operator next. Every `Iterator` over lexemes is a `TokenSource` that ignores this.
+pub trait TokenSource {
+    /// Produce the next lexeme. `arg_mode` is true if an operand (an atom or prefix operator) is
+    /// expected, and false if an operator (an infix or suffix operator) is expected.
+    fn next_lexeme(&mut self, arg_mode: bool) -> Option<Lexeme>;
+}
+
+impl<I: Iterator<Item = Lexeme>> TokenSource for I {
+    fn next_lexeme(&mut self, _arg_mode: bool) -> Option<Lexeme> {
+        self.next()
+    }
+}
+
+/// A lexeme produced for the parser, and whether an operand was expected when it was requested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Lexed {
+    pub(crate) lexeme: Lexeme,
+    pub(crate) arg_mode: bool,
+}
+
+/// A `TokenSource` that remembers every lexeme it produced.
+pub(crate) struct RecordingTokenSource<T: TokenSource> {
+    pub(crate) inner: T,
+    pub(crate) recorded: Vec<Lexed>,
+}
+
+impl<T: TokenSource> RecordingTokenSource<T> {
+    pub(crate) fn new(inner: T) -> RecordingTokenSource<T> {
+        RecordingTokenSource {
+            inner,
+            recorded: Vec::new(),
+        }
+    }
+}
+
+impl<T: TokenSource> TokenSource for &mut RecordingTokenSource<T> {
+    fn next_lexeme(&mut self, arg_mode: bool) -> Option<Lexeme> {
+        let lexeme = self.inner.next_lexeme(arg_mode)?;
+        self.recorded.push(Lexed { lexeme, arg_mode });
+        Some(lexeme)
+    }
+}
+
+/// A kind of comment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Comment {
+    /// Starts with the string, and goes until the end of the line.
+    Line(String),
+    /// Starts with `start` and ends with `end`. If `nested`, each inner `start` needs a matching
+    /// `end`.
+    Block {
+        start: String,
+        end: String,
+        nested: bool,
+    },
+}
+
+impl Comment {
+    fn start(&self) -> &str {
+        match self {
+            Comment::Line(start) => start,
+            Comment::Block { start, .. } => start,
+        }
+    }
+
+    /// The length of the comment at the beginning of `source`, or `None` if it never ends.
+    fn len(&self, source: &str) -> Option<usize> {
+        match self {
+            Comment::Line(_) => Some(source.find('\n').unwrap_or(source.len())),
+            Comment::Block { start, end, nested } => {
+                let mut depth = 0;
+                let mut len = 0;
+                while len < source.len() {
+                    let rest = &source[len..];
+                    if (depth == 0 || *nested) && rest.starts_with(start.as_str()) {
+                        depth += 1;
+                        len += start.len();
+                    } else if rest.starts_with(end.as_str()) {
+                        depth -= 1;
+                        len += end.len();
+                        if depth == 0 {
+                            return Some(len);
+                        }
+                    } else {
+                        len += rest.chars().next().unwrap().len_utf8();
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
 /// A builder for `Lexer`. Specify the patterns to match.
 #[derive(Debug, Clone)]
 pub struct LexerBuilder {
     whitespace: Regex,
+    layout: bool,
+    comments: Vec<Comment>,
     patterns: Vec<Pattern>,
+    modes: Vec<TokenMode>,
 }
 
 impl LexerBuilder {
     pub fn new(whitespace_regex: &str) -> Result<LexerBuilder, RegexError> {
         let mut builder = LexerBuilder {
             whitespace: new_regex(whitespace_regex)?,
+            layout: false,
+            comments: vec![],
             patterns: vec![],
+            modes: vec![],
         };
         builder.reserve_token()?; // Reserved for TOKEN_ERROR
         builder.reserve_token()?; // Reserved for TOKEN_BLANK
         builder.reserve_token()?; // Reserved for TOKEN_JUXTAPOSE
+        builder.reserve_token()?; // Reserved for TOKEN_INDENT
+        builder.reserve_token()?; // Reserved for TOKEN_DEDENT
+        builder.reserve_token()?; // Reserved for TOKEN_NEWLINE
+        builder.reserve_token()?; // Reserved for TOKEN_BAD_INDENT
         Ok(builder)
     }
 
@@ -93,6 +240,7 @@ impl LexerBuilder {
 
         let token = self.patterns.len();
         self.patterns.push(pattern);
+        self.modes.push(TokenMode::Any);
         Ok(token)
     }
 
@@ -115,27 +263,70 @@ impl LexerBuilder {
 
         let token = self.patterns.len();
         self.patterns.push(pattern);
+        self.modes.push(TokenMode::Any);
         Ok(token)
     }
 
     /// Reserve a token for personal use.
     pub fn reserve_token(&mut self) -> Result<Token, RegexError> {
         let pattern = Pattern {
-            regex: Regex::new("$.")?,
+            regex: Regex::new("^$.")?,
             length: None,
         };
 
         let token = self.patterns.len();
         self.patterns.push(pattern);
+        self.modes.push(TokenMode::Any);
         Ok(token)
     }
 
+    /// Restrict when `token` may be produced by `Lexer::lex_modal`. Every token starts out as
+    /// `TokenMode::Any`.
+    pub fn set_mode(&mut self, token: Token, mode: TokenMode) {
+        self.modes[token] = mode;
+    }
+
+    /// Produce `TOKEN_INDENT`, `TOKEN_DEDENT`, `TOKEN_NEWLINE`, and `TOKEN_BAD_INDENT` lexemes based
+    /// on the indentation of each line.
+    pub fn layout(&mut self) {
+        self.layout = true;
+    }
+
+    /// Skip comments that begin with `start` and continue until the end of the line.
+    pub fn line_comment(&mut self, start: &str) {
+        assert!(!start.is_empty(), "Comment start must not be empty");
+        self.comments.push(Comment::Line(start.to_owned()));
+    }
+
+    /// Skip comments that begin with `start` and end with `end`. If `nested` is true, comments
+    /// can be nested inside each other, like `/* a /* b */ c */`.
+    pub fn block_comment(&mut self, start: &str, end: &str, nested: bool) {
+        assert!(
+            !start.is_empty() && !end.is_empty(),
+            "Comment delimiters must not be empty"
+        );
+        self.comments.push(Comment::Block {
+            start: start.to_owned(),
+            end: end.to_owned(),
+            nested,
+        });
+    }
+
     /// Call this when you're done adding token patterns, to construct the lexer.
     pub fn finish(self) -> Result<Lexer, RegexError> {
+        let multiline = !is_repeated_class(&parse_hir(self.whitespace.as_str()))
+            || self
+                .patterns
+                .iter()
+                .any(|p| can_match_newline(&parse_hir(p.regex.as_str())));
         Ok(Lexer {
+            multiline,
             whitespace: self.whitespace,
+            layout: self.layout,
+            comments: self.comments,
             regex_set: RegexSet::new(self.patterns.iter().map(|p| p.regex.as_str()))?,
             patterns: self.patterns,
+            modes: self.modes,
         })
     }
 }
@@ -144,21 +335,108 @@ fn new_regex(regex: &str) -> Result<Regex, RegexError> {
     Regex::new(&format!("^({})", regex))
 }
 
+/// Parse a regex that `Regex::new` already accepted.
+fn parse_hir(regex: &str) -> Hir {
+    regex_syntax::Parser::new()
+        .parse(regex)
+        .expect("regex was already checked")
+}
+
+/// Whether any string that `hir` matches contains a newline.
+fn can_match_newline(hir: &Hir) -> bool {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Anchor(_) | HirKind::WordBoundary(_) => false,
+        HirKind::Literal(Literal::Unicode(ch)) => *ch == '\n',
+        HirKind::Literal(Literal::Byte(byte)) => *byte == b'\n',
+        HirKind::Class(Class::Unicode(class)) => class
+            .iter()
+            .any(|range| range.start() <= '\n' && '\n' <= range.end()),
+        HirKind::Class(Class::Bytes(class)) => class
+            .iter()
+            .any(|range| range.start() <= b'\n' && b'\n' <= range.end()),
+        HirKind::Repetition(rep) => can_match_newline(&rep.hir),
+        HirKind::Group(group) => can_match_newline(&group.hir),
+        HirKind::Concat(hirs) | HirKind::Alternation(hirs) => hirs.iter().any(can_match_newline),
+    }
+}
+
+/// Whether `hir` is `^(C*)` or `^(C+)` (or similar) for a character class `C`, so that it stops
+/// matching at the first character not in `C`.
+fn is_repeated_class(hir: &Hir) -> bool {
+    match hir.kind() {
+        HirKind::Concat(hirs) if hirs.len() == 2 => {
+            matches!(hirs[0].kind(), HirKind::Anchor(_)) && is_repeated_class(&hirs[1])
+        }
+        HirKind::Group(group) => is_repeated_class(&group.hir),
+        HirKind::Repetition(rep) => {
+            matches!(rep.hir.kind(), HirKind::Class(_) | HirKind::Literal(_))
+        }
+        _ => false,
+    }
+}
+
 /// A set of patterns to use to lex.
 #[derive(Debug, Clone)]
 pub struct Lexer {
+    // Whether lexing a token might look past the end of the line it starts on
+    multiline: bool,
     whitespace: Regex,
+    layout: bool,
+    comments: Vec<Comment>,
     patterns: Vec<Pattern>,
+    modes: Vec<TokenMode>,
     regex_set: RegexSet,
 }
 
 impl Lexer {
     /// Split `source` into a stream of lexemes. It is frequently useful to wrap this in
     /// [`iter::Peekable`](https://doc.rust-lang.org/stable/std/iter/struct.Peekable.html).
-    pub fn lex<'l, 's: 'l>(&'l self, source: &'s str) -> impl Iterator<Item = Lexeme> + 'l {
+    ///
+    /// This ignores token modes: every token may be produced anywhere.
+    pub fn lex<'l, 's: 'l>(&'l self, source: &'s str) -> LexemeIter<'l, 's> {
         LexemeIter::new(self, source)
     }
 
+    /// Split `source` into a stream of lexemes, only producing each token where its `TokenMode`
+    /// allows it.
+    pub fn lex_modal<'l, 's: 'l>(&'l self, source: &'s str) -> ModalLexemeIter<'l, 's> {
+        ModalLexemeIter(LexemeIter::new(self, source))
+    }
+
+    /// Like `lex_modal`, but start lexing at `offset`, which must be where the lexer stopped after
+    /// some lexeme. Only valid without layout, since the indentation of the enclosing blocks
+    /// isn't known.
+    pub(crate) fn lex_modal_at<'l, 's: 'l>(
+        &'l self,
+        source: &'s str,
+        offset: Offset,
+    ) -> ModalLexemeIter<'l, 's> {
+        let mut iter = LexemeIter::new(self, source);
+        iter.source = &source[offset..];
+        iter.offset = offset;
+        iter.line_start = source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        ModalLexemeIter(iter)
+    }
+
+    /// Whether this lexer produces layout lexemes (see `LexerBuilder::layout`).
+    pub(crate) fn has_layout(&self) -> bool {
+        self.layout
+    }
+
+    /// Whether lexing a token might depend on the text after the line that it starts on. This is
+    /// false if no token's regex can match a newline, and the whitespace regex is a repeated
+    /// character class (like `UNICODE_WHITESPACE_REGEX`), so that every match attempt ends by the
+    /// end of its line.
+    pub(crate) fn is_multiline(&self) -> bool {
+        self.multiline
+    }
+
+    /// Whether any token is restricted by a `TokenMode`, so that lexing depends on whether an
+    /// operand is expected.
+    pub(crate) fn has_modes(&self) -> bool {
+        self.modes.iter().any(|mode| *mode != TokenMode::Any)
+    }
+
     /// The number of tokens. Each `Token` returned by the builder is guaranteed to be smaller than
     /// this number.
     pub fn num_tokens(&self) -> usize {
@@ -166,38 +444,97 @@ impl Lexer {
     }
 }
 
+/// The lexemes of a source string. Constructed with `Lexer::lex`.
 #[derive(Debug, Clone)]
-struct LexemeIter<'l, 's> {
+pub struct LexemeIter<'l, 's> {
     lexer: &'l Lexer,
+    // The whole source
+    text: &'s str,
+    // The rest of the source
     source: &'s str,
-    position: Position,
     offset: Offset,
+    // The offset of the start of the current line
+    line_start: Offset,
+    comments: Vec<Span>,
+    // Layout: the start of the line that the last lexeme ended on, the indentation of each
+    // enclosing block, and lexemes to produce before lexing any further.
+    last_line: Option<Offset>,
+    indents: Vec<&'s str>,
+    pending: VecDeque<Lexeme>,
+    // Whether the last token lexed could have lexed differently in another mode
+    mode_sensitive: bool,
 }
 
 impl<'l, 's> LexemeIter<'l, 's> {
     fn new(lexer: &'l Lexer, source: &'s str) -> LexemeIter<'l, 's> {
         LexemeIter {
             lexer,
+            text: source,
             source,
-            position: Position {
-                line: 0,
-                col: 0,
-                utf8_col: 0,
-            },
             offset: 0,
+            line_start: 0,
+            comments: vec![],
+            last_line: None,
+            indents: vec![""],
+            pending: VecDeque::new(),
+            mode_sensitive: false,
         }
     }
 
+    /// The spans of the comments skipped so far, in order.
+    pub fn comments(&self) -> &[Span] {
+        &self.comments
+    }
+
+    /// The spans of the comments skipped so far, in order.
+    pub fn into_comments(self) -> Vec<Span> {
+        self.comments
+    }
+
     fn consume(&mut self, len: usize) -> Span {
-        let start = self.position;
-        for ch in self.source[..len].chars() {
-            self.offset += ch.len_utf8();
-            self.position = self.position.advance_by_char(ch);
+        let start = self.offset;
+        if let Some(newline) = self.source[..len].rfind('\n') {
+            self.line_start = start + newline + 1;
         }
-        let end = self.position;
-
+        self.offset += len;
         self.source = &self.source[len..];
-        Span { start, end }
+        Span::new(start, self.offset)
+    }
+
+    /// Queue up the layout lexemes for the start of a new line, which is indented by the
+    /// whitespace at its start.
+    fn layout(&mut self) {
+        let here = Span::new_at(self.offset);
+        // Comments before the first token on the line don't count as indentation
+        let line = &self.text[self.line_start..self.offset];
+        let indent_len = self.lexer.whitespace.find(line).map_or(0, |m| m.end());
+        let indent = &line[..indent_len];
+        let mut top = *self.indents.last().unwrap();
+        if indent.len() > top.len() && indent.starts_with(top) {
+            self.indents.push(indent);
+            self.pending
+                .push_back(Lexeme::new(TOKEN_INDENT, here.start, here.end));
+            return;
+        }
+        let mut num_dedents = 0;
+        while !indent.starts_with(top) {
+            self.indents.pop();
+            num_dedents += 1;
+            top = *self.indents.last().unwrap();
+        }
+        if indent != top {
+            let indent_end = self.line_start + indent_len;
+            let bad_indent = Lexeme::new(TOKEN_BAD_INDENT, self.line_start, indent_end);
+            self.pending.push_back(bad_indent);
+        }
+        for _ in 0..num_dedents {
+            self.pending
+                .push_back(Lexeme::new(TOKEN_DEDENT, here.start, here.end));
+        }
+        if self.last_line.is_some() {
+            self.pending
+                .push_back(Lexeme::new(TOKEN_NEWLINE, here.start, here.end));
+        }
     }
 }
 
@@ -205,19 +542,136 @@ impl Iterator for LexemeIter<'_, '_> {
     type Item = Lexeme;
 
     fn next(&mut self) -> Option<Lexeme> {
-        // Consume whitespace
-        if let Some(span) = self.lexer.whitespace.find(self.source) {
-            self.consume(span.end());
+        self.next_in_mode(None)
+    }
+}
+
+/// The lexemes of a source string, lexed according to each token's `TokenMode`. Constructed with
+/// `Lexer::lex_modal`.
+#[derive(Debug, Clone)]
+pub struct ModalLexemeIter<'l, 's>(LexemeIter<'l, 's>);
+
+impl<'l, 's> ModalLexemeIter<'l, 's> {
+    /// The spans of the comments skipped so far, in order.
+    pub fn comments(&self) -> &[Span] {
+        self.0.comments()
+    }
+
+    /// The spans of the comments skipped so far, in order.
+    pub fn into_comments(self) -> Vec<Span> {
+        self.0.into_comments()
+    }
+
+    /// Remove and return the spans of the comments skipped so far.
+    pub(crate) fn take_comments(&mut self) -> Vec<Span> {
+        std::mem::take(&mut self.0.comments)
+    }
+
+    /// Lex the next lexeme, like `next_lexeme`. Also say whether it might have lexed differently
+    /// if `arg_mode` was different (because a token with a `TokenMode` matched here).
+    pub(crate) fn next_speculative(&mut self, arg_mode: bool) -> Option<(Lexeme, bool)> {
+        let lexeme = self.0.next_in_mode(Some(arg_mode))?;
+        Some((lexeme, self.0.mode_sensitive))
+    }
+}
+
+impl TokenSource for ModalLexemeIter<'_, '_> {
+    fn next_lexeme(&mut self, arg_mode: bool) -> Option<Lexeme> {
+        self.0.next_in_mode(Some(arg_mode))
+    }
+}
+
+impl TokenSource for &mut ModalLexemeIter<'_, '_> {
+    fn next_lexeme(&mut self, arg_mode: bool) -> Option<Lexeme> {
+        self.0.next_in_mode(Some(arg_mode))
+    }
+}
+
+impl LexemeIter<'_, '_> {
+    /// Lex the next lexeme. If `arg_mode` is given, prefer tokens whose mode allows it.
+    fn next_in_mode(&mut self, arg_mode: Option<bool>) -> Option<Lexeme> {
+        self.mode_sensitive = false;
+        if let Some(lexeme) = self.pending.pop_front() {
+            return Some(lexeme);
+        }
+
+        // Consume whitespace and comments
+        let lexer = self.lexer;
+        loop {
+            if let Some(span) = lexer.whitespace.find(self.source) {
+                self.consume(span.end());
+            }
+            let comment = match lexer
+                .comments
+                .iter()
+                .find(|comment| self.source.starts_with(comment.start()))
+            {
+                Some(comment) => comment,
+                None => break,
+            };
+            match comment.len(self.source) {
+                Some(len) => {
+                    let span = self.consume(len);
+                    self.comments.push(span);
+                }
+                None => {
+                    // Unterminated block comment
+                    let span = self.consume(self.source.len());
+                    return Some(Lexeme {
+                        token: TOKEN_ERROR,
+                        span,
+                    });
+                }
+            }
+        }
+
+        // Produce layout lexemes, at the start of each line and the end of the file.
+        if self.lexer.layout {
+            if self.source.is_empty() {
+                while self.indents.len() > 1 {
+                    self.indents.pop();
+                    let offset = self.offset;
+                    self.pending
+                        .push_back(Lexeme::new(TOKEN_DEDENT, offset, offset));
+                }
+            } else if self.last_line != Some(self.line_start) {
+                self.layout();
+            }
+            self.last_line = Some(self.line_start);
+            if let Some(lexeme) = self.pending.pop_front() {
+                return Some(lexeme);
+            }
         }
 
         // If we're at the end of the file, we're done.
         if self.source.is_empty() {
             return None;
         }
+        let lexeme = self.lex_token(arg_mode);
+        self.last_line = Some(self.line_start);
+        Some(lexeme)
+    }
+
+    /// Lex a token, which must be at the current position. Whitespace and comments must have
+    /// already been consumed.
+    fn lex_token(&mut self, arg_mode: Option<bool>) -> Lexeme {
+        // Only consider tokens allowed in this mode, unless none of them match.
+        let matches = self.lexer.regex_set.matches(self.source);
+        self.mode_sensitive = matches
+            .iter()
+            .any(|token| self.lexer.modes[token] != TokenMode::Any);
+        let allowed = |token: Token| match arg_mode {
+            None => true,
+            Some(arg_mode) => self.lexer.modes[token].allows(arg_mode),
+        };
+        let any_allowed = matches.iter().any(allowed);
 
         // Find the best match (longest, with a tie-breaker of is_str)
         let mut best_match: Option<(Token, usize, bool)> = None;
-        for token in &self.lexer.regex_set.matches(self.source) {
+        for token in matches
+            .iter()
+            .filter(|token| !any_allowed || allowed(*token))
+        {
             let pattern = &self.lexer.patterns[token];
 
             // Find the length (and tie-breaker is_str) of this match.
@@ -241,7 +695,7 @@ impl Iterator for LexemeIter<'_, '_> {
         // If there was a best match, consume and return it.
         if let Some((token, len, _)) = best_match {
             let span = self.consume(len);
-            return Some(Lexeme { token, span });
+            return Lexeme { token, span };
         }
 
         // Otherwise, nothing matched. Lex error! By definition we can't lex, but let's say the
@@ -252,9 +706,9 @@ impl Iterator for LexemeIter<'_, '_> {
             .find(basic_whitespace)
             .unwrap_or(self.source.len());
         let span = self.consume(len);
-        Some(Lexeme {
+        Lexeme {
             token: TOKEN_ERROR,
             span,
-        })
+        }
     }
 }
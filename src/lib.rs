@@ -1,25 +1,53 @@
+mod from_parse_tree;
 mod grammar;
+mod grammar_file;
 mod lexer;
 mod op;
+mod op_enum;
+mod parallel;
 mod parse_error;
 mod parse_tree;
+mod reparse;
 mod resolver;
 mod shunter;
 mod source;
+mod source_map;
+mod trace;
 mod tree_visitor;
 
 use lexer::Lexer;
-use op::Op;
+use op::{Op, PrecOrder};
+use parse_tree::Item;
+use reparse::ReparseState;
+use resolver::{Resolver, ResolverError};
+use shunter::Shunter;
+use std::mem;
+use tree_visitor::Forest;
 
-pub use grammar::{Grammar, GrammarError, Pattern};
-pub use op::{Fixity, Prec};
-pub use parse_error::ParseError;
-pub use parse_tree::{ParseTree, Visitor};
-pub use source::{Col, Line, Offset, Position, Source, Span};
+#[doc(hidden)]
+pub use from_parse_tree::derive_helpers;
+pub use from_parse_tree::FromParseTree;
+pub use grammar::{Grammar, GrammarError, Pattern, PatternGroup, PatternPart, Repetition};
+pub use grammar_file::GrammarFileError;
+pub use lexer::TokenSource;
+pub use op::{Assoc, Fixity, OpId, Prec};
+pub use op_enum::{OpEnum, OpMap, OpMapError};
+pub use panfix_derive::grammar;
+pub use panfix_derive::FromParseTree;
+pub use parse_error::{ErrorDisplay, OwnedParseError, ParseError};
+pub use parse_tree::{Clause, OwnedParseTree, OwnedVisitor, ParseTree, Trivia, Visitor};
+pub use source::{Col, Edit, Line, Offset, Position, Source, Span, DEFAULT_TAB_WIDTH};
+pub use source_map::{FileId, FileSpan, Location, SourceMap};
+pub use trace::{Trace, TraceEntry};
 
 /// A category of lexeme, such as "INTEGER" or "VARIABLE" or "OPEN_PAREN".
 pub type Token = usize;
 type OpToken = Token;
+/// Identifies a sub-grammar: see `Grammar::subgrammar`.
+type SubgrammarId = usize;
+
+/// The sub-grammar that top-level source code is parsed with.
+const MAIN_SUBGRAMMAR: SubgrammarId = 0;
 
 /// Represents a lexing error.
 pub const TOKEN_ERROR: Token = 0;
@@ -27,10 +55,23 @@ pub const TOKEN_ERROR: Token = 0;
 pub const TOKEN_BLANK: Token = 1;
 /// Represents a missing operator.
 pub const TOKEN_JUXTAPOSE: Token = 2;
+/// Represents an increase in indentation, at the start of a line.
+pub const TOKEN_INDENT: Token = 3;
+/// Represents a decrease in indentation, at the start of a line.
+pub const TOKEN_DEDENT: Token = 4;
+/// Represents the start of a new line, at the same indentation as the enclosing block.
+pub const TOKEN_NEWLINE: Token = 5;
+/// Represents indentation that doesn't match any enclosing block.
+pub const TOKEN_BAD_INDENT: Token = 6;
 
 const NAME_ERROR: &str = "LexError";
 const NAME_BLANK: &str = "Blank";
 const NAME_JUXTAPOSE: &str = "Juxtapose";
+const NAME_CHAIN_ERROR: &str = "ChainError";
+const NAME_INDENT: &str = "INDENT";
+const NAME_DEDENT: &str = "DEDENT";
+const NAME_NEWLINE: &str = "NEWLINE";
+const NAME_BAD_INDENT: &str = "BadIndent";
 
 /// One "word" in the stream returned by the lexer.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -44,12 +85,23 @@ pub struct Lexeme {
 pub struct Parser {
     lexer: Lexer,
     tok_to_name: Vec<String>,
-    tok_to_prefix: Vec<Option<(OpToken, bool)>>,
-    tok_to_suffix: Vec<Option<(OpToken, bool)>>,
-    optok_to_follower: Vec<Option<(Token, OpToken, bool)>>,
+    // Indexed by sub-grammar, then by token
+    tok_to_prefix: Vec<Vec<Option<(OpToken, bool)>>>,
+    tok_to_suffix: Vec<Vec<Option<(OpToken, bool)>>>,
+    optok_to_followers: Vec<Vec<(Token, OpToken, bool)>>,
+    // The op token to use instead if the op ends at this one, when it's followed by optional tokens
+    optok_to_final: Vec<Option<OpToken>>,
+    // The index of each op token in its op's pattern
+    optok_to_position: Vec<usize>,
+    optok_to_subgrammar: Vec<SubgrammarId>,
     optok_to_name: Vec<String>,
     optok_to_op: Vec<Option<Op>>,
+    // The op to use instead when an op is illegally chained or mixed with its argument
+    optok_to_chain_error: Vec<Option<Op>>,
     optok_to_prec: Vec<(Prec, Prec)>,
+    // Only present if there are named precedence groups
+    prec_order: Option<PrecOrder>,
+    op_names: Vec<String>,
 }
 
 impl Parser {
@@ -58,75 +110,491 @@ impl Parser {
         &'g self,
         source: &'s Source,
     ) -> Result<ParseTree<'s, 'g>, ParseError<'s>> {
-        use parse_tree::Item;
-        use resolver::resolve;
-        use shunter::shunt;
-        use tree_visitor::Forest;
-
-        // 1. Lex
-        let lexemes = self.lexer.lex(source.source());
-        #[cfg(feature = "debug_mode")]
-        let lexemes = self.print_lexemes(source, "Lexed:    ", lexemes);
-
-        // 2. Resolve
-        let lexemes = resolve(
+        // 1. Lex and 2. Resolve. These are interleaved, because the lexer needs to know whether
+        //    the resolver expects an operand or an operator.
+        let mut lexemes = self.lexer.lex_modal(source.source());
+        let resolved = self.resolve(source, &mut lexemes)?;
+        let comments = lexemes.into_comments();
+        let mut errors = Vec::new();
+        let tree = self.build_tree(source, resolved, comments, None, &mut errors, None);
+        match errors.into_iter().next() {
+            None => Ok(tree),
+            Some(error) => Err(error),
+        }
+    }
+
+    /// Parse `source`, recovering from errors instead of stopping at the first one. Always
+    /// produces a parse tree, together with every error found. Runs in linear time.
+    ///
+    /// - An unrecognized or unexpected token becomes a `"LexError"` node.
+    /// - A missing token of a multifix operator (like a missing `)`) is made up, with an empty
+    ///   span at the end of the preceding token.
+    /// - A non-associative operator that's chained with another from its group, or an operator
+    ///   that's mixed with one of incomparable precedence, becomes a `"ChainError"` node with the
+    ///   same tokens and children (see `Grammar::non_assoc` and `Grammar::group`).
+    ///
+    /// If the list of errors is empty, the tree is the same one that `parse` would produce.
+    pub fn parse_recovering<'s, 'g>(
+        &'g self,
+        source: &'s Source,
+    ) -> (ParseTree<'s, 'g>, Vec<ParseError<'s>>) {
+        // 1. Lex and 2. Resolve. These are interleaved, because the lexer needs to know whether
+        //    the resolver expects an operand or an operator.
+        let mut lexemes = self.lexer.lex_modal(source.source());
+        let (resolved, mut errors) = self.resolve_recovering(source, &mut lexemes);
+        let comments = lexemes.into_comments();
+        let tree = self.build_tree(source, resolved, comments, None, &mut errors, None);
+        (tree, errors)
+    }
+
+    /// Parse a stream of lexemes produced by your own lexer, instead of using the lexer built
+    /// from the grammar. Runs in linear time.
+    ///
+    /// The lexemes must use the tokens this parser assigned: see `.token_names()` and `.token()`.
+    /// They may also be `TOKEN_ERROR` (for lexing errors), or the layout tokens `TOKEN_INDENT`,
+    /// `TOKEN_DEDENT`, `TOKEN_NEWLINE`, and `TOKEN_BAD_INDENT`, if your lexer produces them.
+    /// Their spans refer to `source`. Any `Iterator` of `Lexeme`s will do; implement
+    /// `TokenSource` yourself if your lexer needs to know whether an operand or an operator is
+    /// expected next.
+    ///
+    /// Comments are not known to the parser, so the tree won't have any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a lexeme's token isn't one of this parser's tokens.
+    pub fn parse_tokens<'s, 'g>(
+        &'g self,
+        source: &'s Source,
+        lexemes: impl TokenSource,
+    ) -> Result<ParseTree<'s, 'g>, ParseError<'s>> {
+        let resolved = self.resolve(source, lexemes)?;
+        let mut errors = Vec::new();
+        let tree = self.build_tree(source, resolved, Vec::new(), None, &mut errors, None);
+        match errors.into_iter().next() {
+            None => Ok(tree),
+            Some(error) => Err(error),
+        }
+    }
+
+    /// Like `parse_tokens`, but recovering from errors like `parse_recovering` does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a lexeme's token isn't one of this parser's tokens.
+    pub fn parse_tokens_recovering<'s, 'g>(
+        &'g self,
+        source: &'s Source,
+        lexemes: impl TokenSource,
+    ) -> (ParseTree<'s, 'g>, Vec<ParseError<'s>>) {
+        let (resolved, mut errors) = self.resolve_recovering(source, lexemes);
+        let tree = self.build_tree(source, resolved, Vec::new(), None, &mut errors, None);
+        (tree, errors)
+    }
+
+    /// The name of every token, indexed by `Token`. A token made by `Grammar::string` or used in
+    /// a pattern is named by its string, and a token made by `Grammar::regex` is named by its
+    /// atom's name. The reserved tokens come first, starting with `TOKEN_ERROR`.
+    pub fn token_names(&self) -> &[String] {
+        &self.tok_to_name
+    }
+
+    /// Look up a token by its name (see `.token_names()`).
+    pub fn token(&self, name: &str) -> Option<Token> {
+        self.tok_to_name
+            .iter()
+            .position(|tok_name| tok_name == name)
+    }
+
+    /// The name of every op, indexed by `OpId::index`. The reserved ops `"LexError"`, `"Blank"`,
+    /// `"Juxtapose"`, and `"ChainError"` come first.
+    pub fn op_names(&self) -> &[String] {
+        &self.op_names
+    }
+
+    /// Look up an op by its name.
+    pub fn op_id(&self, name: &str) -> Option<OpId> {
+        self.op_names
+            .iter()
+            .position(|op_name| op_name == name)
+            .map(OpId)
+    }
+
+    /// Make a table for converting the `OpId`s of this parser into the enum `E` (see `op_enum!`).
+    /// Errors if `E` doesn't have exactly one variant for each op of the grammar, including
+    /// `LexError`, `Blank`, `Juxtapose`, and `ChainError`.
+    pub fn op_map<E: OpEnum>(&self) -> Result<OpMap<E>, OpMapError> {
+        OpMap::new(&self.op_names)
+    }
+
+    /// The op of an item of a parse tree made by this parser.
+    pub(crate) fn item_op(&self, item: &Item) -> &Op {
+        let ops = if item.chain_error {
+            &self.optok_to_chain_error
+        } else {
+            &self.optok_to_op
+        };
+        ops[item.optok].as_ref().unwrap()
+    }
+
+    fn resolve<'s>(
+        &self,
+        source: &'s Source,
+        lexemes: impl TokenSource,
+    ) -> Result<Vec<Lexeme>, ParseError<'s>> {
+        resolver::resolve(
             &self.tok_to_prefix,
             &self.tok_to_suffix,
-            &self.optok_to_follower,
+            &self.optok_to_followers,
+            &self.optok_to_final,
+            &self.optok_to_subgrammar,
             lexemes,
         )
-        .map_err(|err| {
-            ParseError::from_resolver_error(source, &self.tok_to_name, &self.optok_to_name, err)
-        })?;
-        #[cfg(feature = "debug_mode")]
-        let lexemes = self.print_lexemes(source, "Resolved: ", lexemes);
-
-        // 3. Shunt
-        let lexemes = shunt(&self.optok_to_prec, lexemes.into_iter());
-        #[cfg(feature = "debug_mode")]
-        let lexemes = self.print_lexemes(source, "Shunted:  ", lexemes);
+        .map_err(|err| self.resolver_error(source, err))
+    }
 
-        // 4. Filter
-        let lexemes = lexemes
+    fn resolve_recovering<'s>(
+        &self,
+        source: &'s Source,
+        lexemes: impl TokenSource,
+    ) -> (Vec<Lexeme>, Vec<ParseError<'s>>) {
+        let (resolved, errors) = resolver::resolve_recovering(
+            &self.tok_to_prefix,
+            &self.tok_to_suffix,
+            &self.optok_to_followers,
+            &self.optok_to_final,
+            &self.optok_to_subgrammar,
+            lexemes,
+        );
+        let errors = errors
             .into_iter()
-            .filter(|lex| self.optok_to_op[lex.token].is_some());
-        #[cfg(feature = "debug_mode")]
-        let lexemes = self.print_lexemes(source, "Filtered: ", lexemes);
+            .map(|err| self.resolver_error(source, err))
+            .collect::<Vec<_>>();
+        (resolved, errors)
+    }
 
-        // 5. Map to Op
-        let lexemes = lexemes.into_iter().map(|lex| Item {
-            op: self.optok_to_op[lex.token].as_ref().unwrap(),
-            span: lex.span,
-        });
+    /// A resolver for this grammar, to feed lexemes to one at a time.
+    fn resolver(&self, recover: bool) -> Resolver<'_> {
+        Resolver::new(
+            &self.tok_to_prefix,
+            &self.tok_to_suffix,
+            &self.optok_to_followers,
+            &self.optok_to_final,
+            &self.optok_to_subgrammar,
+            recover,
+        )
+    }
 
-        // 6. Construct forest
-        let forest = Forest::from_iter(lexemes);
-        Ok(ParseTree::new(source, self, forest))
+    fn resolver_error<'s>(&self, source: &'s Source, err: ResolverError) -> ParseError<'s> {
+        ParseError::from_resolver_error(source, &self.tok_to_name, &self.optok_to_name, err)
     }
 
-    #[cfg(feature = "debug_mode")]
-    fn print_lexemes(
-        &self,
-        source: &Source,
-        message: &str,
-        lexemes: impl IntoIterator<Item = Lexeme>,
-    ) -> impl IntoIterator<Item = Lexeme> {
-        eprint!("{}", message);
-        let lexemes = lexemes.into_iter().collect::<Vec<_>>();
-        for lexeme in &lexemes {
-            if lexeme.span.is_empty() {
-                eprint!("_ ");
-            } else {
-                eprint!("{} ", source.substr(lexeme.span));
+    /// If `item` is about to be pushed onto the forest, find an argument of it that it can't be
+    /// next to without parentheses: one on its left or right (or any element of a list) that's
+    /// from the same non-associative group, or from an incomparable group. Also gives which of
+    /// `item`'s arguments it is.
+    fn conflicting_arg(&self, forest: &Forest<Item>, item: &Item) -> Option<(Item, usize)> {
+        let op = self.item_op(item);
+        if op.assoc != Assoc::NonAssoc && self.prec_order.is_none() {
+            return None;
+        }
+        let num_trees = forest.num_trees();
+        let first_arg = num_trees - item.arity;
+        let args = if op.is_list {
+            (0..item.arity).collect::<Vec<_>>()
+        } else {
+            let left_arg = matches!(op.fixity, Fixity::Infix | Fixity::Suffix).then_some(0);
+            let right_arg =
+                matches!(op.fixity, Fixity::Infix | Fixity::Prefix).then(|| item.arity - 1);
+            left_arg.into_iter().chain(right_arg).collect()
+        };
+        args.into_iter()
+            .map(|i| (*forest.tree(first_arg + i).unwrap().item(), i))
+            .filter(|(arg, _)| self.item_op(arg).fixity != Fixity::Nilfix)
+            .find(|(arg, _)| {
+                let arg = self.item_op(arg);
+                if arg.prec == op.prec {
+                    op.assoc == Assoc::NonAssoc
+                } else {
+                    match &self.prec_order {
+                        Some(order) => !order.comparable(arg.prec, op.prec),
+                        None => false,
+                    }
+                }
+            })
+    }
+
+    /// Build a tree from resolved lexemes. Any illegally chained or mixed ops are added to
+    /// `errors`, keeping it in order. If `trace` is given, record the shunted and filtered lexemes in it.
+    /// `reparse` is what to keep in the tree for `reparse`, if anything.
+    fn build_tree<'s, 'g>(
+        &'g self,
+        source: &'s Source,
+        lexemes: Vec<Lexeme>,
+        comments: Vec<Span>,
+        reparse: Option<ReparseState>,
+        errors: &mut Vec<ParseError<'s>>,
+        trace: Option<&mut Trace<'g>>,
+    ) -> ParseTree<'s, 'g> {
+        let mut builder = TreeBuilder::new(self, trace);
+        for lex in lexemes {
+            builder.push(lex);
+        }
+        builder.finish(source, comments, reparse, errors)
+    }
+}
+
+/// An op that was illegally chained or mixed with its argument `arg`. The error points at `span`.
+#[derive(Debug, Clone, Copy)]
+struct Conflict {
+    arg: Item,
+    item: Item,
+    span: Span,
+}
+
+/// Builds a tree from resolved lexemes, one at a time. This is steps 3 to 5 of parsing.
+struct TreeBuilder<'g, 't> {
+    parser: &'g Parser,
+    shunter: Shunter<'g>,
+    // The lexemes output by the shunter that are yet to be added
+    shunted: Vec<Lexeme>,
+    forest: Forest<Item>,
+    token_spans: Vec<Span>,
+    token_positions: Vec<usize>,
+    // The followers of the next op, with their positions in its pattern. In RPN order, the
+    // followers of an op come immediately before its first token, in reverse order.
+    followers: Vec<(Span, usize)>,
+    // The separators of a chain of nested list ops, in RPN order (so right to left). Since
+    // list ops are right associative, each one in the chain is the last child of the next.
+    list: Option<(Token, Vec<Span>)>,
+    conflicts: Vec<Conflict>,
+    trace: Option<&'t mut Trace<'g>>,
+}
+
+/// The state of a `TreeBuilder` at some point, from which it can be resumed. See
+/// `TreeBuilder::checkpoint`.
+#[derive(Debug, Clone)]
+struct BuildCheckpoint {
+    /// The number of resolved lexemes pushed so far
+    num_resolved: usize,
+    shunter_stack: Vec<Lexeme>,
+    forest: tree_visitor::Checkpoint<Item>,
+    num_tokens: usize,
+    followers: Vec<(Span, usize)>,
+    list: Option<(Token, Vec<Span>)>,
+    num_conflicts: usize,
+}
+
+impl<'g, 't> TreeBuilder<'g, 't> {
+    fn new(parser: &'g Parser, trace: Option<&'t mut Trace<'g>>) -> TreeBuilder<'g, 't> {
+        TreeBuilder {
+            parser,
+            shunter: Shunter::new(&parser.optok_to_prec),
+            shunted: Vec::new(),
+            forest: Forest::new(),
+            token_spans: Vec::new(),
+            token_positions: Vec::new(),
+            followers: Vec::new(),
+            list: None,
+            conflicts: Vec::new(),
+            trace,
+        }
+    }
+
+    /// A builder in the state of `checkpoint`, which was taken while building `old` from
+    /// `old_conflicts`.
+    fn resume(
+        parser: &'g Parser,
+        old: &ParseTree,
+        old_conflicts: &[Conflict],
+        checkpoint: &BuildCheckpoint,
+    ) -> TreeBuilder<'g, 't> {
+        let (forest, token_spans, token_positions) = old.parts();
+        TreeBuilder {
+            parser,
+            shunter: Shunter::with_stack(&parser.optok_to_prec, checkpoint.shunter_stack.clone()),
+            shunted: Vec::new(),
+            forest: forest.at_checkpoint(&checkpoint.forest),
+            token_spans: token_spans[..checkpoint.num_tokens].to_vec(),
+            token_positions: token_positions[..checkpoint.num_tokens].to_vec(),
+            followers: checkpoint.followers.clone(),
+            list: checkpoint.list.clone(),
+            conflicts: old_conflicts[..checkpoint.num_conflicts].to_vec(),
+            trace: None,
+        }
+    }
+
+    /// Record the current state, after pushing `num_resolved` lexemes.
+    fn checkpoint(&self, num_resolved: usize) -> BuildCheckpoint {
+        BuildCheckpoint {
+            num_resolved,
+            shunter_stack: self.shunter.stack().to_vec(),
+            forest: self.forest.checkpoint(),
+            num_tokens: self.token_spans.len(),
+            followers: self.followers.clone(),
+            list: self.list.clone(),
+            num_conflicts: self.conflicts.len(),
+        }
+    }
+
+    /// Roughly how long it takes to make a checkpoint.
+    fn checkpoint_size(&self) -> usize {
+        let list_len = self.list.as_ref().map(|(_, seps)| seps.len()).unwrap_or(0);
+        self.shunter.stack().len() + self.forest.num_trees() + self.followers.len() + list_len
+    }
+
+    /// Add the next resolved lexeme.
+    fn push(&mut self, lex: Lexeme) {
+        // 3. Shunt
+        let mut shunted = mem::take(&mut self.shunted);
+        self.shunter.push(lex, &mut shunted);
+        for lex in shunted.drain(..) {
+            self.push_shunted(lex);
+        }
+        self.shunted = shunted;
+    }
+
+    /// Add the next shunted lexeme.
+    fn push_shunted(&mut self, lex: Lexeme) {
+        let parser = self.parser;
+        if let Some(trace) = &mut self.trace {
+            trace.shunted.push(parser.trace_optok(lex));
+        }
+        // 4. Collect token spans, and the position of each token in its op's pattern.
+        let op = parser.optok_to_op[lex.token].as_ref();
+        if let (Some(op), Some((list_token, separators))) = (op, &mut self.list) {
+            if op.is_list && *list_token == lex.token {
+                separators.push(lex.span);
+                return;
+            }
+        }
+        if let Some((list_token, separators)) = self.list.take() {
+            self.push_list(list_token, separators);
+        }
+        match op {
+            None => self
+                .followers
+                .push((lex.span, parser.optok_to_position[lex.token])),
+            Some(op) if op.is_list => self.list = Some((lex.token, vec![lex.span])),
+            Some(op) => {
+                if let Some(trace) = &mut self.trace {
+                    trace.filtered.push(parser.trace_optok(lex));
+                }
+                let first_token = self.token_spans.len();
+                self.token_spans.push(lex.span);
+                self.token_positions
+                    .push(parser.optok_to_position[lex.token]);
+                for (span, position) in self.followers.drain(..).rev() {
+                    self.token_spans.push(span);
+                    self.token_positions.push(position);
+                }
+                // 5. Construct forest
+                let positions = &self.token_positions[first_token..];
+                let item = Item::new(op, lex.token, first_token, positions);
+                self.push_item(item, lex);
             }
         }
-        eprintln!();
-        lexemes
+    }
+
+    /// Push `item`, whose first token is `lex`, onto the forest. If it's illegally chained or
+    /// mixed with one of its arguments, replace its op with a `"ChainError"` op, and record the
+    /// conflict unless that was already done.
+    fn push_item(&mut self, mut item: Item, lex: Lexeme) {
+        if let Some((arg, i)) = self.parser.conflicting_arg(&self.forest, &item) {
+            // If the conflict was already reported, don't report it again
+            if self.parser.item_op(&arg).id != OpId::CHAIN_ERROR {
+                let op = self.parser.item_op(&item);
+                // For a list, point at the separator before the element (or after the first one)
+                let span = if op.is_list {
+                    self.token_spans[item.first_token + i.saturating_sub(1)]
+                } else {
+                    lex.span
+                };
+                self.conflicts.push(Conflict { arg, item, span });
+            }
+            item.chain_error = true;
+        }
+        self.forest.push(item);
+    }
+
+    /// Push a single item for a chain of nested list ops, given their separators in RPN order.
+    /// If the last element is a `Blank` (because of a trailing separator), it's popped off of the
+    /// forest instead of becoming a child.
+    fn push_list(&mut self, token: Token, separators: Vec<Span>) {
+        let parser = self.parser;
+        let mut arity = separators.len() + 1;
+        let last_tree = self.forest.tree(self.forest.num_trees() - 1).unwrap();
+        let trailing_blank = parser.item_op(last_tree.item()).name == NAME_BLANK;
+        if trailing_blank {
+            self.forest.pop();
+            arity -= 1;
+        }
+        let lex = Lexeme {
+            token,
+            span: *separators.last().unwrap(),
+        };
+        if let Some(trace) = &mut self.trace {
+            if trailing_blank {
+                trace.filtered.pop();
+            }
+            trace.filtered.push(parser.trace_optok(lex));
+        }
+        let item = Item {
+            optok: token,
+            chain_error: false,
+            first_token: self.token_spans.len(),
+            num_tokens: separators.len(),
+            arity,
+        };
+        self.token_positions
+            .resize(self.token_positions.len() + separators.len(), 0);
+        self.token_spans.extend(separators.into_iter().rev());
+        self.push_item(item, lex);
+    }
+
+    /// Finish building the tree, at the end of the resolved lexemes. Any illegally chained or
+    /// mixed ops are added to `errors`, keeping it in order. If `reparse` is given, it's kept in
+    /// the tree, together with the conflicts found.
+    fn finish<'s>(
+        mut self,
+        source: &'s Source,
+        comments: Vec<Span>,
+        mut reparse: Option<ReparseState>,
+        errors: &mut Vec<ParseError<'s>>,
+    ) -> ParseTree<'s, 'g> {
+        let mut shunted = mem::take(&mut self.shunted);
+        self.shunter.finish(&mut shunted);
+        for lex in shunted {
+            self.push_shunted(lex);
+        }
+        if let Some((list_token, separators)) = self.list.take() {
+            self.push_list(list_token, separators);
+        }
+        let parser = self.parser;
+        errors.extend(self.conflicts.iter().map(|conflict| {
+            let arg = parser.item_op(&conflict.arg);
+            let op = parser.item_op(&conflict.item);
+            ParseError::new_conflict_error(source, arg, op, conflict.span)
+        }));
+        if !self.conflicts.is_empty() {
+            errors.sort_by_key(|error| error.span.start);
+        }
+        if let Some(reparse) = &mut reparse {
+            reparse.conflicts = self.conflicts;
+        }
+        ParseTree::new(
+            source,
+            parser,
+            self.forest,
+            self.token_spans,
+            self.token_positions,
+            comments,
+            reparse,
+        )
     }
 }
 
 impl Lexeme {
-    pub fn new(token: Token, start: Position, end: Position) -> Lexeme {
+    pub fn new(token: Token, start: Offset, end: Offset) -> Lexeme {
         Lexeme {
             token,
             span: Span { start, end },
@@ -146,46 +614,138 @@ impl Lexeme {
 ///     pattern!(_ "+" _);
 ///     pattern!("if" "(" ")" _);  // C-like if
 ///     pattern!("if" "{" "}");    // Rust-like if
+///
+/// An argument between two tokens can be given a sub-grammar to be parsed with, by writing the
+/// sub-grammar's name between those tokens (see `Grammar::subgrammar`):
+///
+///     use panfix::{pattern, Fixity};
+///
+///     pattern!("function" FuncName "(" ")" "end");
+///
+/// The layout tokens `INDENT`, `DEDENT`, and `NEWLINE` can be used without quotes, to make the
+/// grammar indentation sensitive (see `Grammar::op`):
+///
+///     use panfix::{pattern, Fixity};
+///
+///     pattern!(_ ":" INDENT DEDENT);
+///
+/// Tokens after the first can be put in a group that's optional, `( ... )?`, or that may be
+/// repeated, `( ... )*`:
+///
+///     use panfix::{pattern, Fixity};
+///
+///     pattern!("if" "{" "}" ("else" "{" "}")?);
+///
+/// Groups can't be nested, so this doesn't compile:
+///
+/// ```compile_fail
+/// use panfix::pattern;
+///
+/// pattern!("if" "{" "}" ("else" ("if")? "{" "}")?);
+/// ```
 #[macro_export]
 macro_rules! pattern {
     (_ $($items:tt)*) => {
         pattern!(@ Y [ ] $($items)*)
     };
 
-    ($tok:literal $($items:tt)*) => {
-        pattern!(@ N [ ] $tok $($items)*)
+    (@layout INDENT) => { "INDENT".to_string() };
+    (@layout DEDENT) => { "DEDENT".to_string() };
+    (@layout NEWLINE) => { "NEWLINE".to_string() };
+
+    (@repetition ?) => { $crate::Repetition::Optional };
+    (@repetition *) => { $crate::Repetition::Many };
+
+    (@token G $tok:expr, $grammar:expr) => {
+        ($tok, $grammar)
     };
 
-    (@ $l:ident [ $($tokens:tt)* ] $tok:literal $($rest:tt)*) => {
-        pattern!(@ $l [ $($tokens)* $tok.to_string(), ] $($rest)*)
+    (@token $l:ident $tok:expr, $grammar:expr) => {
+        $crate::PatternPart::Token($tok, $grammar)
     };
 
-    (@ Y [ $($tokens:tt)* ] _) => {
-        $crate::Pattern {
-            fixity: $crate::Fixity::Infix,
-            tokens: vec![$($tokens)*],
-        }
+    (@ $l:ident [ ] $tok:literal $($rest:tt)*) => {
+        pattern!(@ $l [ pattern!(@token $l $tok.to_string(), None), ] $($rest)*)
     };
 
-    (@ Y [ $($tokens:tt)* ]) => {
-        $crate::Pattern {
-            fixity: $crate::Fixity::Suffix,
-            tokens: vec![$($tokens)*],
-        }
+    (@ $l:ident [ ] $layout:ident $($rest:tt)*) => {
+        pattern!(@ $l [ pattern!(@token $l pattern!(@layout $layout), None), ] $($rest)*)
     };
 
-    (@ N [ $($tokens:tt)* ] _) => {
-        $crate::Pattern {
-            fixity: $crate::Fixity::Prefix,
-            tokens: vec![$($tokens)*],
-        }
+    (@ Y [ $($parts:tt)+ ] ( $($group:tt)+ ) $repetition:tt $($rest:tt)*) => {
+        pattern!(@ Y
+            [ $($parts)+ pattern!(@group [ $($group)+ ] $repetition), ]
+            $($rest)*)
     };
 
-    (@ N [ $($tokens:tt)* ]) => {
-        $crate::Pattern {
-            fixity: $crate::Fixity::Nilfix,
-            tokens: vec![$($tokens)*],
-        }
+    (@ N [ $($parts:tt)+ ] ( $($group:tt)+ ) $repetition:tt $($rest:tt)*) => {
+        pattern!(@ N
+            [ $($parts)+ pattern!(@group [ $($group)+ ] $repetition), ]
+            $($rest)*)
+    };
+
+    (@group [ $($group:tt)+ ] $repetition:tt) => {
+        $crate::PatternPart::Group(pattern!(@ G [ ] $($group)+), pattern!(@repetition $repetition))
+    };
+
+    (@ $l:ident [ $($parts:tt)+ ] INDENT $($rest:tt)*) => {
+        pattern!(@ $l [ $($parts)+ pattern!(@token $l pattern!(@layout INDENT), None), ] $($rest)*)
+    };
+
+    (@ $l:ident [ $($parts:tt)+ ] DEDENT $($rest:tt)*) => {
+        pattern!(@ $l [ $($parts)+ pattern!(@token $l pattern!(@layout DEDENT), None), ] $($rest)*)
+    };
+
+    (@ $l:ident [ $($parts:tt)+ ] NEWLINE $($rest:tt)*) => {
+        pattern!(@ $l [ $($parts)+ pattern!(@token $l pattern!(@layout NEWLINE), None), ] $($rest)*)
+    };
+
+    (@ $l:ident [ $($parts:tt)+ ] $grammar:ident $layout:ident $($rest:tt)*) => {
+        pattern!(@ $l
+            [ $($parts)+ pattern!(@token $l
+                pattern!(@layout $layout),
+                Some(stringify!($grammar).to_string())), ]
+            $($rest)*)
+    };
+
+    (@ $l:ident [ $($parts:tt)+ ] $grammar:ident $tok:literal $($rest:tt)*) => {
+        pattern!(@ $l
+            [ $($parts)+ pattern!(@token $l
+                $tok.to_string(),
+                Some(stringify!($grammar).to_string())), ]
+            $($rest)*)
+    };
+
+    (@ $l:ident [ $($parts:tt)+ ] $tok:literal $($rest:tt)*) => {
+        pattern!(@ $l [ $($parts)+ pattern!(@token $l $tok.to_string(), None), ] $($rest)*)
+    };
+
+    (@ G [ $($parts:tt)* ]) => {
+        vec![$($parts)*]
+    };
+
+    (@ Y [ $($parts:tt)* ] _) => {
+        $crate::Pattern::from_parts($crate::Fixity::Infix, vec![$($parts)*])
+    };
+
+    (@ Y [ $($parts:tt)* ]) => {
+        $crate::Pattern::from_parts($crate::Fixity::Suffix, vec![$($parts)*])
+    };
+
+    (@ N [ $($parts:tt)* ] _) => {
+        $crate::Pattern::from_parts($crate::Fixity::Prefix, vec![$($parts)*])
+    };
+
+    (@ N [ $($parts:tt)* ]) => {
+        $crate::Pattern::from_parts($crate::Fixity::Nilfix, vec![$($parts)*])
+    };
+
+    ($tok:literal $($items:tt)*) => {
+        pattern!(@ N [ ] $tok $($items)*)
+    };
+
+    ($layout:ident $($items:tt)*) => {
+        pattern!(@ N [ ] $layout $($items)*)
     };
 }
 
@@ -1,4 +1,4 @@
-use crate::{Token, NAME_BLANK, NAME_ERROR, NAME_JUXTAPOSE};
+use crate::{Token, NAME_BLANK, NAME_CHAIN_ERROR, NAME_ERROR, NAME_JUXTAPOSE};
 use std::fmt;
 
 /// Precedence level. Smaller is tighter / wins.
@@ -19,7 +19,7 @@ pub enum Fixity {
     Infix,
 }
 
-/// Whether an operator is left or right associative. For example:
+/// Whether an operator is left or right associative, or can't be chained at all. For example:
 ///
 /// - Subtraction is left associative because `0 - 10 - 1` is equal to `(0 - 10) - 1 = -11` rather
 ///   than `0 - (10 - 1) = -9`.
@@ -29,27 +29,89 @@ pub enum Fixity {
 ///   `x = y = 3` is right-associative. If it were left-associative, it would be equal to `(x = y) =
 ///   3`, which would attempt to set `x` equal to `y` before `y` had been defined.  Instead it is
 ///   right-associative (`InfixR`) and thus equal to `x = (y = 3)`.
+/// - Comparisons are non-associative in many languages: `a < b < c` is an error, rather than
+///   meaning `(a < b) < c`. (It's parsed as if it were left associative, but reported.)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Assoc {
     Left,
     Right,
+    NonAssoc,
+}
+
+/// Which precedence groups can be mixed without parentheses, when there are named groups (see
+/// `Grammar::group`).
+#[derive(Debug, Clone)]
+pub(crate) struct PrecOrder {
+    // The precedence of every group, in increasing order
+    pub(crate) precs: Vec<Prec>,
+    // Indexed by position in `precs`
+    pub(crate) comparable: Vec<Vec<bool>>,
+}
+
+impl PrecOrder {
+    /// Whether the groups with precedences `prec_1` and `prec_2` are comparable. Precedences that
+    /// don't belong to a group (like those of atoms) are comparable with everything.
+    pub(crate) fn comparable(&self, prec_1: Prec, prec_2: Prec) -> bool {
+        match (
+            self.precs.binary_search(&prec_1),
+            self.precs.binary_search(&prec_2),
+        ) {
+            (Ok(i), Ok(j)) => self.comparable[i][j],
+            _ => true,
+        }
+    }
+}
+
+/// Identifies an operator of a grammar, so that parse tree nodes can be matched on without
+/// comparing names. Returned by the methods of `Grammar` that add operators, and by
+/// `Visitor::op_id`. Operators with the same name (say, in different sub-grammars) share an
+/// `OpId`.
+///
+/// To match on a Rust enum instead, see `op_enum!`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OpId(pub(crate) usize);
+
+impl OpId {
+    /// The `"LexError"` op, for unrecognized or unexpected tokens (see `Parser::parse_recovering`).
+    pub const ERROR: OpId = OpId(0);
+    /// The `"Blank"` op, for missing arguments.
+    pub const BLANK: OpId = OpId(1);
+    /// The `"Juxtapose"` op, for missing operators.
+    pub const JUXTAPOSE: OpId = OpId(2);
+    /// The `"ChainError"` op, for an op that's illegally chained with another non-associative op,
+    /// or mixed with an op of incomparable precedence (see `Grammar::non_assoc`).
+    pub const CHAIN_ERROR: OpId = OpId(3);
+
+    /// This op's index in `Parser::op_names`.
+    pub fn index(self) -> usize {
+        self.0
+    }
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct Op {
+    pub(crate) id: OpId,
     pub(crate) name: String,
     pub(crate) fixity: Fixity,
     pub(crate) assoc: Assoc,
     pub(crate) prec: Prec,
     pub(crate) tokens: Vec<String>,
+    // Whether this is a list op, whose nested occurrences get flattened into one node
+    pub(crate) is_list: bool,
     // computed
-    pub(crate) arity: usize,
+    // For each token, whether it's followed by an argument. (Only the ops made by
+    // `Grammar::op` can have optional or repeated tokens, and thus a variable arity.)
+    pub(crate) has_arg_after: Vec<bool>,
+    // For each token, whether it starts a clause: the first token, a group's first token, or the
+    // token after a group.
+    pub(crate) clause_starts: Vec<bool>,
     pub(crate) left_prec: Option<Prec>,
     pub(crate) right_prec: Option<Prec>,
 }
 
 impl Op {
     pub(crate) fn new(
+        id: OpId,
         name: &str,
         fixity: Fixity,
         assoc: Assoc,
@@ -58,15 +120,40 @@ impl Op {
     ) -> Op {
         assert_ne!(name, NAME_BLANK);
         assert_ne!(name, NAME_JUXTAPOSE);
-        Op::new_unchecked(name, fixity, assoc, prec, tokens)
+        assert_ne!(name, NAME_CHAIN_ERROR);
+        Op::new_unchecked(id, name, fixity, assoc, prec, tokens)
     }
 
-    pub(crate) fn new_atom(name: &str, _token: Token) -> Op {
-        Op::new_unchecked(name, Fixity::Nilfix, Assoc::Left, 0, vec![name.to_owned()])
+    /// A list op is parsed like a right associative infix op, so that a chain of them shows up
+    /// as a run of adjacent separators in RPN order, which `Parser::build_tree` merges.
+    pub(crate) fn new_list(id: OpId, name: &str, prec: Prec, separator: &str) -> Op {
+        Op {
+            is_list: true,
+            ..Op::new(
+                id,
+                name,
+                Fixity::Infix,
+                Assoc::Right,
+                prec,
+                vec![separator.to_owned()],
+            )
+        }
+    }
+
+    pub(crate) fn new_atom(id: OpId, name: &str, _token: Token) -> Op {
+        Op::new_unchecked(
+            id,
+            name,
+            Fixity::Nilfix,
+            Assoc::Left,
+            0,
+            vec![name.to_owned()],
+        )
     }
 
     pub(crate) fn new_error() -> Op {
         Op::new_unchecked(
+            OpId::ERROR,
             NAME_ERROR,
             Fixity::Nilfix,
             Assoc::Left,
@@ -77,6 +164,7 @@ impl Op {
 
     pub(crate) fn new_blank() -> Op {
         Op::new_unchecked(
+            OpId::BLANK,
             NAME_BLANK,
             Fixity::Nilfix,
             Assoc::Left,
@@ -87,6 +175,7 @@ impl Op {
 
     pub(crate) fn new_juxtapose(assoc: Assoc, prec: Prec) -> Op {
         Op::new_unchecked(
+            OpId::JUXTAPOSE,
             NAME_JUXTAPOSE,
             Fixity::Infix,
             assoc,
@@ -95,37 +184,51 @@ impl Op {
         )
     }
 
+    /// A `"ChainError"` op with the same syntax as this one, to stand in for it when it's
+    /// illegally chained with another non-associative op, or mixed with an op of incomparable
+    /// precedence.
+    pub(crate) fn new_chain_error(&self) -> Op {
+        Op {
+            id: OpId::CHAIN_ERROR,
+            name: NAME_CHAIN_ERROR.to_owned(),
+            ..self.clone()
+        }
+    }
+
     fn new_unchecked(
+        id: OpId,
         name: &str,
         fixity: Fixity,
         assoc: Assoc,
         prec: Prec,
         tokens: Vec<String>,
     ) -> Op {
-        use Assoc::{Left, Right};
+        use Assoc::{Left, NonAssoc, Right};
         use Fixity::{Infix, Nilfix, Prefix, Suffix};
 
         let (left_prec, right_prec) = match (fixity, assoc) {
             (Nilfix, _) => (None, None),
-            (Prefix, Left) => (None, Some(prec)),
+            (Prefix, Left | NonAssoc) => (None, Some(prec)),
             (Prefix, Right) => (None, Some(prec + 1)),
-            (Suffix, Left) => (Some(prec + 1), None),
+            (Suffix, Left | NonAssoc) => (Some(prec + 1), None),
             (Suffix, Right) => (Some(prec), None),
-            (Infix, Left) => (Some(prec + 1), Some(prec)),
+            (Infix, Left | NonAssoc) => (Some(prec + 1), Some(prec)),
             (Infix, Right) => (Some(prec), Some(prec + 1)),
         };
-        let arity = match fixity {
-            Nilfix => tokens.len() - 1,
-            Prefix | Suffix => tokens.len(),
-            Infix => tokens.len() + 1,
-        };
+        let mut has_arg_after = vec![true; tokens.len()];
+        *has_arg_after.last_mut().unwrap() = right_prec.is_some();
+        let mut clause_starts = vec![false; tokens.len()];
+        clause_starts[0] = true;
         Op {
+            id,
             name: name.to_owned(),
             fixity,
             assoc,
             prec,
             tokens,
-            arity,
+            is_list: false,
+            has_arg_after,
+            clause_starts,
             left_prec,
             right_prec,
         }
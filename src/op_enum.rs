@@ -0,0 +1,117 @@
+//! Matching on parse tree nodes with a Rust enum of a grammar's operators, instead of on names.
+
+use crate::OpId;
+use thiserror::Error;
+
+/// An `op_enum!` enum that doesn't match a grammar's ops (see `Parser::op_map`).
+#[derive(Error, Debug)]
+pub enum OpMapError {
+    /// The enum has a variant for an op that isn't in the grammar.
+    #[error("There is no op named {0} in the grammar, but the op enum has a variant for it.")]
+    UnknownOp(String),
+    /// The grammar has an op that the enum has no variant for.
+    #[error("The op enum has no variant for the op {0}.")]
+    MissingOp(String),
+}
+
+/// An enum with one variant per op of a grammar. Implement this with the `op_enum!` macro, and
+/// convert `OpId`s into it with an `OpMap` from `Parser::op_map`.
+pub trait OpEnum: Copy + 'static {
+    /// The name of the op of each variant, in the same order as `VARIANTS`.
+    const NAMES: &'static [&'static str];
+    /// Every variant.
+    const VARIANTS: &'static [Self];
+}
+
+/// Converts the `OpId`s of one parser into the enum `E`. Made by `Parser::op_map`.
+#[derive(Debug, Clone)]
+pub struct OpMap<E: OpEnum> {
+    // Indexed by `OpId`
+    variants: Vec<E>,
+}
+
+impl<E: OpEnum> OpMap<E> {
+    pub(crate) fn new(op_names: &[String]) -> Result<OpMap<E>, OpMapError> {
+        if let Some(name) = E::NAMES
+            .iter()
+            .find(|name| !op_names.iter().any(|op_name| op_name == *name))
+        {
+            return Err(OpMapError::UnknownOp((*name).to_owned()));
+        }
+        let variants = op_names
+            .iter()
+            .map(
+                |op_name| match E::NAMES.iter().position(|name| name == op_name) {
+                    Some(i) => Ok(E::VARIANTS[i]),
+                    None => Err(OpMapError::MissingOp(op_name.clone())),
+                },
+            )
+            .collect::<Result<_, _>>()?;
+        Ok(OpMap { variants })
+    }
+
+    /// The variant for `id`, usually from `Visitor::op_id`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is from a different parser.
+    pub fn get(&self, id: OpId) -> E {
+        self.variants[id.index()]
+    }
+}
+
+/// Declare an enum with one variant per op of a grammar, for matching on parse tree nodes. Each
+/// variant is named after its op, unless it's given an op name explicitly with `= "name"`. The
+/// enum must also have variants for the reserved ops `LexError`, `Blank`, `Juxtapose`, and
+/// `ChainError`, which can appear in any parse tree. The enum derives `Debug`, `Clone`, `Copy`,
+/// `PartialEq`, `Eq`, and `Hash`.
+///
+/// Get an `OpMap` from `Parser::op_map`, which checks that the enum and the grammar have the same
+/// ops, and use it to convert each node's `Visitor::op_id`:
+///
+/// ```
+/// # use panfix::{op_enum, pattern, Grammar, Source};
+/// op_enum! {
+///     enum Calc {
+///         LexError,
+///         Blank,
+///         Juxtapose,
+///         ChainError,
+///         Number,
+///         Plus = "+",
+///     }
+/// }
+///
+/// let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+/// grammar.regex("Number", "[0-9]+").unwrap();
+/// grammar.op("+", pattern!(_ "+" _)).unwrap();
+/// let parser = grammar.finish().unwrap();
+/// let ops = parser.op_map::<Calc>().unwrap();
+///
+/// let source = Source::new("example", "1 + 2".to_owned());
+/// let tree = parser.parse(&source).unwrap();
+/// assert_eq!(ops.get(tree.visitor().op_id()), Calc::Plus);
+/// ```
+#[macro_export]
+macro_rules! op_enum {
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $name:ident {
+            $($variant:ident $(= $op_name:literal)?),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        $vis enum $name {
+            $($variant),*
+        }
+
+        impl $crate::OpEnum for $name {
+            const NAMES: &'static [&'static str] = &[$($crate::op_enum!(@name $variant $($op_name)?)),*];
+            const VARIANTS: &'static [Self] = &[$($name::$variant),*];
+        }
+    };
+
+    (@name $variant:ident $op_name:literal) => { $op_name };
+    (@name $variant:ident) => { stringify!($variant) };
+}
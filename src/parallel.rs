@@ -0,0 +1,398 @@
+//! Parsing a large source with several threads. See `Parser::parse_parallel`.
+
+use crate::lexer::{Lexer, ModalLexemeIter, TokenSource};
+use crate::resolver::{Resolver, Speculation};
+use crate::shunter::Shunter;
+use crate::{Lexeme, Offset, ParseError, ParseTree, Parser, Prec, Source, Span, TreeBuilder};
+use std::thread;
+
+impl Parser {
+    /// Parse `source` with up to `threads` threads. The result is exactly the same as
+    /// `parse(source)`.
+    ///
+    /// The source is split into chunks at newlines, and each chunk is lexed and resolved on its
+    /// own thread, as if it was the whole source: with no ops open at its start, and an operand
+    /// expected. The chunks are then stitched together in order, on the calling thread, by a
+    /// resolver that knows what came before each one. Wherever a chunk's resolver was in the
+    /// same state (except for knowing what was below its stack), its work is taken as is, only
+    /// checking the lexemes where its stack ran out against what's below. Elsewhere the lexemes
+    /// are resolved again, and lexed again where they might be wrong: at the start of each chunk,
+    /// after a token that spans chunks, like a multi-line string or block comment (which the
+    /// chunk's thread lexed from the middle of), and where a token restricted to operands or
+    /// operators matched in the wrong mode.
+    ///
+    /// The resolved lexemes are then split up again and shunted on several threads, each
+    /// starting with an empty stack, and stitched together the same way. Only building the tree
+    /// itself is done on the calling thread alone.
+    ///
+    /// If `threads` is at most 1, or the grammar uses layout, this is just `parse(source)`.
+    pub fn parse_parallel<'s, 'g>(
+        &'g self,
+        source: &'s Source,
+        threads: usize,
+    ) -> Result<ParseTree<'s, 'g>, ParseError<'s>> {
+        if threads <= 1 || self.lexer.has_layout() {
+            return self.parse(source);
+        }
+        let (tree, errors) = self.parse_in_chunks(source, threads, false)?;
+        match errors.into_iter().next() {
+            None => Ok(tree),
+            Some(error) => Err(error),
+        }
+    }
+
+    /// Like `parse_parallel`, but recovering from errors like `parse_recovering` does. The result
+    /// is exactly the same as `parse_recovering(source)`.
+    pub fn parse_parallel_recovering<'s, 'g>(
+        &'g self,
+        source: &'s Source,
+        threads: usize,
+    ) -> (ParseTree<'s, 'g>, Vec<ParseError<'s>>) {
+        if threads <= 1 || self.lexer.has_layout() {
+            return self.parse_recovering(source);
+        }
+        self.parse_in_chunks(source, threads, true)
+            .expect("can't fail while recovering")
+    }
+
+    /// Parse `source` with up to `threads` threads. Only fails if not `recover`ing.
+    fn parse_in_chunks<'s, 'g>(
+        &'g self,
+        source: &'s Source,
+        threads: usize,
+        recover: bool,
+    ) -> Result<(ParseTree<'s, 'g>, Vec<ParseError<'s>>), ParseError<'s>> {
+        // 1. Lex and 2. Resolve each chunk on its own thread, then stitch them together
+        let chunks = speculate_chunks(self, source.source(), threads);
+        let mut resolver = self.resolver(recover);
+        let mut lexemes = Stitcher::new(&self.lexer, source.source(), &chunks);
+        loop {
+            if let Some((chunk, start)) = lexemes.position() {
+                let spec = &chunks[chunk].spec;
+                let end = agreeing_lexemes(&resolver, spec, start);
+                if end > start {
+                    resolver.splice(spec, start, end);
+                    lexemes.skip_to(end);
+                    continue;
+                }
+            }
+            match lexemes.next_lexeme(resolver.arg_mode()) {
+                Some(lexeme) => resolver
+                    .resolve_lexeme(lexeme)
+                    .map_err(|err| self.resolver_error(source, err))?,
+                None => break,
+            }
+        }
+        resolver
+            .finish()
+            .map_err(|err| self.resolver_error(source, err))?;
+        let comments = lexemes.into_comments();
+        let (resolved, resolver_errors) = resolver.into_output();
+        let mut errors = resolver_errors
+            .into_iter()
+            .map(|err| self.resolver_error(source, err))
+            .collect::<Vec<_>>();
+
+        // 3. Shunt parts of the resolved lexemes on their own threads, then stitch them together
+        let shunted = shunt_parts(&self.optok_to_prec, &resolved, threads);
+
+        // 4. Collect token spans, and 5. Construct forest
+        let mut builder = TreeBuilder::new(self, None);
+        for lex in shunted {
+            builder.push_shunted(lex);
+        }
+        let tree = builder.finish(source, comments, None, &mut errors);
+        Ok((tree, errors))
+    }
+}
+
+/// The lexemes that start in one chunk of the source, lexed and resolved as if the chunk was the
+/// whole source.
+struct Chunk {
+    start: Offset,
+    spec: Speculation,
+    // Parallel to `spec.lexemes`: whether each one might have lexed differently in the other mode
+    mode_sensitive: Vec<bool>,
+    comments: Vec<Span>,
+}
+
+/// Split `text` into (at most) `threads` chunks at newlines, and lex and resolve each on its own
+/// thread.
+fn speculate_chunks(parser: &Parser, text: &str, threads: usize) -> Vec<Chunk> {
+    let mut starts = vec![0];
+    for i in 1..threads {
+        let target = (text.len() * i / threads).max(*starts.last().unwrap());
+        // (A '\n' byte is never part of a longer char, so this finds a char boundary.)
+        match text.as_bytes()[target..].iter().position(|b| *b == b'\n') {
+            Some(newline) if target + newline + 1 < text.len() => starts.push(target + newline + 1),
+            _ => break,
+        }
+    }
+    starts.dedup();
+
+    let ends = starts[1..].iter().copied().chain([text.len()]);
+    let ranges = starts.iter().copied().zip(ends).collect::<Vec<_>>();
+    thread::scope(|scope| {
+        let handles = ranges
+            .into_iter()
+            .map(|(start, end)| scope.spawn(move || speculate_chunk(parser, text, start, end)))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+fn speculate_chunk(parser: &Parser, text: &str, start: Offset, end: Offset) -> Chunk {
+    let mut lexemes = ChunkLexemes {
+        iter: parser.lexer.lex_modal_at(text, start),
+        end,
+        mode_sensitive: Vec::new(),
+    };
+    let spec = parser.resolver(false).speculate(&mut lexemes);
+    Chunk {
+        start,
+        spec,
+        mode_sensitive: lexemes.mode_sensitive,
+        comments: lexemes.iter.into_comments(),
+    }
+}
+
+/// A `TokenSource` for the lexemes that start before `end`.
+struct ChunkLexemes<'l, 's> {
+    iter: ModalLexemeIter<'l, 's>,
+    end: Offset,
+    mode_sensitive: Vec<bool>,
+}
+
+impl TokenSource for &mut ChunkLexemes<'_, '_> {
+    fn next_lexeme(&mut self, arg_mode: bool) -> Option<Lexeme> {
+        let (lexeme, mode_sensitive) = self.iter.next_speculative(arg_mode)?;
+        if lexeme.span.start >= self.end {
+            return None;
+        }
+        self.mode_sensitive.push(mode_sensitive);
+        Some(lexeme)
+    }
+}
+
+/// Where `resolver`, which is about to resolve the lexeme of `spec` at `start`, would stop doing
+/// just what `spec` did: the index of the first lexeme that it would resolve differently (or
+/// that `spec` failed to resolve), or `start` if it isn't in the same state as `spec` was there.
+fn agreeing_lexemes(resolver: &Resolver, spec: &Speculation, start: usize) -> usize {
+    let step = spec.steps[start];
+    if !step.empty || step.arg_mode != resolver.arg_mode() {
+        return start;
+    }
+    let mut end = start;
+    while end < spec.lexemes.len() {
+        let step = spec.steps[end];
+        if step.failed || (step.bottomed_out && !resolver.agrees_below(spec.lexemes[end])) {
+            break;
+        }
+        end += 1;
+    }
+    end
+}
+
+/// Shunt `resolved` in (at most) `threads` parts, each on its own thread, and stitch them
+/// together.
+fn shunt_parts(prec_table: &[(Prec, Prec)], resolved: &[Lexeme], threads: usize) -> Vec<Lexeme> {
+    let part_len = resolved.len().div_ceil(threads).max(1);
+    let specs = thread::scope(|scope| {
+        let handles = resolved
+            .chunks(part_len)
+            .map(|part| scope.spawn(move || Shunter::speculate(prec_table, part)))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    let mut shunter = Shunter::new(prec_table);
+    let mut shunted = Vec::with_capacity(resolved.len());
+    for spec in &specs {
+        shunter.splice(spec, &mut shunted);
+    }
+    shunter.finish(&mut shunted);
+    shunted
+}
+
+/// A `TokenSource` that produces the same lexemes as lexing the whole source would, by replaying
+/// the lexemes of each chunk where it can, and lexing where it can't.
+struct Stitcher<'l, 's, 'c> {
+    lexer: &'l Lexer,
+    text: &'s str,
+    chunks: &'c [Chunk],
+    // Where the lexer would be now: the end of the last lexeme produced
+    offset: Offset,
+    state: StitchState<'l, 's>,
+    comments: Vec<Span>,
+}
+
+enum StitchState<'l, 's> {
+    /// Replaying the lexemes of `chunk`, starting at `next`. `comment` is the first of the
+    /// chunk's comments that hasn't been produced.
+    Replay {
+        chunk: usize,
+        next: usize,
+        comment: usize,
+    },
+    /// Lexing the source.
+    Lex(ModalLexemeIter<'l, 's>),
+    /// At the end of the source.
+    Done,
+}
+
+impl<'l, 's: 'l, 'c> Stitcher<'l, 's, 'c> {
+    fn new(lexer: &'l Lexer, text: &'s str, chunks: &'c [Chunk]) -> Stitcher<'l, 's, 'c> {
+        // The first chunk starts where the lexer does.
+        Stitcher {
+            lexer,
+            text,
+            chunks,
+            offset: 0,
+            state: StitchState::Replay {
+                chunk: 0,
+                next: 0,
+                comment: 0,
+            },
+            comments: Vec::new(),
+        }
+    }
+
+    fn into_comments(self) -> Vec<Span> {
+        self.comments
+    }
+
+    /// If a chunk's lexer stopped just after a lexeme that ends at `end`, it would be in the same
+    /// state as a lexer that just produced `lexeme`. If there is one, give it and the index of
+    /// its lexeme after that.
+    fn resync(&self, lexeme: Lexeme) -> Option<(usize, usize)> {
+        let end = lexeme.span.end;
+        let chunk_of = |offset: Offset| {
+            self.chunks
+                .partition_point(|chunk| chunk.start <= offset)
+                .saturating_sub(1)
+        };
+        let first = chunk_of(lexeme.span.start);
+        let last = chunk_of(end);
+        [first, last].into_iter().find_map(|c| {
+            let lexemes = &self.chunks[c].spec.lexemes;
+            let next = lexemes.partition_point(|lexeme| lexeme.span.end <= end);
+            if next > 0 && lexemes[next - 1].span.end == end {
+                Some((c, next))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn start_lexing(&mut self) {
+        let iter = self.lexer.lex_modal_at(self.text, self.offset);
+        self.state = StitchState::Lex(iter);
+    }
+
+    /// If replaying the lexemes of a chunk, that chunk and the index of the next one.
+    fn position(&self) -> Option<(usize, usize)> {
+        match self.state {
+            StitchState::Replay { chunk, next, .. } => Some((chunk, next)),
+            _ => None,
+        }
+    }
+
+    /// While replaying the lexemes of a chunk, skip ahead to the one at `index`, as if the ones
+    /// before it were produced.
+    fn skip_to(&mut self, index: usize) {
+        if let StitchState::Replay {
+            chunk,
+            next,
+            comment,
+        } = &mut self.state
+        {
+            let chunk_data = &self.chunks[*chunk];
+            let end = chunk_data.spec.lexemes[index - 1].span.end;
+            while let Some(span) = chunk_data.comments.get(*comment) {
+                if span.start >= end {
+                    break;
+                }
+                self.comments.push(*span);
+                *comment += 1;
+            }
+            *next = index;
+            self.offset = end;
+        }
+    }
+}
+
+impl<'l, 's: 'l> TokenSource for Stitcher<'l, 's, '_> {
+    fn next_lexeme(&mut self, arg_mode: bool) -> Option<Lexeme> {
+        loop {
+            match &mut self.state {
+                StitchState::Replay {
+                    chunk,
+                    next,
+                    comment,
+                } => {
+                    let chunk_data = &self.chunks[*chunk];
+                    if *next == chunk_data.spec.lexemes.len() {
+                        if *chunk + 1 < self.chunks.len() {
+                            // The next lexeme starts in a later chunk
+                            self.start_lexing();
+                            continue;
+                        }
+                        // The last chunk's lexer stopped here, so produce the comments after its
+                        // last lexeme
+                        self.comments
+                            .extend_from_slice(&chunk_data.comments[*comment..]);
+                        self.state = StitchState::Done;
+                        return None;
+                    }
+                    let lexeme = chunk_data.spec.lexemes[*next];
+                    let chunk_arg_mode = chunk_data.spec.steps[*next].arg_mode;
+                    if chunk_data.mode_sensitive[*next] && chunk_arg_mode != arg_mode {
+                        // It might lex differently when the other kind of token is expected
+                        self.start_lexing();
+                        continue;
+                    }
+                    *next += 1;
+                    while let Some(span) = chunk_data.comments.get(*comment) {
+                        if span.start >= lexeme.span.start {
+                            break;
+                        }
+                        self.comments.push(*span);
+                        *comment += 1;
+                    }
+                    self.offset = lexeme.span.end;
+                    return Some(lexeme);
+                }
+                StitchState::Lex(iter) => {
+                    let lexeme = iter.next_lexeme(arg_mode);
+                    self.comments.extend(iter.take_comments());
+                    let lexeme = match lexeme {
+                        Some(lexeme) => lexeme,
+                        None => {
+                            self.state = StitchState::Done;
+                            return None;
+                        }
+                    };
+                    self.offset = lexeme.span.end;
+                    if let Some((chunk, next)) = self.resync(lexeme) {
+                        // Back in step with a chunk
+                        let comment = self.chunks[chunk]
+                            .comments
+                            .partition_point(|span| span.start < self.offset);
+                        self.state = StitchState::Replay {
+                            chunk,
+                            next,
+                            comment,
+                        };
+                    }
+                    return Some(lexeme);
+                }
+                StitchState::Done => return None,
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
+use crate::op::Op;
 use crate::resolver::ResolverError;
-use crate::{Source, Span};
+use crate::source::{display_width, expand_tabs};
+use crate::{Lexeme, Position, Source, Span, DEFAULT_TAB_WIDTH};
+use std::borrow::Cow;
 use std::error;
 use std::fmt;
 use std::fmt::Write;
@@ -14,6 +17,33 @@ pub struct ParseError<'s> {
     pub span: Span,
 }
 
+/// A `ParseError` that doesn't borrow its `Source`: it keeps a copy of the filename, and of the
+/// (at most two) source lines that it displays. It's `Send`, `Sync`, and `'static`, so it can be
+/// sent between threads or put in a `Box<dyn Error>`. Make one with `ParseError::into_owned` (or
+/// `.into()`). It displays exactly the same way as the `ParseError` it was made from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedParseError {
+    pub filename: String,
+    pub short_message: String,
+    pub message: String,
+    pub span: Span,
+    // The positions of the ends of the span, and the contents of its first line, and of its last
+    // line if that's different.
+    start: Position,
+    end: Position,
+    first_line: String,
+    last_line: Option<String>,
+}
+
+/// The parts of the source that an error displays.
+struct ErrorLines<'a> {
+    filename: &'a str,
+    start: Position,
+    end: Position,
+    first_line: &'a str,
+    last_line: &'a str,
+}
+
 impl<'s> ParseError<'s> {
     /// Construct a custom parsing error message. (This is useful so that you can re-use the
     /// existing parsing error message printing, and because it will have the same type as other
@@ -32,13 +62,53 @@ impl<'s> ParseError<'s> {
         }
     }
 
+    /// The op `outer` was either chained with `inner` from the same non-associative group, or
+    /// mixed with it from an incomparable group.
+    pub(crate) fn new_conflict_error(
+        source: &'s Source,
+        inner: &Op,
+        outer: &Op,
+        outer_span: Span,
+    ) -> ParseError<'s> {
+        if inner.prec == outer.prec {
+            ParseError {
+                source,
+                short_message: "cannot be chained".to_owned(),
+                message: format!(
+                    "'{}' and '{}' are non-associative, so they cannot be chained. Use parentheses.",
+                    inner.name, outer.name
+                ),
+                span: outer_span,
+            }
+        } else {
+            ParseError {
+                source,
+                short_message: "cannot be mixed".to_owned(),
+                message: format!(
+                    "'{}' and '{}' have incomparable precedence, so they cannot be mixed. Use parentheses.",
+                    inner.name, outer.name
+                ),
+                span: outer_span,
+            }
+        }
+    }
+
     pub(crate) fn from_resolver_error(
         source: &'s Source,
         tok_to_name: &[String],
         optok_to_name: &[String],
         error: ResolverError,
     ) -> ParseError<'s> {
-        use ResolverError::{IncompleteOp, LexError, UnexpectedToken};
+        use ResolverError::{BadIndentation, IncompleteOp, LexError, UnexpectedToken};
+
+        // Made up lexemes, like layout lexemes, are shown by name.
+        let describe = |lexeme: Lexeme| {
+            if lexeme.span.is_empty() {
+                tok_to_name[lexeme.token].as_str()
+            } else {
+                source.substr(lexeme.span)
+            }
+        };
 
         match error {
             LexError(lexeme) => ParseError {
@@ -50,9 +120,15 @@ impl<'s> ParseError<'s> {
             UnexpectedToken(found) => ParseError {
                 source,
                 short_message: "unexpected token".to_owned(),
-                message: format!("Unexpected token '{}'", source.substr(found.span)),
+                message: format!("Unexpected token '{}'", describe(found)),
                 span: found.span,
             },
+            BadIndentation(lexeme) => ParseError {
+                source,
+                short_message: "inconsistent indentation".to_owned(),
+                message: "Indentation does not match any enclosing block.".to_owned(),
+                span: lexeme.span,
+            },
             IncompleteOp {
                 op,
                 expected,
@@ -79,7 +155,7 @@ impl<'s> ParseError<'s> {
                     "While parsing '{}', expected '{}' but found '{}'.",
                     &optok_to_name[op],
                     &tok_to_name[expected],
-                    source.substr(found.span)
+                    describe(found)
                 ),
                 span: found.span,
             },
@@ -88,85 +164,206 @@ impl<'s> ParseError<'s> {
 
     /// The regular `fmt::Display` implementation attempts to infer whether to print with color.
     /// Use this method to manually set whether to print with color.
-    pub fn display_with_color_override(&self, use_color: bool) -> impl fmt::Display + '_ {
-        /// Wrapper struct for recording whether to print with color.
-        struct DisplayParseError<'a> {
-            error: &'a ParseError<'a>,
-            use_color: bool,
+    pub fn display_with_color_override(&self, use_color: bool) -> ErrorDisplay<'_> {
+        self.display().color_override(use_color)
+    }
+
+    /// The regular `fmt::Display` implementation expands each tab in the source to the next
+    /// multiple of `DEFAULT_TAB_WIDTH` columns. Use this method to set the tab width instead.
+    pub fn display_with_tab_width(&self, tab_width: usize) -> ErrorDisplay<'_> {
+        self.display().tab_width(tab_width)
+    }
+
+    fn display(&self) -> ErrorDisplay<'_> {
+        ErrorDisplay::new(self.lines(), &self.short_message, &self.message)
+    }
+
+    /// Convert this into an `OwnedParseError`, which copies the parts of the source that it
+    /// displays instead of borrowing the source.
+    pub fn into_owned(self) -> OwnedParseError {
+        let lines = self.lines();
+        let first_line = lines.first_line.to_owned();
+        let last_line = (lines.start.line != lines.end.line).then(|| lines.last_line.to_owned());
+        OwnedParseError {
+            filename: self.source.filename().to_owned(),
+            short_message: self.short_message,
+            message: self.message,
+            span: self.span,
+            start: lines.start,
+            end: lines.end,
+            first_line,
+            last_line,
         }
+    }
 
-        impl fmt::Display for DisplayParseError<'_> {
-            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                colored::control::set_override(self.use_color);
-                write!(f, "{}", self.error)?;
-                colored::control::unset_override();
-                Ok(())
-            }
+    fn lines(&self) -> ErrorLines<'s> {
+        let start = self.source.position_at(self.span.start);
+        let end = self.source.position_at(self.span.end);
+        ErrorLines {
+            filename: self.source.filename(),
+            start,
+            end,
+            first_line: self.source.line_contents(start.line),
+            last_line: self.source.line_contents(end.line),
         }
+    }
+}
 
-        DisplayParseError {
-            error: self,
-            use_color,
+impl OwnedParseError {
+    /// The regular `fmt::Display` implementation attempts to infer whether to print with color.
+    /// Use this method to manually set whether to print with color.
+    pub fn display_with_color_override(&self, use_color: bool) -> ErrorDisplay<'_> {
+        self.display().color_override(use_color)
+    }
+
+    /// The regular `fmt::Display` implementation expands each tab in the source to the next
+    /// multiple of `DEFAULT_TAB_WIDTH` columns. Use this method to set the tab width instead.
+    pub fn display_with_tab_width(&self, tab_width: usize) -> ErrorDisplay<'_> {
+        self.display().tab_width(tab_width)
+    }
+
+    fn display(&self) -> ErrorDisplay<'_> {
+        ErrorDisplay::new(self.lines(), &self.short_message, &self.message)
+    }
+
+    fn lines(&self) -> ErrorLines<'_> {
+        ErrorLines {
+            filename: &self.filename,
+            start: self.start,
+            end: self.end,
+            first_line: &self.first_line,
+            last_line: self.last_line.as_deref().unwrap_or(&self.first_line),
         }
     }
 }
 
-impl fmt::Display for ParseError<'_> {
+impl From<ParseError<'_>> for OwnedParseError {
+    fn from(error: ParseError<'_>) -> OwnedParseError {
+        error.into_owned()
+    }
+}
+
+/// Displays a `ParseError` or `OwnedParseError` with settings other than the defaults. Made with
+/// `display_with_color_override` or `display_with_tab_width`, and the settings can be combined,
+/// like `error.display_with_tab_width(8).color_override(false)`.
+pub struct ErrorDisplay<'a> {
+    lines: ErrorLines<'a>,
+    short_message: &'a str,
+    message: &'a str,
+    use_color: Option<bool>,
+    tab_width: usize,
+}
+
+impl<'a> ErrorDisplay<'a> {
+    fn new(lines: ErrorLines<'a>, short_message: &'a str, message: &'a str) -> ErrorDisplay<'a> {
+        ErrorDisplay {
+            lines,
+            short_message,
+            message,
+            use_color: None,
+            tab_width: DEFAULT_TAB_WIDTH,
+        }
+    }
+
+    /// Set whether to print with color, instead of inferring it.
+    pub fn color_override(mut self, use_color: bool) -> ErrorDisplay<'a> {
+        self.use_color = Some(use_color);
+        self
+    }
+
+    /// Expand each tab in the source to the next multiple of `tab_width` columns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tab_width` is 0.
+    pub fn tab_width(mut self, tab_width: usize) -> ErrorDisplay<'a> {
+        assert!(tab_width > 0, "Tab width must be positive");
+        self.tab_width = tab_width;
+        self
+    }
+}
+
+impl fmt::Display for ErrorDisplay<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        show_error_with_loc(
+        if let Some(use_color) = self.use_color {
+            colored::control::set_override(use_color);
+        }
+        let result = show_error_with_loc(
             f,
-            self.source,
+            &self.lines,
             "Parse Error",
-            self.span,
-            &self.short_message,
-            &self.message,
-        )
+            self.short_message,
+            self.message,
+            self.tab_width,
+        );
+        if self.use_color.is_some() {
+            colored::control::unset_override();
+        }
+        result
+    }
+}
+
+impl fmt::Display for ParseError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.display())
+    }
+}
+
+impl fmt::Display for OwnedParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.display())
     }
 }
 
 impl error::Error for ParseError<'_> {}
 
+impl error::Error for OwnedParseError {}
+
 struct LineInfo<'a> {
     num: String,
-    contents: &'a str,
+    contents: Cow<'a, str>,
     carets_start: usize,
     carets_len: usize,
 }
 
 fn show_error_with_loc(
     buffer: &mut impl Write,
-    source: &Source,
+    lines: &ErrorLines,
     error_kind: &str,
-    span: Span,
     short_message: &str,
     long_message: &str,
+    tab_width: usize,
 ) -> fmt::Result {
     use colored::Colorize;
 
-    let start = span.start;
-    let end = span.end;
+    let start = lines.start;
+    let end = lines.end;
+    // Carets are placed by display width, so that they line up under wide characters and tabs.
+    // (A column can be past the end of the line's contents, if it's between a `\r` and a `\n`.)
+    let start_col = display_width(prefix(lines.first_line, start.col as usize), tab_width);
+    let end_col = display_width(prefix(lines.last_line, end.col as usize), tab_width);
     let (first_line, last_line, margin, ellided) = {
         if start.line == end.line {
             let first_line = LineInfo {
                 num: format!("{}", start.line + 1),
-                contents: source.line_contents(start.line),
-                carets_start: start.col as usize,
-                carets_len: (end.col - start.col).max(1) as usize,
+                contents: expand_tabs(lines.first_line, tab_width),
+                carets_start: start_col,
+                carets_len: end_col.saturating_sub(start_col).max(1),
             };
             let margin = first_line.num.len() + 1;
             (first_line, None, margin, false)
         } else {
             let first_line = LineInfo {
                 num: format!("{}", start.line + 1),
-                contents: source.line_contents(start.line),
-                carets_start: start.col as usize,
-                carets_len: source.line_contents(start.line).chars().count() - start.col as usize,
+                contents: expand_tabs(lines.first_line, tab_width),
+                carets_start: start_col,
+                carets_len: display_width(lines.first_line, tab_width) - start_col,
             };
             let last_line = LineInfo {
                 num: format!("{}", end.line + 1),
-                contents: source.line_contents(end.line),
+                contents: expand_tabs(lines.last_line, tab_width),
                 carets_start: 0,
-                carets_len: end.col.max(1) as usize,
+                carets_len: end_col.max(1),
             };
             let margin = first_line.num.len().max(last_line.num.len()) + 1;
             let ellided = end.line - start.line >= 2;
@@ -187,7 +384,7 @@ fn show_error_with_loc(
         "{:margin$}{} {}:{}:{}",
         "",
         "-->".blue().bold(),
-        source.filename(),
+        lines.filename,
         start.line + 1,
         start.col + 1,
         margin = margin - 1,
@@ -195,18 +392,18 @@ fn show_error_with_loc(
 
     if let Some(last_line) = last_line {
         show_line(buffer, margin, "", "")?;
-        show_line(buffer, margin, &first_line.num, first_line.contents)?;
+        show_line(buffer, margin, &first_line.num, &first_line.contents)?;
         let carets = show_carets(first_line.carets_start, first_line.carets_len, "");
         show_line(buffer, margin, "", &carets)?;
         if ellided {
             writeln!(buffer, "{}", "...".blue().bold())?;
         }
-        show_line(buffer, margin, &last_line.num, last_line.contents)?;
+        show_line(buffer, margin, &last_line.num, &last_line.contents)?;
         let carets = show_carets(last_line.carets_start, last_line.carets_len, short_message);
         show_line(buffer, margin, "", &carets)
     } else {
         show_line(buffer, margin, "", "")?;
-        show_line(buffer, margin, &first_line.num, first_line.contents)?;
+        show_line(buffer, margin, &first_line.num, &first_line.contents)?;
         let carets = show_carets(
             first_line.carets_start,
             first_line.carets_len,
@@ -216,6 +413,11 @@ fn show_error_with_loc(
     }
 }
 
+/// The first `len` bytes of `line`, or all of it if it's shorter.
+fn prefix(line: &str, len: usize) -> &str {
+    &line[..len.min(line.len())]
+}
+
 fn show_carets(carets_start: usize, carets_len: usize, short_message: &str) -> String {
     use colored::Colorize;
 
@@ -1,61 +1,390 @@
-use crate::op::{Assoc, Fixity, Op, Prec};
+use crate::op::{Assoc, Fixity, Op, OpId, Prec};
 use crate::parse_error::ParseError;
+use crate::reparse::ReparseState;
 use crate::source::Source;
-use crate::tree_visitor::{Arity, Forest, Visitor as ForestVisitor};
-use crate::{Parser, Position, Span, NAME_BLANK, NAME_JUXTAPOSE};
+use crate::tree_visitor::{Arity, Forest, Node, Visitor as ForestVisitor};
+use crate::{Offset, OpToken, Parser, Span, NAME_BLANK, NAME_JUXTAPOSE};
 use std::fmt;
+use std::sync::Arc;
 
 /// The result of parsing a source string. Call `.visitor()` to walk it.
 ///
 /// To minimize allocations, this contains references into both the source text and the grammar, so
-/// it cannot outlive either.
+/// it cannot outlive either. Call `.into_owned()` for a tree that owns them instead.
 #[derive(Debug)]
 pub struct ParseTree<'s, 'p> {
     source: &'s Source,
     parser: &'p Parser,
-    forest: Forest<Item<'p>>,
+    data: TreeData,
+}
+
+/// A parse tree that holds its `Source` and `Parser` in `Arc`s, so that it has no lifetimes. It
+/// can be stored, returned from the function that parsed it, and sent to other threads. Cloning it
+/// is cheap.
+///
+/// Call `.visitor()` to walk it with a (borrowed) `Visitor`, or `.root()` to walk it with an
+/// `OwnedVisitor`, which has the same methods but no lifetimes.
+#[derive(Debug, Clone)]
+pub struct OwnedParseTree(Arc<OwnedTreeData>);
+
+#[derive(Debug)]
+struct OwnedTreeData {
+    source: Arc<Source>,
+    parser: Arc<Parser>,
+    data: TreeData,
+}
+
+/// Everything in a parse tree besides its source and parser.
+#[derive(Debug)]
+struct TreeData {
+    forest: Forest<Item>,
+    // The span of every token of every op. Each item's spans are contiguous.
+    token_spans: Vec<Span>,
+    // Parallel to `token_spans`: the index of each token in its op's pattern.
+    token_positions: Vec<usize>,
+    // The span of every comment, in order.
+    comments: Vec<Span>,
+    // Only present if made by `Parser::parse_for_reparse` or `Parser::reparse`.
+    reparse: Option<ReparseState>,
+    // Only present if `with_trivia` was called.
+    trivia: Option<TriviaTable>,
+}
+
+/// The whitespace and comments around one token. See `ParseTree::with_trivia`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trivia {
+    /// Everything between the previous token's trailing trivia and this token.
+    pub leading: Span,
+    /// Everything after this token up to the end of its line, or up to the next token if that's on
+    /// the same line. A comment that starts on this line is included in full, even if it continues
+    /// onto later lines.
+    pub trailing: Span,
+}
+
+#[derive(Debug, Clone)]
+struct TriviaTable {
+    // Parallel to `ParseTree::token_spans`
+    tokens: Vec<Trivia>,
+    // Everything after the last token's trailing trivia
+    end_of_file: Span,
 }
 
 #[derive(Debug, Clone, Copy)]
-pub(crate) struct Item<'p> {
-    pub(crate) op: &'p Op,
-    pub(crate) span: Span,
+pub(crate) struct Item {
+    /// The op token this op was made from. The op is `Parser::optok_to_op[optok]`, or
+    /// `Parser::optok_to_chain_error[optok]` if `chain_error` is set. (It's referred to by index,
+    /// so that the tree doesn't borrow the parser: see `Parser::item_op`.)
+    pub(crate) optok: OpToken,
+    pub(crate) chain_error: bool,
+    /// Index of this op's first token in `ParseTree::token_spans`.
+    pub(crate) first_token: usize,
+    /// The number of tokens. Usually `op.tokens.len()`, but a list op has one per separator.
+    pub(crate) num_tokens: usize,
+    /// The number of children. Usually `op.arity`, but a list op has one per element.
+    pub(crate) arity: usize,
+}
+
+impl Item {
+    /// An item for `op`, made from `optok`, whose tokens are at `positions` in its pattern.
+    pub(crate) fn new(op: &Op, optok: OpToken, first_token: usize, positions: &[usize]) -> Item {
+        let has_left_arg = matches!(op.fixity, Fixity::Infix | Fixity::Suffix);
+        let num_args = positions
+            .iter()
+            .filter(|position| op.has_arg_after[**position])
+            .count();
+        Item {
+            optok,
+            chain_error: false,
+            first_token,
+            num_tokens: positions.len(),
+            arity: usize::from(has_left_arg) + num_args,
+        }
+    }
 }
 
 impl<'s, 'p> ParseTree<'s, 'p> {
     pub(crate) fn new(
         source: &'s Source,
         parser: &'p Parser,
-        forest: Forest<Item<'p>>,
+        forest: Forest<Item>,
+        token_spans: Vec<Span>,
+        token_positions: Vec<usize>,
+        comments: Vec<Span>,
+        reparse: Option<ReparseState>,
     ) -> ParseTree<'s, 'p> {
         ParseTree {
             source,
             parser,
-            forest,
+            data: TreeData {
+                forest,
+                token_spans,
+                token_positions,
+                comments,
+                reparse,
+                trivia: None,
+            },
         }
     }
 
+    /// Record the _trivia_ (whitespace and comments) around every token, making this a lossless
+    /// syntax tree: with it, `.to_lossless_string()` reproduces the source exactly. Takes linear
+    /// time (plus the time to sort the tokens by position).
+    ///
+    /// Each token owns the trivia on its own line after it (its _trailing_ trivia), together with
+    /// everything else since the previous token (its _leading_ trivia). Tokens with empty spans,
+    /// like those of `Blank` and `Juxtapose`, have empty trivia.
+    pub fn with_trivia(mut self) -> ParseTree<'s, 'p> {
+        self.data.add_trivia(self.source);
+        self
+    }
+
+    /// The trivia after the last token, up to the end of the file. `None` unless `.with_trivia()`
+    /// was called.
+    pub fn end_of_file_trivia(&self) -> Option<Span> {
+        self.data.end_of_file_trivia()
+    }
+
+    /// Print the tree back out, including all of its trivia. For a tree that hasn't been modified
+    /// this reproduces the source exactly, byte for byte.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `.with_trivia()` wasn't called.
+    pub fn to_lossless_string(&self) -> String {
+        self.data.to_lossless_string(self.source, self.parser)
+    }
+
     /// Obtains a "visitor" that can walk the source tree.
     ///
     /// (This is not the visitor pattern: you're not supplying a function to run on each node.
     /// Instead you can navigate the nodes and do whatever you want yourself.)
     pub fn visitor<'t>(&'t self) -> Visitor<'s, 'p, 't> {
+        self.data.visitor(self.source, self.parser)
+    }
+
+    pub fn source(&self) -> &'s Source {
+        self.source
+    }
+
+    /// The spans of every comment in the source, in order.
+    pub fn comments(&self) -> &[Span] {
+        &self.data.comments
+    }
+
+    /// The spans of the comments that lie entirely within `span`, in order. For example,
+    /// `tree.comments_in(visitor.span())` gives the comments inside of a node. Takes logarithmic
+    /// time.
+    pub fn comments_in(&self, span: Span) -> &[Span] {
+        self.data.comments_in(span)
+    }
+
+    /// What `Parser::reparse` needs, if this tree kept it.
+    pub(crate) fn reparse_state(&self) -> Option<&ReparseState> {
+        self.data.reparse.as_ref()
+    }
+
+    /// The forest, and the span and pattern position of every token.
+    pub(crate) fn parts(&self) -> (&Forest<Item>, &[Span], &[usize]) {
+        (
+            &self.data.forest,
+            &self.data.token_spans,
+            &self.data.token_positions,
+        )
+    }
+
+    /// Convert this into an `OwnedParseTree`, by cloning its `Source` and `Parser`. If you already
+    /// have them in `Arc`s, use `.into_owned_with()` to avoid the clones.
+    pub fn into_owned(self) -> OwnedParseTree {
+        OwnedParseTree(Arc::new(OwnedTreeData {
+            source: Arc::new(self.source.clone()),
+            parser: Arc::new(self.parser.clone()),
+            data: self.data,
+        }))
+    }
+
+    /// Convert this into an `OwnedParseTree` that holds `source` and `parser`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `source` and `parser` aren't the very `Source` and `Parser` that this tree was
+    /// parsed with.
+    pub fn into_owned_with(self, source: Arc<Source>, parser: Arc<Parser>) -> OwnedParseTree {
+        assert!(
+            std::ptr::eq(self.source, &*source) && std::ptr::eq(self.parser, &*parser),
+            "ParseTree::into_owned_with: must be given the tree's own source and parser"
+        );
+        OwnedParseTree(Arc::new(OwnedTreeData {
+            source,
+            parser,
+            data: self.data,
+        }))
+    }
+}
+
+impl OwnedParseTree {
+    /// Obtains a (borrowed) visitor that can walk the source tree. See `ParseTree::visitor`.
+    pub fn visitor(&self) -> Visitor<'_, '_, '_> {
+        self.0.data.visitor(&self.0.source, &self.0.parser)
+    }
+
+    /// Obtains an owned visitor at the root of the tree. Unlike `.visitor()`, it doesn't borrow
+    /// the tree.
+    pub fn root(&self) -> OwnedVisitor {
+        OwnedVisitor {
+            tree: self.clone(),
+            node: self.visitor().node.node(),
+        }
+    }
+
+    pub fn source(&self) -> &Arc<Source> {
+        &self.0.source
+    }
+
+    pub fn parser(&self) -> &Arc<Parser> {
+        &self.0.parser
+    }
+
+    /// The trivia after the last token, up to the end of the file. See
+    /// `ParseTree::end_of_file_trivia`.
+    pub fn end_of_file_trivia(&self) -> Option<Span> {
+        self.0.data.end_of_file_trivia()
+    }
+
+    /// Print the tree back out, including all of its trivia. See `ParseTree::to_lossless_string`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ParseTree::with_trivia()` wasn't called before the tree was made owned.
+    pub fn to_lossless_string(&self) -> String {
+        self.0
+            .data
+            .to_lossless_string(&self.0.source, &self.0.parser)
+    }
+
+    /// The spans of every comment in the source, in order.
+    pub fn comments(&self) -> &[Span] {
+        &self.0.data.comments
+    }
+
+    /// The spans of the comments that lie entirely within `span`, in order. See
+    /// `ParseTree::comments_in`.
+    pub fn comments_in(&self, span: Span) -> &[Span] {
+        self.0.data.comments_in(span)
+    }
+}
+
+impl TreeData {
+    fn visitor<'s, 'p, 't>(
+        &'t self,
+        source: &'s Source,
+        parser: &'p Parser,
+    ) -> Visitor<'s, 'p, 't> {
         Visitor {
-            source: self.source,
-            parser: self.parser,
+            source,
+            parser,
             // Parser guarantees there's at least one node
             node: self.forest.tree(0).unwrap(),
+            all_token_spans: &self.token_spans,
+            all_token_positions: &self.token_positions,
+            all_trivia: self.trivia.as_ref().map(|trivia| trivia.tokens.as_slice()),
         }
     }
 
-    pub fn source(&self) -> &'s Source {
-        self.source
+    fn add_trivia(&mut self, source: &Source) {
+        let mut tokens = self
+            .token_spans
+            .iter()
+            .map(|span| Trivia {
+                leading: Span::new_at(span.start),
+                trailing: Span::new_at(span.end),
+            })
+            .collect::<Vec<_>>();
+        let mut order = (0..self.token_spans.len())
+            .filter(|i| !self.token_spans[*i].is_empty())
+            .collect::<Vec<_>>();
+        order.sort_unstable_by_key(|i| self.token_spans[*i].start);
+
+        let end_of_file = source.source().len();
+        let mut cursor = 0;
+        let mut comment_index = 0;
+        for (k, i) in order.iter().enumerate() {
+            let span = self.token_spans[*i];
+            let next_start = match order.get(k + 1) {
+                Some(j) => self.token_spans[*j].start,
+                None => end_of_file,
+            };
+            let trailing_end =
+                self.end_of_trailing_trivia(source, span.end, next_start, &mut comment_index);
+            tokens[*i] = Trivia {
+                leading: Span::new(cursor, span.start),
+                trailing: Span::new(span.end, trailing_end),
+            };
+            cursor = trailing_end;
+        }
+        self.trivia = Some(TriviaTable {
+            tokens,
+            end_of_file: Span::new(cursor, end_of_file),
+        });
+    }
+
+    /// Find where the trivia after a token ending at `start` stops: at the first newline that
+    /// isn't inside a comment, or at `limit`.
+    fn end_of_trailing_trivia(
+        &self,
+        source: &Source,
+        start: Offset,
+        limit: Offset,
+        comment_index: &mut usize,
+    ) -> Offset {
+        while matches!(self.comments.get(*comment_index), Some(comment) if comment.start < start) {
+            *comment_index += 1;
+        }
+        let text = source.source();
+        let mut offset = start;
+        while offset < limit {
+            if let Some(comment) = self.comments.get(*comment_index) {
+                if comment.start == offset {
+                    *comment_index += 1;
+                    offset = comment.end;
+                    continue;
+                }
+            }
+            let rest = &text[offset..limit];
+            if rest.starts_with('\n') || rest.starts_with("\r\n") {
+                break;
+            }
+            offset += rest.chars().next().unwrap().len_utf8();
+        }
+        offset
+    }
+
+    fn end_of_file_trivia(&self) -> Option<Span> {
+        self.trivia.as_ref().map(|trivia| trivia.end_of_file)
+    }
+
+    fn to_lossless_string(&self, source: &Source, parser: &Parser) -> String {
+        let end_of_file = self
+            .end_of_file_trivia()
+            .expect("ParseTree: call with_trivia() before to_lossless_string()");
+        let mut string = self.visitor(source, parser).to_lossless_string();
+        string.push_str(source.substr(end_of_file));
+        string
+    }
+
+    fn comments_in(&self, span: Span) -> &[Span] {
+        let start = self
+            .comments
+            .partition_point(|comment| comment.start < span.start);
+        let end = self
+            .comments
+            .partition_point(|comment| comment.end <= span.end);
+        &self.comments[start..end.max(start)]
     }
 }
 
-impl Arity for Item<'_> {
+impl Arity for Item {
     fn arity(&self) -> usize {
-        self.op.arity
+        self.arity
     }
 }
 
@@ -64,13 +393,49 @@ impl Arity for Item<'_> {
 pub struct Visitor<'s, 'p, 't> {
     source: &'s Source,
     parser: &'p Parser,
-    node: ForestVisitor<'t, Item<'p>>,
+    node: ForestVisitor<'t, Item>,
+    all_token_spans: &'t [Span],
+    all_token_positions: &'t [usize],
+    all_trivia: Option<&'t [Trivia]>,
+}
+
+/// One clause of a node whose pattern has groups, like the `elif c { }` in `if a { } elif c { }`.
+/// See `Visitor::clauses`.
+#[derive(Debug, Clone, Copy)]
+pub struct Clause<'s, 'p, 't> {
+    visitor: Visitor<'s, 'p, 't>,
+    // Indices into the node's `token_spans()`
+    first_token: usize,
+    num_tokens: usize,
+    // Indices of the node's children
+    first_child: usize,
+    num_children: usize,
+}
+
+/// One node in an `OwnedParseTree`. It has the same methods as `Visitor`, but holds on to the tree
+/// instead of borrowing it, so it has no lifetimes. Call `.visitor()` to get a (borrowed)
+/// `Visitor` for the same node, for example to pass to `FromParseTree`.
+#[derive(Debug, Clone)]
+pub struct OwnedVisitor {
+    tree: OwnedParseTree,
+    node: Node<Item>,
 }
 
 impl<'s, 'p, 't> Visitor<'s, 'p, 't> {
+    fn op(&self) -> &'p Op {
+        self.parser.item_op(self.node.item())
+    }
+
     /// The name of the op at this node.
     pub fn name(&self) -> &'p str {
-        &self.node.item().op.name
+        &self.op().name
+    }
+
+    /// The id of the op at this node. Cheaper to match on than `.name()`: compare it against the
+    /// ids returned by `Grammar`'s methods (or `Parser::op_id`), or convert it into an enum with
+    /// an `OpMap` (see `op_enum!`).
+    pub fn op_id(&self) -> OpId {
+        self.op().id
     }
 
     /// The span of this node and it's children.
@@ -81,28 +446,85 @@ impl<'s, 'p, 't> Visitor<'s, 'p, 't> {
         }
     }
 
-    fn start(&self) -> Position {
+    fn start(&self) -> Offset {
         match self.fixity() {
             Fixity::Infix | Fixity::Suffix => self.child(0).start(),
-            Fixity::Nilfix | Fixity::Prefix => self.node.item().span.start,
+            Fixity::Nilfix | Fixity::Prefix => self.token_span().start,
         }
     }
 
-    fn end(&self) -> Position {
+    fn end(&self) -> Offset {
         match self.fixity() {
-            Fixity::Infix | Fixity::Prefix => self.child(self.num_children() - 1).end(),
-            Fixity::Nilfix | Fixity::Suffix => self.node.item().span.end,
+            Fixity::Infix | Fixity::Prefix if !self.has_trailing_separator() => {
+                self.child(self.num_children() - 1).end()
+            }
+            _ => self.token_spans().last().unwrap().end,
         }
     }
 
     /// The span of this node's first token.
     pub fn token_span(&self) -> Span {
-        self.node.item().span
+        self.all_token_spans[self.node.item().first_token]
+    }
+
+    /// The spans of each of this node's tokens, in the same order as `.tokens()`. For example,
+    /// for `f(x)` parsed with `pattern!(_ "(" ")")`, these would be the spans of `(` and `)`.
+    ///
+    /// A token that was missing from the source and made up by `Parser::parse_recovering` has an
+    /// empty span. A list op (see `Grammar::list_op`) has the span of each of its separators.
+    pub fn token_spans(&self) -> &'t [Span] {
+        let item = self.node.item();
+        &self.all_token_spans[item.first_token..item.first_token + item.num_tokens]
+    }
+
+    /// The trivia around each of this node's tokens, in the same order as `.tokens()`. `None`
+    /// unless `ParseTree::with_trivia` was called.
+    pub fn trivia(&self) -> Option<&'t [Trivia]> {
+        let item = self.node.item();
+        self.all_trivia
+            .map(|trivia| &trivia[item.first_token..item.first_token + item.num_tokens])
+    }
+
+    /// Print this node back out, including the trivia of all of its tokens (but not the trivia
+    /// at the end of the file: see `ParseTree::to_lossless_string`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ParseTree::with_trivia()` wasn't called.
+    pub fn to_lossless_string(&self) -> String {
+        let mut string = String::new();
+        self.write_lossless(&mut string);
+        string
+    }
+
+    fn write_lossless(&self, string: &mut String) {
+        let trivia = self
+            .trivia()
+            .expect("Visitor: call ParseTree::with_trivia() before to_lossless_string()");
+        let op = self.op();
+        let item = self.node.item();
+        let positions =
+            &self.all_token_positions[item.first_token..item.first_token + item.num_tokens];
+        let mut children = self.children_iter();
+        if matches!(self.fixity(), Fixity::Infix | Fixity::Suffix) {
+            children.next().unwrap().write_lossless(string);
+        }
+        for ((span, trivia), position) in self.token_spans().iter().zip(trivia).zip(positions) {
+            string.push_str(self.source.substr(trivia.leading));
+            string.push_str(self.source.substr(*span));
+            string.push_str(self.source.substr(trivia.trailing));
+            // Tokens that can end an optional or repeated part of a pattern may have no child after
+            if op.has_arg_after[*position] {
+                if let Some(child) = children.next() {
+                    child.write_lossless(string);
+                }
+            }
+        }
     }
 
     /// The source text covered by `.token_span()`.
     pub fn token_source(&self) -> &'s str {
-        self.source.substr(self.span())
+        self.source.substr(self.token_span())
     }
 
     /// The source text covered by `.span()`.
@@ -112,27 +534,88 @@ impl<'s, 'p, 't> Visitor<'s, 'p, 't> {
 
     /// The fixity of this node's operator.
     pub fn fixity(&self) -> Fixity {
-        self.node.item().op.fixity
+        self.op().fixity
     }
 
     /// The precedence of this node's operator.
     pub fn prec(&self) -> Prec {
-        self.node.item().op.prec
+        self.op().prec
     }
 
     /// The associativity of this node's operator.
     pub fn assoc(&self) -> Assoc {
-        self.node.item().op.assoc
+        self.op().assoc
     }
 
-    /// The tokens of this node's operator.
-    pub fn tokens(&self) -> &[String] {
-        &self.node.item().op.tokens
+    /// The tokens of this node's operator's pattern. (For a list op, this is just the separator.)
+    /// If the pattern has groups, these are all of its tokens, whether or not they appeared: see
+    /// `.clauses()` for the ones that did.
+    pub fn tokens(&self) -> &'p [String] {
+        &self.op().tokens
     }
 
-    /// The number of children this node has (which is determined by its operator).
+    /// The number of children this node has. This is determined by its operator, except for list
+    /// ops (see `Grammar::list_op`), which have one child per element.
     pub fn num_children(&self) -> usize {
-        self.node.item().op.arity
+        self.node.item().arity
+    }
+
+    /// Whether this is a list op (see `Grammar::list_op`).
+    pub(crate) fn is_list(&self) -> bool {
+        self.op().is_list
+    }
+
+    /// Whether this is a list op (see `Grammar::list_op`) whose last element was followed by a
+    /// separator, like `[1, 2,]`.
+    pub fn has_trailing_separator(&self) -> bool {
+        let item = self.node.item();
+        self.op().is_list && item.num_tokens == item.arity
+    }
+
+    /// Iterate over the clauses of this node. A clause starts at the first token, at the first
+    /// token of each occurrence of a group, and at the first token after a group. For example, with
+    /// `pattern!("if" "{" "}" ("elif" "{" "}")* ("else" "{" "}")?)`, the source
+    /// `if a { b } elif c { d } elif e { f }` has three clauses: `if`, `elif`, and `elif`. Each clause
+    /// has the children that follow its tokens; the left argument of an infix or suffix op is not
+    /// in any clause.
+    ///
+    /// An op without groups has a single clause. (Except for a list op, which has one per separator.)
+    pub fn clauses(&self) -> impl Iterator<Item = Clause<'s, 'p, 't>> {
+        let visitor = *self;
+        let item = *self.node.item();
+        let op = self.op();
+        let all_token_positions = self.all_token_positions;
+        let positions = &all_token_positions[item.first_token..item.first_token + item.num_tokens];
+        let mut token = 0;
+        let mut child = usize::from(matches!(op.fixity, Fixity::Infix | Fixity::Suffix));
+        std::iter::from_fn(move || {
+            if token == positions.len() {
+                return None;
+            }
+            let (first_token, first_child) = (token, child);
+            loop {
+                if op.has_arg_after[positions[token]] {
+                    child = (child + 1).min(item.arity);
+                }
+                token += 1;
+                if token == positions.len() || op.clause_starts[positions[token]] {
+                    break;
+                }
+            }
+            Some(Clause {
+                visitor,
+                first_token,
+                num_tokens: token - first_token,
+                first_child,
+                num_children: child - first_child,
+            })
+        })
+    }
+
+    /// Iterate over this node's children.
+    pub fn children_iter(&self) -> impl ExactSizeIterator<Item = Visitor<'s, 'p, 't>> {
+        let visitor = *self;
+        (0..self.num_children()).map(move |i| visitor.child(i))
     }
 
     /// Get this node's `n`th child.
@@ -145,11 +628,14 @@ impl<'s, 'p, 't> Visitor<'s, 'p, 't> {
                 source: self.source,
                 parser: self.parser,
                 node,
+                all_token_spans: self.all_token_spans,
+                all_token_positions: self.all_token_positions,
+                all_trivia: self.all_trivia,
             },
             None => panic!(
                 "Visitor: child index '{}' out of bound for op '{}'",
                 n,
-                self.node.item().op.name
+                self.op().name
             ),
         }
     }
@@ -159,8 +645,8 @@ impl<'s, 'p, 't> Visitor<'s, 'p, 't> {
     /// # Panics
     ///
     /// Panics if `N` does not match the number of children. Note that the number of children is
-    /// not dynamic: you can tell how many there will be from the grammar. Even if a child is
-    /// "missing", it will actually be represented as blank.
+    /// not dynamic (except for list ops): you can tell how many there will be from the grammar.
+    /// Even if a child is "missing", it will actually be represented as blank.
     #[track_caller]
     pub fn children<const N: usize>(&self) -> [Visitor<'s, 'p, 't>; N] {
         let mut array = [*self; N]; // dummy value
@@ -176,6 +662,9 @@ impl<'s, 'p, 't> Visitor<'s, 'p, 't> {
                 source: self.source,
                 parser: self.parser,
                 node: self.node.child(i).unwrap(),
+                all_token_spans: self.all_token_spans,
+                all_token_positions: self.all_token_positions,
+                all_trivia: self.all_trivia,
             };
         }
         array
@@ -192,6 +681,208 @@ impl<'s, 'p, 't> Visitor<'s, 'p, 't> {
     }
 }
 
+impl<'s, 'p, 't> Clause<'s, 'p, 't> {
+    /// The first token of this clause, as written in the pattern. For example, `"elif"`.
+    pub fn token(&self) -> &'p str {
+        let item = self.visitor.node.item();
+        let position = self.visitor.all_token_positions[item.first_token + self.first_token];
+        &self.visitor.op().tokens[position]
+    }
+
+    /// The spans of this clause's tokens.
+    pub fn token_spans(&self) -> &'t [Span] {
+        &self.visitor.token_spans()[self.first_token..self.first_token + self.num_tokens]
+    }
+
+    /// The number of children in this clause.
+    pub fn num_children(&self) -> usize {
+        self.num_children
+    }
+
+    /// Iterate over this clause's children.
+    pub fn children_iter(&self) -> impl ExactSizeIterator<Item = Visitor<'s, 'p, 't>> {
+        let (visitor, first_child) = (self.visitor, self.first_child);
+        (0..self.num_children).map(move |i| visitor.child(first_child + i))
+    }
+
+    /// Get this clause's `n`th child.
+    ///
+    /// # Panics if there aren't at least `n` children.
+    #[track_caller]
+    pub fn child(&self, n: usize) -> Visitor<'s, 'p, 't> {
+        if n >= self.num_children() {
+            panic!(
+                "Clause: child index '{}' out of bound for clause '{}'",
+                n,
+                self.token()
+            );
+        }
+        self.visitor.child(self.first_child + n)
+    }
+
+    /// Extract this clause's children into an array.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N` does not match the number of children.
+    #[track_caller]
+    pub fn children<const N: usize>(&self) -> [Visitor<'s, 'p, 't>; N] {
+        if N != self.num_children() {
+            panic!(
+                "Clause::children -- expected {} children but found {}",
+                N,
+                self.num_children()
+            );
+        }
+        let mut array = [self.visitor; N]; // dummy value
+        for (i, child) in array.iter_mut().enumerate() {
+            *child = self.visitor.child(self.first_child + i);
+        }
+        array
+    }
+}
+
+impl OwnedVisitor {
+    /// A (borrowed) `Visitor` for this node.
+    pub fn visitor(&self) -> Visitor<'_, '_, '_> {
+        let tree = &self.tree.0;
+        Visitor {
+            node: tree.data.forest.visit(self.node),
+            ..tree.data.visitor(&tree.source, &tree.parser)
+        }
+    }
+
+    /// The tree this node is in.
+    pub fn tree(&self) -> &OwnedParseTree {
+        &self.tree
+    }
+
+    /// The name of the op at this node.
+    pub fn name(&self) -> &str {
+        self.visitor().name()
+    }
+
+    /// The id of the op at this node. See `Visitor::op_id`.
+    pub fn op_id(&self) -> OpId {
+        self.visitor().op_id()
+    }
+
+    /// The span of this node and it's children.
+    pub fn span(&self) -> Span {
+        self.visitor().span()
+    }
+
+    /// The span of this node's first token.
+    pub fn token_span(&self) -> Span {
+        self.visitor().token_span()
+    }
+
+    /// The spans of each of this node's tokens. See `Visitor::token_spans`.
+    pub fn token_spans(&self) -> &[Span] {
+        self.visitor().token_spans()
+    }
+
+    /// The trivia around each of this node's tokens. See `Visitor::trivia`.
+    pub fn trivia(&self) -> Option<&[Trivia]> {
+        self.visitor().trivia()
+    }
+
+    /// Print this node back out, including its trivia. See `Visitor::to_lossless_string`.
+    pub fn to_lossless_string(&self) -> String {
+        self.visitor().to_lossless_string()
+    }
+
+    /// The source text covered by `.token_span()`.
+    pub fn token_source(&self) -> &str {
+        self.visitor().token_source()
+    }
+
+    /// The source text covered by `.span()`.
+    pub fn source(&self) -> &str {
+        self.visitor().source()
+    }
+
+    /// The fixity of this node's operator.
+    pub fn fixity(&self) -> Fixity {
+        self.visitor().fixity()
+    }
+
+    /// The precedence of this node's operator.
+    pub fn prec(&self) -> Prec {
+        self.visitor().prec()
+    }
+
+    /// The associativity of this node's operator.
+    pub fn assoc(&self) -> Assoc {
+        self.visitor().assoc()
+    }
+
+    /// The tokens of this node's operator's pattern. See `Visitor::tokens`.
+    pub fn tokens(&self) -> &[String] {
+        self.visitor().tokens()
+    }
+
+    /// The number of children this node has. See `Visitor::num_children`.
+    pub fn num_children(&self) -> usize {
+        self.visitor().num_children()
+    }
+
+    /// Whether this is a list op whose last element was followed by a separator. See
+    /// `Visitor::has_trailing_separator`.
+    pub fn has_trailing_separator(&self) -> bool {
+        self.visitor().has_trailing_separator()
+    }
+
+    /// Iterate over the clauses of this node. See `Visitor::clauses`.
+    pub fn clauses(&self) -> impl Iterator<Item = Clause<'_, '_, '_>> {
+        self.visitor().clauses()
+    }
+
+    /// Iterate over this node's children.
+    pub fn children_iter(&self) -> impl ExactSizeIterator<Item = OwnedVisitor> + '_ {
+        (0..self.num_children()).map(move |i| self.child(i))
+    }
+
+    /// Get this node's `n`th child.
+    ///
+    /// # Panics if there aren't at least `n` children.
+    #[track_caller]
+    pub fn child(&self, n: usize) -> OwnedVisitor {
+        OwnedVisitor {
+            tree: self.tree.clone(),
+            node: self.visitor().child(n).node.node(),
+        }
+    }
+
+    /// Extract this visitor's children into an array.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N` does not match the number of children.
+    #[track_caller]
+    pub fn children<const N: usize>(&self) -> [OwnedVisitor; N] {
+        self.visitor().children::<N>().map(|child| OwnedVisitor {
+            tree: self.tree.clone(),
+            node: child.node.node(),
+        })
+    }
+
+    /// Create a custom parsing error with the given message at the location `self.span()`.
+    pub fn error(&self, short_message: &str, message: &str) -> ParseError<'_> {
+        ParseError::custom_error(&self.tree.0.source, short_message, message, self.span())
+    }
+
+    /// Create a custom parsing error with the given message at the location `self.token_span()`.
+    pub fn error_at_token(&self, short_message: &str, message: &str) -> ParseError<'_> {
+        ParseError::custom_error(
+            &self.tree.0.source,
+            short_message,
+            message,
+            self.token_span(),
+        )
+    }
+}
+
 impl fmt::Display for Visitor<'_, '_, '_> {
     /// Display this node as an s-expression.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -206,8 +897,8 @@ impl fmt::Display for Visitor<'_, '_, '_> {
             } else {
                 write!(f, "{}", self.name())?;
             }
-            for i in 0..self.num_children() {
-                write!(f, " {}", self.child(i))?;
+            for child in self.children_iter() {
+                write!(f, " {}", child)?;
             }
             write!(f, ")")
         }
@@ -220,3 +911,17 @@ impl fmt::Display for ParseTree<'_, '_> {
         write!(f, "{}", self.visitor())
     }
 }
+
+impl fmt::Display for OwnedParseTree {
+    /// Display this tree as an s-expression.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.visitor())
+    }
+}
+
+impl fmt::Display for OwnedVisitor {
+    /// Display this node as an s-expression.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.visitor())
+    }
+}
@@ -0,0 +1,438 @@
+//! Reparsing a source after an edit, reusing the work done before it. See `Parser::reparse`.
+
+use crate::lexer::{Lexed, Lexer, ModalLexemeIter, TokenSource};
+use crate::resolver::{self, Resolver, ResolverError};
+use crate::{
+    BuildCheckpoint, Conflict, Edit, Lexeme, Offset, ParseError, ParseTree, Parser, Source, Span,
+    TreeBuilder,
+};
+
+/// The least number of steps between two checkpoints. There are more steps between them when
+/// the state is bigger, so that all of the checkpoints together take linear space.
+const CHECKPOINT_INTERVAL: usize = 64;
+
+/// What a tree keeps so that it can be reparsed. See `Parser::parse_for_reparse`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ReparseState {
+    // The lexemes the lexer produced
+    lexed: Vec<Lexed>,
+    // The output of the resolver, and the errors it found
+    resolved: Vec<Lexeme>,
+    resolver_errors: Vec<ResolverError>,
+    // The illegally chained or mixed ops found while building the tree
+    pub(crate) conflicts: Vec<Conflict>,
+    // The state of the resolver and of the tree builder every so often, in order
+    resolver_checkpoints: Vec<resolver::Checkpoint>,
+    build_checkpoints: Vec<BuildCheckpoint>,
+}
+
+/// An old tree to reuse, and the edit made since.
+type Old<'o> = (&'o ParseTree<'o, 'o>, &'o ReparseState, &'o Edit);
+
+impl Parser {
+    /// Like `parse`, but keep what `reparse` needs in the tree, so that it can be reparsed after
+    /// an edit. (`parse` doesn't, to save memory.)
+    pub fn parse_for_reparse<'s, 'g>(
+        &'g self,
+        source: &'s Source,
+    ) -> Result<ParseTree<'s, 'g>, ParseError<'s>> {
+        let (tree, errors) = self.parse_keeping_state(source, None, false)?;
+        match errors.into_iter().next() {
+            None => Ok(tree),
+            Some(error) => Err(error),
+        }
+    }
+
+    /// Like `parse_recovering`, but keep what `reparse` needs in the tree, so that it can be
+    /// reparsed after an edit.
+    pub fn parse_for_reparse_recovering<'s, 'g>(
+        &'g self,
+        source: &'s Source,
+    ) -> (ParseTree<'s, 'g>, Vec<ParseError<'s>>) {
+        self.parse_keeping_state(source, None, true)
+            .expect("can't fail while recovering")
+    }
+
+    /// Parse `source` after `edit` was made to it, reusing the work done to produce `old`. `old`
+    /// must have been parsed by this parser, from the source as it was just before `edit` (and
+    /// `edit` must be the only change made since). The result is exactly the same as
+    /// `parse(source)`, except that it can be reparsed in turn.
+    ///
+    /// Lexing is the slowest part of parsing, so this reuses the lexemes of `old` before the line
+    /// that the edit starts on, and re-lexes from there until the lexer is back in step with
+    /// `old` after the edit, reusing the rest. If a token's regex can match a newline (like a
+    /// multi-line string), an edit could change how any earlier line lexes, so instead lexing
+    /// starts over from the start of the source, and only the lexemes after the edit are reused.
+    ///
+    /// The later stages of parsing are resumed from where they were in `old`, shortly before the
+    /// first lexeme that changed: the resolver's stack, the shunter's stack, and the subtrees
+    /// already built there are all reused, and only the rest of the source is resolved and built
+    /// again.
+    ///
+    /// Only a tree made by `parse_for_reparse` or `reparse` (or their recovering versions) has
+    /// anything to reuse. For any other tree, or if the grammar uses layout, this simply parses
+    /// `source` from scratch (with `parse_for_reparse`).
+    pub fn reparse<'s, 'g>(
+        &'g self,
+        source: &'s Source,
+        old: &ParseTree,
+        edit: &Edit,
+    ) -> Result<ParseTree<'s, 'g>, ParseError<'s>> {
+        let old_state = match old.reparse_state() {
+            Some(old_state) if !self.lexer.has_layout() => old_state,
+            _ => return self.parse_for_reparse(source),
+        };
+        let (tree, errors) =
+            self.parse_keeping_state(source, Some((old, old_state, edit)), false)?;
+        match errors.into_iter().next() {
+            None => Ok(tree),
+            Some(error) => Err(error),
+        }
+    }
+
+    /// Like `reparse`, but recovering from errors like `parse_recovering` does. The result is
+    /// exactly the same as `parse_recovering(source)`, except that it can be reparsed in turn.
+    pub fn reparse_recovering<'s, 'g>(
+        &'g self,
+        source: &'s Source,
+        old: &ParseTree,
+        edit: &Edit,
+    ) -> (ParseTree<'s, 'g>, Vec<ParseError<'s>>) {
+        let old_state = match old.reparse_state() {
+            Some(old_state) if !self.lexer.has_layout() => old_state,
+            _ => return self.parse_for_reparse_recovering(source),
+        };
+        self.parse_keeping_state(source, Some((old, old_state, edit)), true)
+            .expect("can't fail while recovering")
+    }
+
+    /// Parse `source`, keeping a `ReparseState` in the tree, and reusing `old` if given. Only
+    /// fails if not `recover`ing.
+    fn parse_keeping_state<'s, 'g>(
+        &'g self,
+        source: &'s Source,
+        old: Option<Old>,
+        recover: bool,
+    ) -> Result<(ParseTree<'s, 'g>, Vec<ParseError<'s>>), ParseError<'s>> {
+        let text = source.source();
+        let mut state = ReparseState::default();
+        let mut resolver = self.resolver(recover);
+
+        // 1. Lex and 2. Resolve, from the last checkpoint before the edit
+        let comments = match old {
+            None => {
+                let mut lexemes = self.lexer.lex_modal(text);
+                self.resolve_keeping_state(source, &mut resolver, &mut lexemes, &mut state)?;
+                lexemes.into_comments()
+            }
+            Some((old_tree, old_state, edit)) => {
+                let limit = unchanged_lexemes(&self.lexer, text, &old_state.lexed, edit);
+                let checkpoints = &old_state.resolver_checkpoints;
+                let usable = checkpoints
+                    .iter()
+                    .rposition(|cp| cp.num_lexemes <= limit && (recover || cp.num_errors == 0));
+                let start = match usable {
+                    None => 0,
+                    Some(i) => {
+                        let checkpoint = &checkpoints[i];
+                        resolver.restore(
+                            checkpoint,
+                            &old_state.resolved,
+                            &old_state.resolver_errors,
+                        );
+                        state.resolver_checkpoints = checkpoints[..=i].to_vec();
+                        checkpoint.num_lexemes
+                    }
+                };
+                state.lexed = old_state.lexed[..start].to_vec();
+                let mut lexemes = Relexer::new(
+                    &self.lexer,
+                    text,
+                    &old_state.lexed,
+                    old_tree.comments(),
+                    edit,
+                    start,
+                    limit,
+                );
+                self.resolve_keeping_state(source, &mut resolver, &mut lexemes, &mut state)?;
+                lexemes.into_comments()
+            }
+        };
+        let (resolved, resolver_errors) = resolver.into_output();
+        let mut errors = resolver_errors
+            .iter()
+            .map(|err| self.resolver_error(source, err.clone()))
+            .collect::<Vec<_>>();
+
+        // 3. Shunt, 4. Collect token spans, and 5. Construct forest, from the last checkpoint
+        //    before the first resolved lexeme that changed
+        let reused = old.and_then(|(old_tree, old_state, _)| {
+            let same = old_state
+                .resolved
+                .iter()
+                .zip(&resolved)
+                .take_while(|(old_lex, lex)| old_lex == lex)
+                .count();
+            let checkpoints = &old_state.build_checkpoints;
+            let num_usable = checkpoints.partition_point(|cp| cp.num_resolved <= same);
+            let checkpoint = checkpoints[..num_usable].last()?;
+            state.build_checkpoints = checkpoints[..num_usable].to_vec();
+            let builder = TreeBuilder::resume(self, old_tree, &old_state.conflicts, checkpoint);
+            Some((builder, checkpoint.num_resolved))
+        });
+        let (mut builder, start) = reused.unwrap_or_else(|| (TreeBuilder::new(self, None), 0));
+        let mut since_checkpoint = 0;
+        for (i, lex) in resolved.iter().enumerate().skip(start) {
+            builder.push(*lex);
+            since_checkpoint += 1;
+            if since_checkpoint >= CHECKPOINT_INTERVAL.max(builder.checkpoint_size()) {
+                state.build_checkpoints.push(builder.checkpoint(i + 1));
+                since_checkpoint = 0;
+            }
+        }
+        state.resolved = resolved;
+        state.resolver_errors = resolver_errors;
+        let tree = builder.finish(source, comments, Some(state), &mut errors);
+        Ok((tree, errors))
+    }
+
+    /// Resolve the lexemes from `input` with `resolver`, recording them and taking checkpoints
+    /// in `state`.
+    fn resolve_keeping_state<'s>(
+        &self,
+        source: &'s Source,
+        resolver: &mut Resolver,
+        mut input: impl TokenSource,
+        state: &mut ReparseState,
+    ) -> Result<(), ParseError<'s>> {
+        let last_checkpoint = state
+            .resolver_checkpoints
+            .last()
+            .map(|cp| cp.num_lexemes)
+            .unwrap_or(0);
+        let mut since_checkpoint = state.lexed.len() - last_checkpoint;
+        while let Some(lexeme) = input.next_lexeme(resolver.arg_mode()) {
+            let arg_mode = resolver.arg_mode();
+            state.lexed.push(Lexed { lexeme, arg_mode });
+            resolver
+                .resolve_lexeme(lexeme)
+                .map_err(|err| self.resolver_error(source, err))?;
+            since_checkpoint += 1;
+            if since_checkpoint >= CHECKPOINT_INTERVAL.max(resolver.stack_len()) {
+                let checkpoint = resolver.checkpoint(state.lexed.len());
+                state.resolver_checkpoints.push(checkpoint);
+                since_checkpoint = 0;
+            }
+        }
+        resolver
+            .finish()
+            .map_err(|err| self.resolver_error(source, err))
+    }
+}
+
+/// The number of lexemes at the start of `old_lexed` that can't have been changed by `edit`.
+/// Lexemes that end before the line the edit starts on can't have changed, unless lexing might
+/// look past the end of a line (e.g. for an unterminated multi-line string, which the edit could
+/// terminate).
+fn unchanged_lexemes(lexer: &Lexer, text: &str, old_lexed: &[Lexed], edit: &Edit) -> usize {
+    if lexer.is_multiline() {
+        return 0;
+    }
+    let line_start = text[..edit.new_span.start]
+        .rfind('\n')
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    old_lexed.partition_point(|lexed| lexed.lexeme.span.end < line_start)
+}
+
+/// A `TokenSource` that produces the same lexemes as lexing the edited source would, by replaying
+/// the old lexemes where it can, and lexing where it can't.
+struct Relexer<'l, 's, 'o> {
+    lexer: &'l Lexer,
+    // The source after the edit
+    text: &'s str,
+    old_lexed: &'o [Lexed],
+    old_comments: &'o [Span],
+    edit: Edit,
+    // Whether lexing depends on whether an operand is expected
+    modal: bool,
+    // Where the lexer would be now: the end of the last lexeme produced
+    offset: Offset,
+    state: RelexState<'l, 's>,
+    comments: Vec<Span>,
+}
+
+enum RelexState<'l, 's> {
+    /// Replaying the old lexemes, starting at `next`, moved by `shift` bytes. If `limit` is given,
+    /// lexing starts there, and otherwise the replay goes to the end of the source. `comment` is
+    /// the first old comment that hasn't been produced.
+    Replay {
+        next: usize,
+        limit: Option<usize>,
+        shift: isize,
+        comment: usize,
+    },
+    /// Lexing the new source.
+    Lex(ModalLexemeIter<'l, 's>),
+    /// At the end of the source.
+    Done,
+}
+
+impl<'l, 's: 'l, 'o> Relexer<'l, 's, 'o> {
+    /// Start replaying the old lexemes at index `start`, and start lexing at index `limit`, or
+    /// earlier if they might lex differently.
+    fn new(
+        lexer: &'l Lexer,
+        text: &'s str,
+        old_lexed: &'o [Lexed],
+        old_comments: &'o [Span],
+        edit: &Edit,
+        start: usize,
+        limit: usize,
+    ) -> Relexer<'l, 's, 'o> {
+        let offset = match start {
+            0 => 0,
+            _ => old_lexed[start - 1].lexeme.span.end,
+        };
+        // These come before the edit, so haven't moved
+        let comment = old_comments.partition_point(|span| span.start < offset);
+        Relexer {
+            lexer,
+            text,
+            old_lexed,
+            old_comments,
+            edit: *edit,
+            modal: lexer.has_modes(),
+            offset,
+            state: RelexState::Replay {
+                next: start,
+                limit: Some(limit),
+                shift: 0,
+                comment,
+            },
+            comments: old_comments[..comment].to_vec(),
+        }
+    }
+
+    fn into_comments(self) -> Vec<Span> {
+        self.comments
+    }
+
+    /// The change in the offset of the text after the edit.
+    fn delta(&self) -> isize {
+        self.edit.new_span.len() as isize - self.edit.old_span.len() as isize
+    }
+
+    /// If lexing the old source, stopping just after a lexeme that ends at new offset `end`, would
+    /// leave the lexer in the same state, give the index of the old lexeme after that.
+    fn resync(&self, end: Offset) -> Option<usize> {
+        if end < self.edit.new_span.end {
+            return None;
+        }
+        let old_end = shift(end, -self.delta());
+        let next = self
+            .old_lexed
+            .partition_point(|lexed| lexed.lexeme.span.end <= old_end);
+        if next > 0 && self.old_lexed[next - 1].lexeme.span.end == old_end {
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    fn start_lexing(&mut self) {
+        let iter = self.lexer.lex_modal_at(self.text, self.offset);
+        self.state = RelexState::Lex(iter);
+    }
+
+    fn take_lexer_comments(&mut self) {
+        if let RelexState::Lex(iter) = &mut self.state {
+            self.comments.extend(iter.take_comments());
+        }
+    }
+}
+
+impl<'l, 's: 'l> TokenSource for &mut Relexer<'l, 's, '_> {
+    fn next_lexeme(&mut self, arg_mode: bool) -> Option<Lexeme> {
+        loop {
+            match &mut self.state {
+                RelexState::Replay {
+                    next,
+                    limit,
+                    shift: delta,
+                    comment,
+                } => {
+                    let delta = *delta;
+                    if Some(*next) == *limit {
+                        // Past the unchanged lexemes before the edit
+                        self.start_lexing();
+                        continue;
+                    }
+                    if *next == self.old_lexed.len() {
+                        // The old lexer stopped here, so produce the comments after its last lexeme
+                        let rest = &self.old_comments[*comment..];
+                        self.comments
+                            .extend(rest.iter().map(|span| shift_span(*span, delta)));
+                        self.state = RelexState::Done;
+                        return None;
+                    }
+                    let lexed = self.old_lexed[*next];
+                    if self.modal && lexed.arg_mode != arg_mode {
+                        // It might lex differently when the other kind of token is expected
+                        self.start_lexing();
+                        continue;
+                    }
+                    *next += 1;
+                    while let Some(span) = self.old_comments.get(*comment) {
+                        if span.start >= lexed.lexeme.span.start {
+                            break;
+                        }
+                        self.comments.push(shift_span(*span, delta));
+                        *comment += 1;
+                    }
+                    let lexeme = Lexeme {
+                        token: lexed.lexeme.token,
+                        span: shift_span(lexed.lexeme.span, delta),
+                    };
+                    self.offset = lexeme.span.end;
+                    return Some(lexeme);
+                }
+                RelexState::Lex(iter) => {
+                    let lexeme = iter.next_lexeme(arg_mode);
+                    self.take_lexer_comments();
+                    let lexeme = match lexeme {
+                        Some(lexeme) => lexeme,
+                        None => {
+                            self.state = RelexState::Done;
+                            return None;
+                        }
+                    };
+                    self.offset = lexeme.span.end;
+                    if let Some(next) = self.resync(self.offset) {
+                        // Back in step with the old lexemes after the edit
+                        let old_offset = self.old_lexed[next - 1].lexeme.span.end;
+                        let comment = self
+                            .old_comments
+                            .partition_point(|span| span.start < old_offset);
+                        self.state = RelexState::Replay {
+                            next,
+                            limit: None,
+                            shift: self.delta(),
+                            comment,
+                        };
+                    }
+                    return Some(lexeme);
+                }
+                RelexState::Done => return None,
+            }
+        }
+    }
+}
+
+fn shift(offset: Offset, delta: isize) -> Offset {
+    (offset as isize + delta) as Offset
+}
+
+fn shift_span(span: Span, delta: isize) -> Span {
+    Span::new(shift(span.start, delta), shift(span.end, delta))
+}
@@ -1,4 +1,8 @@
-use crate::{Lexeme, OpToken, Position, Span, Token, TOKEN_BLANK, TOKEN_ERROR, TOKEN_JUXTAPOSE};
+use crate::lexer::TokenSource;
+use crate::{
+    Lexeme, Offset, OpToken, Span, SubgrammarId, Token, MAIN_SUBGRAMMAR, TOKEN_BAD_INDENT,
+    TOKEN_BLANK, TOKEN_ERROR, TOKEN_JUXTAPOSE,
+};
 
 /// Resolve "tokens" into "op tokens". Both have type `Token` but are in different spaces (i.e. the
 /// token `4` and the op token `4` are likely unrelated). This resolution achieves two tasks:
@@ -7,21 +11,80 @@ use crate::{Lexeme, OpToken, Position, Span, Token, TOKEN_BLANK, TOKEN_ERROR, TO
 ///    and binary minus would have the same _token_ but different _op tokens_.
 /// 2. Insert a `TOKEN_BLANK` for every missing argument and a `TOKEN_JUXTAPOSE` for every missing
 ///    binary operator.
+///
+/// The prefix and suffix tables are indexed first by sub-grammar, then by token. Which sub-grammar
+/// is in use is determined by `optok_to_subgrammar`, for the innermost op token that is still
+/// waiting for its follower (or is the main sub-grammar, if there is none).
+///
+/// An op token may have several possible followers (listed in `optok_to_followers`), and if it
+/// has an entry in `optok_to_final`, the op may also end there instead. In that case, the op ends
+/// once a lexeme is found that doesn't continue it, and the op token is replaced in the output by
+/// its final form (which has the op's right precedence, rather than waiting for a follower).
+///
+/// Each lexeme is requested from `input` together with whether an argument is expected at that
+/// point, so that lexing may depend on it.
 pub fn resolve(
-    tok_to_prefix: &[Option<(OpToken, bool)>],
-    tok_to_suffix: &[Option<(OpToken, bool)>],
-    optok_to_follower: &[Option<(Token, OpToken, bool)>],
-    input: impl IntoIterator<Item = Lexeme>,
+    tok_to_prefix: &[Vec<Option<(OpToken, bool)>>],
+    tok_to_suffix: &[Vec<Option<(OpToken, bool)>>],
+    optok_to_followers: &[Vec<(Token, OpToken, bool)>],
+    optok_to_final: &[Option<OpToken>],
+    optok_to_subgrammar: &[SubgrammarId],
+    input: impl TokenSource,
 ) -> Result<Vec<Lexeme>, ResolverError> {
-    Resolver::new(tok_to_prefix, tok_to_suffix, optok_to_follower).resolve(input.into_iter())
+    Resolver::new(
+        tok_to_prefix,
+        tok_to_suffix,
+        optok_to_followers,
+        optok_to_final,
+        optok_to_subgrammar,
+        false,
+    )
+    .resolve(input)
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Like [`resolve`], but never stops at the first error. Instead:
+///
+/// - Lexing errors and unexpected tokens become `TOKEN_ERROR` atoms.
+/// - Bad indentation is skipped.
+/// - If a token is expected by an operator further down the stack, the operators above it are
+///   completed by inventing their missing tokens (with empty spans, at the end of the previous
+///   lexeme).
+/// - Likewise, any operators still incomplete at the end of the input are completed.
+///
+/// Returns the resolved lexemes (which always form a complete parse), together with every error
+/// encountered along the way.
+pub fn resolve_recovering(
+    tok_to_prefix: &[Vec<Option<(OpToken, bool)>>],
+    tok_to_suffix: &[Vec<Option<(OpToken, bool)>>],
+    optok_to_followers: &[Vec<(Token, OpToken, bool)>],
+    optok_to_final: &[Option<OpToken>],
+    optok_to_subgrammar: &[SubgrammarId],
+    mut input: impl TokenSource,
+) -> (Vec<Lexeme>, Vec<ResolverError>) {
+    let mut resolver = Resolver::new(
+        tok_to_prefix,
+        tok_to_suffix,
+        optok_to_followers,
+        optok_to_final,
+        optok_to_subgrammar,
+        true,
+    );
+    while let Some(lexeme) = input.next_lexeme(resolver.arg_mode) {
+        // Can't fail in recovering mode
+        let _ = resolver.resolve_lexeme(lexeme);
+    }
+    let _ = resolver.finish();
+    (resolver.output, resolver.errors)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ResolverError {
     /// Error while lexing.
     LexError(Lexeme),
     /// Did not expect token; it does not start an operator.
     UnexpectedToken(Lexeme),
+    /// A line's indentation did not match any enclosing block.
+    BadIndentation(Lexeme),
     /// While parsing `op`, expected token `expected` but found token `found.token` (or found
     /// end-of-file if None).
     IncompleteOp {
@@ -32,36 +95,182 @@ pub enum ResolverError {
     },
 }
 
-struct Resolver<'a> {
-    tok_to_prefix: &'a [Option<(OpToken, bool)>],
-    tok_to_suffix: &'a [Option<(OpToken, bool)>],
-    optok_to_follower: &'a [Option<(Token, OpToken, bool)>],
+/// Resolves one lexeme at a time. See `resolve` and `resolve_recovering`.
+#[derive(Debug, Clone)]
+pub(crate) struct Resolver<'a> {
+    tok_to_prefix: &'a [Vec<Option<(OpToken, bool)>>],
+    tok_to_suffix: &'a [Vec<Option<(OpToken, bool)>>],
+    optok_to_followers: &'a [Vec<(Token, OpToken, bool)>],
+    optok_to_final: &'a [Option<OpToken>],
+    optok_to_subgrammar: &'a [SubgrammarId],
     arg_mode: bool,
-    last_pos: Position,
-    stack: Vec<(Token, OpToken, bool, Span)>,
+    last_pos: Offset,
+    stack: Vec<Expected>,
+    output: Vec<Lexeme>,
+    // Error recovery
+    recover: bool,
+    errors: Vec<ResolverError>,
+    // Token -> how many entries on the stack are waiting for it
+    num_expected: Vec<usize>,
+    // Only while speculating: see `Resolver::speculate`
+    log: Option<StackLog>,
+}
+
+/// What a resolver did with part of the input, starting from an empty stack and guessing that an
+/// operand was expected, as if it didn't know what came before. See `Resolver::speculate`.
+#[derive(Debug, Clone)]
+pub(crate) struct Speculation {
+    /// The lexemes resolved
+    pub(crate) lexemes: Vec<Lexeme>,
+    /// The state before each lexeme, and at the end
+    pub(crate) steps: Vec<Step>,
     output: Vec<Lexeme>,
+    // Every entry pushed onto the stack, in order, with the index of the lexeme it was popped
+    // while resolving (or `usize::MAX` if it wasn't)
+    pushed: Vec<(Expected, usize)>,
+}
+
+/// The state of a speculating resolver before one lexeme, and what happened when it resolved it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Step {
+    pub(crate) arg_mode: bool,
+    /// Whether the stack was empty. If so, the real resolver can pick up from here, whatever it
+    /// has on its stack.
+    pub(crate) empty: bool,
+    /// Whether the stack ran out while resolving the lexeme, so that it might have been resolved
+    /// differently with something below. See `Resolver::agrees_below`.
+    pub(crate) bottomed_out: bool,
+    /// Whether resolving the lexeme failed. The resolver then started over after it.
+    pub(crate) failed: bool,
+    num_resolved: usize,
+    num_pushed: usize,
+}
+
+/// What a speculating resolver records about its stack, while resolving the lexeme at `index`.
+#[derive(Debug, Clone, Default)]
+struct StackLog {
+    index: usize,
+    bottomed_out: bool,
+    pushed: Vec<(Expected, usize)>,
+}
+
+/// The state of a `Resolver` at some point, from which it can be resumed. See
+/// `Resolver::checkpoint`.
+#[derive(Debug, Clone)]
+pub(crate) struct Checkpoint {
+    /// The number of lexemes resolved so far
+    pub(crate) num_lexemes: usize,
+    /// The length of the output so far
+    pub(crate) num_resolved: usize,
+    /// The number of errors found so far
+    pub(crate) num_errors: usize,
+    arg_mode: bool,
+    last_pos: Offset,
+    stack: Vec<Expected>,
+}
+
+/// An op token that has been found, waiting for its follower.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Expected {
+    /// The op token that is waiting
+    optok: OpToken,
+    /// Its index in the output, so that it can be replaced if the op ends there
+    index: usize,
+    /// Whether it's followed by an argument
+    has_arg: bool,
+    /// The span of the op token that is waiting
+    span: Span,
+    /// The sub-grammar to use until the follower is found
+    subgrammar: SubgrammarId,
 }
 
 impl<'a> Resolver<'a> {
-    fn new(
-        tok_to_prefix: &'a [Option<(OpToken, bool)>],
-        tok_to_suffix: &'a [Option<(OpToken, bool)>],
-        optok_to_follower: &'a [Option<(Token, OpToken, bool)>],
+    pub(crate) fn new(
+        tok_to_prefix: &'a [Vec<Option<(OpToken, bool)>>],
+        tok_to_suffix: &'a [Vec<Option<(OpToken, bool)>>],
+        optok_to_followers: &'a [Vec<(Token, OpToken, bool)>],
+        optok_to_final: &'a [Option<OpToken>],
+        optok_to_subgrammar: &'a [SubgrammarId],
+        recover: bool,
     ) -> Resolver<'a> {
         Resolver {
             tok_to_prefix,
             tok_to_suffix,
-            optok_to_follower,
+            optok_to_followers,
+            optok_to_final,
+            optok_to_subgrammar,
             arg_mode: true,
-            last_pos: Position::start_of_file(),
+            last_pos: 0,
             stack: vec![],
             output: vec![],
+            recover,
+            errors: vec![],
+            num_expected: if recover {
+                vec![0; tok_to_prefix[MAIN_SUBGRAMMAR].len()]
+            } else {
+                vec![]
+            },
+            log: None,
         }
     }
 
+    /// Whether an operand (an atom or prefix operator) is expected next.
+    pub(crate) fn arg_mode(&self) -> bool {
+        self.arg_mode
+    }
+
+    /// The number of op tokens waiting for their followers.
+    pub(crate) fn stack_len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// The resolved lexemes and the errors found. Call `finish` first.
+    pub(crate) fn into_output(self) -> (Vec<Lexeme>, Vec<ResolverError>) {
+        (self.output, self.errors)
+    }
+
+    /// Record the current state, after resolving `num_lexemes` lexemes. O(`self.stack_len()`).
+    pub(crate) fn checkpoint(&self, num_lexemes: usize) -> Checkpoint {
+        Checkpoint {
+            num_lexemes,
+            num_resolved: self.output.len(),
+            num_errors: self.errors.len(),
+            arg_mode: self.arg_mode,
+            last_pos: self.last_pos,
+            stack: self.stack.clone(),
+        }
+    }
+
+    /// Put this new resolver into the state of `checkpoint`, which was taken while resolving a
+    /// source whose final output was `resolved`, and that found `errors`. This must be a
+    /// recovering resolver if the checkpoint's errors aren't empty.
+    pub(crate) fn restore(
+        &mut self,
+        checkpoint: &Checkpoint,
+        resolved: &[Lexeme],
+        errors: &[ResolverError],
+    ) {
+        self.arg_mode = checkpoint.arg_mode;
+        self.last_pos = checkpoint.last_pos;
+        self.output = resolved[..checkpoint.num_resolved].to_vec();
+        self.errors = errors[..checkpoint.num_errors].to_vec();
+        for entry in &checkpoint.stack {
+            // It hadn't been replaced by its final form yet
+            self.output[entry.index].token = entry.optok;
+            self.push(*entry);
+        }
+    }
+
+    /// Produce `optok`. `self.arg_mode` must already say whether it's followed by an argument.
     fn produce(&mut self, optok: OpToken, span: Span) {
-        if let Some((tok, optok, has_arg)) = self.optok_to_follower[optok] {
-            self.stack.push((tok, optok, has_arg, span));
+        if !self.optok_to_followers[optok].is_empty() {
+            self.push(Expected {
+                optok,
+                index: self.output.len(),
+                has_arg: self.arg_mode,
+                span,
+                subgrammar: self.optok_to_subgrammar[optok],
+            });
         }
         self.output.push(Lexeme { token: optok, span });
     }
@@ -69,20 +278,102 @@ impl<'a> Resolver<'a> {
     fn produce_at_last_pos(&mut self, optok: OpToken) {
         self.output.push(Lexeme {
             token: optok,
-            span: Span::new_at_pos(self.last_pos),
+            span: Span::new_at(self.last_pos),
         });
     }
 
-    fn error(
-        &mut self,
-        top: Option<(Token, OpToken, bool, Span)>,
-        lexeme: Lexeme,
-    ) -> ResolverError {
-        if let Some((tok, optok, _, span)) = top {
+    fn push(&mut self, entry: Expected) {
+        if self.recover {
+            for (token, _, _) in &self.optok_to_followers[entry.optok] {
+                self.num_expected[*token] += 1;
+            }
+        }
+        if let Some(log) = &mut self.log {
+            log.pushed.push((entry, usize::MAX));
+        }
+        self.stack.push(entry);
+    }
+
+    fn pop(&mut self) -> Option<Expected> {
+        let entry = self.stack.pop()?;
+        if self.recover {
+            for (token, _, _) in &self.optok_to_followers[entry.optok] {
+                self.num_expected[*token] -= 1;
+            }
+        }
+        if let Some(log) = &mut self.log {
+            // Entries are pushed in the order they're output
+            let i = log
+                .pushed
+                .partition_point(|(pushed, _)| pushed.index < entry.index);
+            log.pushed[i].1 = log.index;
+        }
+        Some(entry)
+    }
+
+    /// If `token` can follow `optok`, the follower's op token and whether it's followed by an
+    /// argument.
+    fn follower(&self, optok: OpToken, token: Token) -> Option<(OpToken, bool)> {
+        self.optok_to_followers[optok]
+            .iter()
+            .find(|(tok, _, _)| *tok == token)
+            .map(|(_, follower, has_arg)| (*follower, *has_arg))
+    }
+
+    /// The follower to expect after `optok` if it isn't there: the one that continues past any
+    /// optional tokens.
+    fn expected(&self, optok: OpToken) -> (Token, OpToken, bool) {
+        *self.optok_to_followers[optok].last().unwrap()
+    }
+
+    /// Whether the op on top of the stack may end here, because `lexeme` doesn't continue it:
+    /// it's neither a follower, nor the start of an op in the op's argument.
+    fn top_op_ends_before(&self, top: Expected, lexeme: Lexeme) -> bool {
+        if self.optok_to_final[top.optok].is_none()
+            || self.follower(top.optok, lexeme.token).is_some()
+        {
+            return false;
+        }
+        let starts_op = |table: &[Vec<Option<(OpToken, bool)>>]| {
+            table[top.subgrammar]
+                .get(lexeme.token)
+                .map(|entry| entry.is_some())
+                .unwrap_or(false)
+        };
+        !top.has_arg || !(starts_op(self.tok_to_prefix) || starts_op(self.tok_to_suffix))
+    }
+
+    /// End the op on top of the stack at the token it's waiting after, by replacing that token
+    /// with its final form.
+    fn end_top_op(&mut self) {
+        let top = self.pop().unwrap();
+        self.output[top.index].token = self.optok_to_final[top.optok].unwrap();
+    }
+
+    /// The sub-grammar in use at the current position.
+    fn subgrammar(&self) -> SubgrammarId {
+        self.stack
+            .last()
+            .map(|entry| entry.subgrammar)
+            .unwrap_or(MAIN_SUBGRAMMAR)
+    }
+
+    /// In strict mode, fail with `error`. In recovering mode, record it and carry on.
+    fn report(&mut self, error: ResolverError) -> Result<(), ResolverError> {
+        if self.recover {
+            self.errors.push(error);
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    fn error(&mut self, top: Option<Expected>, lexeme: Lexeme) -> ResolverError {
+        if let Some(top) = top {
             ResolverError::IncompleteOp {
-                op: optok,
-                op_span: span,
-                expected: tok,
+                op: top.optok,
+                op_span: top.span,
+                expected: self.expected(top.optok).0,
                 found: Some(lexeme),
             }
         } else {
@@ -90,54 +381,225 @@ impl<'a> Resolver<'a> {
         }
     }
 
-    fn resolve(
-        mut self,
-        input: impl Iterator<Item = Lexeme>,
-    ) -> Result<Vec<Lexeme>, ResolverError> {
-        for lexeme in input {
-            if lexeme.token == TOKEN_ERROR {
-                return Err(ResolverError::LexError(lexeme));
+    fn resolve(mut self, mut input: impl TokenSource) -> Result<Vec<Lexeme>, ResolverError> {
+        while let Some(lexeme) = input.next_lexeme(self.arg_mode) {
+            self.resolve_lexeme(lexeme)?;
+        }
+        self.finish()?;
+        Ok(self.output)
+    }
+
+    pub(crate) fn resolve_lexeme(&mut self, lexeme: Lexeme) -> Result<(), ResolverError> {
+        if lexeme.token == TOKEN_BAD_INDENT {
+            // When recovering, bad indentation is otherwise ignored.
+            return self.report(ResolverError::BadIndentation(lexeme));
+        }
+        if lexeme.token == TOKEN_ERROR {
+            // When recovering, the error token falls through and becomes an atom.
+            self.report(ResolverError::LexError(lexeme))?;
+        }
+        while let Some(top) = self.stack.last().copied() {
+            if !self.top_op_ends_before(top, lexeme) {
+                break;
             }
-            match self.stack.last().copied() {
-                Some((tok, optok, has_arg, _)) if lexeme.token == tok => {
-                    if self.arg_mode {
-                        self.produce_at_last_pos(TOKEN_BLANK);
-                    }
+            self.end_top_op();
+        }
+        if let Some(log) = &mut self.log {
+            log.bottomed_out = self.stack.is_empty();
+        }
+        let top = self.stack.last().copied();
+        match top.and_then(|top| self.follower(top.optok, lexeme.token)) {
+            Some((optok, has_arg)) => self.produce_follower(optok, has_arg, lexeme.span),
+            None => {
+                let subgrammar = self.subgrammar();
+                let (tok_to_op, fallback_tok_to_op, missing) = if self.arg_mode {
+                    (
+                        &self.tok_to_prefix[subgrammar],
+                        &self.tok_to_suffix[subgrammar],
+                        TOKEN_BLANK,
+                    )
+                } else {
+                    (
+                        &self.tok_to_suffix[subgrammar],
+                        &self.tok_to_prefix[subgrammar],
+                        TOKEN_JUXTAPOSE,
+                    )
+                };
+                if let Some((optok, has_arg)) = tok_to_op[lexeme.token] {
                     self.arg_mode = has_arg;
-                    self.stack.pop();
                     self.produce(optok, lexeme.span);
-                }
-                top => {
-                    let (tok_to_op, fallback_tok_to_op, missing) = if self.arg_mode {
-                        (&self.tok_to_prefix, &self.tok_to_suffix, TOKEN_BLANK)
-                    } else {
-                        (&self.tok_to_suffix, &self.tok_to_prefix, TOKEN_JUXTAPOSE)
-                    };
-                    if let Some((optok, has_arg)) = tok_to_op[lexeme.token] {
-                        self.arg_mode = has_arg;
-                        self.produce(optok, lexeme.span);
-                    } else if let Some((optok, has_arg)) = fallback_tok_to_op[lexeme.token] {
-                        self.arg_mode = has_arg;
-                        self.produce_at_last_pos(missing);
-                        self.produce(optok, lexeme.span);
-                    } else {
-                        return Err(self.error(top, lexeme));
+                } else if let Some((optok, has_arg)) = fallback_tok_to_op[lexeme.token] {
+                    self.arg_mode = has_arg;
+                    self.produce_at_last_pos(missing);
+                    self.produce(optok, lexeme.span);
+                } else if !self.recover {
+                    return Err(self.error(top, lexeme));
+                } else if self.num_expected[lexeme.token] > 0 {
+                    // Some enclosing op is waiting for this token. Complete the ops in between.
+                    while let Some(top) = self.stack.last().copied() {
+                        if self.follower(top.optok, lexeme.token).is_some() {
+                            break;
+                        }
+                        if self.optok_to_final[top.optok].is_some() {
+                            self.end_top_op();
+                            continue;
+                        }
+                        self.report(ResolverError::IncompleteOp {
+                            op: top.optok,
+                            op_span: top.span,
+                            expected: self.expected(top.optok).0,
+                            found: Some(lexeme),
+                        })?;
+                        self.complete_top_op();
+                    }
+                    let top = self.stack.last().copied().unwrap();
+                    let (optok, has_arg) = self.follower(top.optok, lexeme.token).unwrap();
+                    self.produce_follower(optok, has_arg, lexeme.span);
+                } else {
+                    // Nothing wants this token. Treat it as an error atom.
+                    self.report(ResolverError::UnexpectedToken(lexeme))?;
+                    if !self.arg_mode {
+                        self.produce_at_last_pos(TOKEN_JUXTAPOSE);
                     }
+                    self.arg_mode = false;
+                    self.produce(TOKEN_ERROR, lexeme.span);
                 }
             }
-            self.last_pos = lexeme.span.end;
         }
-        if let Some((tok, optok, _, span)) = self.stack.pop() {
-            return Err(ResolverError::IncompleteOp {
-                op: optok,
-                op_span: span,
-                expected: tok,
+        self.last_pos = lexeme.span.end;
+        Ok(())
+    }
+
+    /// Produce the follower on top of the stack, that was just found at `span`.
+    fn produce_follower(&mut self, optok: OpToken, has_arg: bool, span: Span) {
+        if self.arg_mode {
+            self.produce_at_last_pos(TOKEN_BLANK);
+        }
+        self.arg_mode = has_arg;
+        self.pop();
+        self.produce(optok, span);
+    }
+
+    /// Invent all of the remaining tokens of the op on top of the stack, placing them at the end
+    /// of the last lexeme. Optional tokens are left out.
+    fn complete_top_op(&mut self) {
+        while let Some(top) = self.stack.last().copied() {
+            if self.optok_to_final[top.optok].is_some() {
+                self.end_top_op();
+                break;
+            }
+            let (_, optok, has_arg) = self.expected(top.optok);
+            self.produce_follower(optok, has_arg, Span::new_at(self.last_pos));
+            if self.optok_to_followers[optok].is_empty() {
+                break;
+            }
+        }
+    }
+
+    /// Resolve every lexeme from `input`, from the state this new resolver is in, recording
+    /// enough to stitch its work into that of a resolver that knows what came before (see
+    /// `splice`). Wherever resolving a lexeme fails, start over after it, with an empty stack.
+    /// Doesn't call `finish`.
+    pub(crate) fn speculate(mut self, mut input: impl TokenSource) -> Speculation {
+        self.log = Some(StackLog::default());
+        let mut lexemes = Vec::new();
+        let mut steps = Vec::new();
+        loop {
+            let log = self.log.as_mut().unwrap();
+            log.index = lexemes.len();
+            log.bottomed_out = false;
+            let mut step = Step {
+                arg_mode: self.arg_mode,
+                empty: self.stack.is_empty(),
+                bottomed_out: false,
+                failed: false,
+                num_resolved: self.output.len(),
+                num_pushed: log.pushed.len(),
+            };
+            let lexeme = match input.next_lexeme(self.arg_mode) {
+                Some(lexeme) => lexeme,
+                None => {
+                    steps.push(step);
+                    break;
+                }
+            };
+            if self.resolve_lexeme(lexeme).is_err() {
+                step.failed = true;
+                // The entries left on the stack are abandoned, rather than popped
+                self.stack.clear();
+                self.arg_mode = true;
+                self.last_pos = lexeme.span.end;
+            }
+            step.bottomed_out = self.log.as_ref().unwrap().bottomed_out;
+            lexemes.push(lexeme);
+            steps.push(step);
+        }
+        Speculation {
+            lexemes,
+            steps,
+            output: self.output,
+            pushed: self.log.unwrap().pushed,
+        }
+    }
+
+    /// Whether a speculating resolver whose stack ran out while resolving `lexeme` (see
+    /// `Step::bottomed_out`) did the same as this one would have, with its stack below: if the op
+    /// on top of it doesn't end before `lexeme`, isn't continued by it, and uses the main
+    /// sub-grammar for its argument.
+    pub(crate) fn agrees_below(&self, lexeme: Lexeme) -> bool {
+        match self.stack.last().copied() {
+            None => true,
+            Some(top) => {
+                !self.top_op_ends_before(top, lexeme)
+                    && self.follower(top.optok, lexeme.token).is_none()
+                    && top.subgrammar == MAIN_SUBGRAMMAR
+            }
+        }
+    }
+
+    /// Take the work that `spec` did on its lexemes from `start` up to `end`, as if this resolver
+    /// had resolved them. This resolver must be in the state that `spec` was in at `start`, which
+    /// must be a step whose stack was `empty`, except for what this one has on its stack. And
+    /// none of the lexemes may have `failed`, or have `bottomed_out` unless this one
+    /// `agrees_below` it.
+    pub(crate) fn splice(&mut self, spec: &Speculation, start: usize, end: usize) {
+        let (from, to) = (spec.steps[start], spec.steps[end]);
+        let base = self.output.len();
+        self.output
+            .extend_from_slice(&spec.output[from.num_resolved..to.num_resolved]);
+        for (entry, popped) in &spec.pushed[from.num_pushed..to.num_pushed] {
+            if *popped >= end {
+                let entry = Expected {
+                    index: entry.index - from.num_resolved + base,
+                    ..*entry
+                };
+                // It hadn't been replaced by its final form yet
+                self.output[entry.index].token = entry.optok;
+                self.push(entry);
+            }
+        }
+        self.arg_mode = to.arg_mode;
+        self.last_pos = spec.lexemes[end - 1].span.end;
+    }
+
+    /// Complete every op that's still waiting, at the end of the input.
+    pub(crate) fn finish(&mut self) -> Result<(), ResolverError> {
+        while let Some(top) = self.stack.last().copied() {
+            if self.optok_to_final[top.optok].is_some() {
+                self.end_top_op();
+                continue;
+            }
+            self.report(ResolverError::IncompleteOp {
+                op: top.optok,
+                op_span: top.span,
+                expected: self.expected(top.optok).0,
                 found: None,
-            });
+            })?;
+            self.complete_top_op();
         }
         if self.arg_mode {
             self.produce_at_last_pos(TOKEN_BLANK);
         }
-        Ok(self.output)
+        Ok(())
     }
 }
@@ -1,4 +1,5 @@
 use crate::{Lexeme, Prec};
+use std::collections::VecDeque;
 use std::iter;
 
 /// Convert a token stream into reverse polish notation. For example, `1 * 2 + 3 * 4` would become
@@ -13,58 +14,166 @@ pub fn shunt<'a, 's: 'a>(
     prec_table: &'a [(Prec, Prec)],
     iter: impl Iterator<Item = Lexeme> + 'a,
 ) -> impl Iterator<Item = Lexeme> + 'a {
-    Shunter {
-        prec_table,
-        stack: vec![],
-        iter: iter.peekable(),
-        pop_mode: false,
-    }
+    let mut shunter = Shunter::new(prec_table);
+    let mut iter = iter.fuse();
+    let mut output = VecDeque::new();
+    let mut finished = false;
+    iter::from_fn(move || loop {
+        if let Some(lexeme) = output.pop_front() {
+            return Some(lexeme);
+        }
+        match iter.next() {
+            Some(lexeme) => shunter.push(lexeme, &mut output),
+            None if !finished => {
+                shunter.finish(&mut output);
+                finished = true;
+            }
+            None => return None,
+        }
+    })
+}
+
+/// What a shunter did with part of its input, starting from an empty stack, as if it didn't know
+/// what came before. See `Shunter::speculate`.
+#[derive(Debug, Clone)]
+pub(crate) struct Speculation {
+    output: Vec<Lexeme>,
+    // Wherever the stack ran out, the index into `output`, and what it would have done next with
+    // more on its stack
+    drains: Vec<(usize, Drain)>,
+    stack: Vec<Lexeme>,
+}
+
+/// What a speculating shunter would have done when its stack ran out, if it was given more.
+#[derive(Debug, Clone, Copy)]
+enum Drain {
+    /// It just output a lexeme with this left precedence, so would keep popping lexemes that bind
+    /// tighter.
+    AfterPop(Prec),
+    /// It's about to push a lexeme with this left precedence, so would first pop lexemes that
+    /// bind tighter.
+    BeforePush(Prec),
 }
 
-struct Shunter<'a, I>
-where
-    I: Iterator<Item = Lexeme>,
-{
+/// The state of the shunting yard algorithm: the lexemes that are waiting for their right
+/// argument to end. Lexemes are pushed in one at a time, and each push outputs every lexeme
+/// that's now complete.
+#[derive(Debug, Clone)]
+pub(crate) struct Shunter<'a> {
     prec_table: &'a [(Prec, Prec)],
     stack: Vec<Lexeme>,
-    iter: iter::Peekable<I>,
-    pop_mode: bool,
 }
 
-impl<I: Iterator<Item = Lexeme>> Shunter<'_, I> {
+impl<'a> Shunter<'a> {
+    pub(crate) fn new(prec_table: &'a [(Prec, Prec)]) -> Shunter<'a> {
+        Shunter {
+            prec_table,
+            stack: vec![],
+        }
+    }
+
+    /// A shunter that's waiting on `stack`, as given by `.stack()`.
+    pub(crate) fn with_stack(prec_table: &'a [(Prec, Prec)], stack: Vec<Lexeme>) -> Shunter<'a> {
+        Shunter { prec_table, stack }
+    }
+
+    /// The lexemes that are waiting, from the bottom of the stack up.
+    pub(crate) fn stack(&self) -> &[Lexeme] {
+        &self.stack
+    }
+
     fn top_rprec(&self) -> Prec {
         self.stack
             .last()
             .map(|lex| self.prec_table[lex.token].1)
             .unwrap_or(Prec::MAX)
     }
-}
-
-impl<I: Iterator<Item = Lexeme>> Iterator for Shunter<'_, I> {
-    type Item = Lexeme;
 
-    fn next(&mut self) -> Option<Lexeme> {
+    /// Pop lexemes off the stack and output them, until the one popped binds looser than the
+    /// one below it.
+    fn pop_run(&mut self, output: &mut impl Extend<Lexeme>) {
         loop {
-            if self.pop_mode {
-                let lexeme = self.stack.pop().unwrap();
-                let lprec = self.prec_table[lexeme.token].0;
-                let rprec = self.top_rprec();
-                if rprec > lprec {
-                    self.pop_mode = false;
+            let lexeme = self.stack.pop().unwrap();
+            output.extend([lexeme]);
+            if self.top_rprec() > self.prec_table[lexeme.token].0 {
+                return;
+            }
+        }
+    }
+
+    /// Push `lexeme`, first outputting everything it ends.
+    pub(crate) fn push(&mut self, lexeme: Lexeme, output: &mut impl Extend<Lexeme>) {
+        let lprec = self.prec_table[lexeme.token].0;
+        while self.top_rprec() < lprec {
+            self.pop_run(output);
+        }
+        self.stack.push(lexeme);
+    }
+
+    /// Shunt `lexemes`, starting from an empty stack, recording enough to stitch the work into
+    /// that of a shunter that knows what came before (see `splice`).
+    pub(crate) fn speculate(prec_table: &[(Prec, Prec)], lexemes: &[Lexeme]) -> Speculation {
+        let mut shunter = Shunter::new(prec_table);
+        let mut output = Vec::new();
+        let mut drains = Vec::new();
+        for lexeme in lexemes {
+            let lprec = prec_table[lexeme.token].0;
+            loop {
+                if shunter.stack.is_empty() {
+                    drains.push((output.len(), Drain::BeforePush(lprec)));
+                    break;
                 }
-                return Some(lexeme);
-            } else if let Some(lexeme) = self.iter.peek().copied() {
-                let rprec = self.top_rprec();
-                let lprec = self.prec_table[lexeme.token].0;
-                if rprec >= lprec {
-                    self.stack.push(lexeme);
-                    self.iter.next();
-                } else {
-                    self.pop_mode = true;
+                if shunter.top_rprec() >= lprec {
+                    break;
+                }
+                loop {
+                    let popped = shunter.stack.pop().unwrap();
+                    output.push(popped);
+                    let popped_lprec = prec_table[popped.token].0;
+                    if shunter.stack.is_empty() {
+                        drains.push((output.len(), Drain::AfterPop(popped_lprec)));
+                        break;
+                    }
+                    if shunter.top_rprec() > popped_lprec {
+                        break;
+                    }
                 }
-            } else {
-                return self.stack.pop();
             }
+            shunter.stack.push(*lexeme);
+        }
+        Speculation {
+            output,
+            drains,
+            stack: shunter.stack,
         }
     }
+
+    /// Take the work that `spec` did, as if this shunter had shunted its lexemes, adding to
+    /// `output`.
+    pub(crate) fn splice(&mut self, spec: &Speculation, output: &mut Vec<Lexeme>) {
+        let mut copied = 0;
+        for (index, drain) in &spec.drains {
+            output.extend_from_slice(&spec.output[copied..*index]);
+            copied = *index;
+            match *drain {
+                Drain::AfterPop(lprec) => {
+                    if self.top_rprec() <= lprec {
+                        self.pop_run(output);
+                    }
+                }
+                Drain::BeforePush(lprec) => {
+                    while self.top_rprec() < lprec {
+                        self.pop_run(output);
+                    }
+                }
+            }
+        }
+        output.extend_from_slice(&spec.output[copied..]);
+        self.stack.extend_from_slice(&spec.stack);
+    }
+
+    /// Output everything that's left, at the end of the input.
+    pub(crate) fn finish(&mut self, output: &mut impl Extend<Lexeme>) {
+        output.extend(self.stack.drain(..).rev());
+    }
 }
@@ -1,4 +1,7 @@
+use std::borrow::Cow;
 use std::fmt;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
 
 /// A byte offset into the source file.
 pub type Offset = usize;
@@ -7,6 +10,10 @@ pub type Line = u32;
 /// A column number of a source file. Zero indexed.
 pub type Col = u32;
 
+/// The number of columns between tab stops when displaying source text, unless set otherwise (see
+/// `ParseError::display_with_tab_width`).
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
 /// A position in the source text. Positions are _between_ characters.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Position {
@@ -18,11 +25,12 @@ pub struct Position {
     pub utf8_col: Col,
 }
 
-/// A start and end position in the source text.
+/// A start and end byte offset in the source text. Use `Source::position_at` to find the line and
+/// column of either end.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Span {
-    pub start: Position,
-    pub end: Position,
+    pub start: Offset,
+    pub end: Offset,
 }
 
 impl fmt::Display for Position {
@@ -67,25 +75,39 @@ impl Position {
 }
 
 impl Span {
-    pub fn new(start: Position, end: Position) -> Span {
+    pub fn new(start: Offset, end: Offset) -> Span {
         Span { start, end }
     }
 
-    pub fn new_at_pos(pos: Position) -> Span {
+    /// The empty span at `offset`.
+    pub fn new_at(offset: Offset) -> Span {
         Span {
-            start: pos,
-            end: pos,
+            start: offset,
+            end: offset,
         }
     }
 
+    /// The length of the span, in bytes.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
     pub fn is_empty(&self) -> bool {
         self.start == self.end
     }
 }
 
+/// A change made to a `Source` by `Source::apply_edit`: the text that was at `old_span` was
+/// replaced by the text that's now at `new_span`. (They start at the same offset.)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Edit {
+    pub old_span: Span,
+    pub new_span: Span,
+}
+
 /// Source text, and a "filename" for it (though it need not have been read from a file).
 /// Newline positions within the source are indexed, which allows all methods to run in
-/// constant time.
+/// constant time, except for `position_at` which takes logarithmic time.
 #[derive(Debug, Clone)]
 pub struct Source {
     filename: String,
@@ -130,6 +152,43 @@ impl Source {
         }
     }
 
+    /// Replace the text in `range` with `new_text`, and update the index of newline positions.
+    /// Returns the `Edit`, which `Parser::reparse` uses to reparse only the part of the source
+    /// around it. Takes time linear in the length of the source, to move the text after the
+    /// edit, but only scans `new_text` for newlines.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds, or its ends aren't on char boundaries.
+    pub fn apply_edit(&mut self, range: Span, new_text: &str) -> Edit {
+        self.source.replace_range(range.start..range.end, new_text);
+        let new_end = range.start + new_text.len();
+
+        // A line starts just after each newline. Keep the lines that start before the edit, add
+        // the ones that start in the new text, and shift the ones that start after the edit.
+        let first_removed = self
+            .newline_positions
+            .partition_point(|line_start| *line_start <= range.start);
+        let first_shifted = self
+            .newline_positions
+            .partition_point(|line_start| *line_start <= range.end);
+        let added = new_text
+            .match_indices('\n')
+            .map(|(i, _)| range.start + i + 1);
+        let shifted = self.newline_positions[first_shifted..]
+            .iter()
+            .map(|line_start| line_start - range.end + new_end);
+        let rest = added.chain(shifted).collect::<Vec<_>>();
+        self.newline_positions.truncate(first_removed);
+        self.newline_positions.extend(rest);
+        self.ends_in_newline = self.source.ends_with('\n');
+
+        Edit {
+            old_span: range,
+            new_span: Span::new(range.start, new_end),
+        }
+    }
+
     /// The "filename" given in the constructor (though the source need not have been
     /// read from a file).
     pub fn filename(&self) -> &str {
@@ -148,7 +207,7 @@ impl Source {
 
     /// Get the substring of the source between the start and end of the given span.
     pub fn substr(&self, span: Span) -> &str {
-        &self.source[self.offset(span.start)..self.offset(span.end)]
+        &self.source[span.start..span.end]
     }
 
     /// Get the substring of the source between the start and end offset.
@@ -161,6 +220,67 @@ impl Source {
         self.newline_positions[pos.line as usize] + pos.col as usize
     }
 
+    /// Convert a byte offset from the beginning of the source into a position (its line and
+    /// column). Takes logarithmic time in the number of lines, plus linear time in the length of
+    /// the line to count its codepoints.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is past the end of the source, or isn't on a char boundary.
+    pub fn position_at(&self, offset: Offset) -> Position {
+        let line = self
+            .newline_positions
+            .partition_point(|line_start| *line_start <= offset)
+            - 1;
+        let line_start = self.newline_positions[line];
+        Position {
+            line: line as Line,
+            col: (offset - line_start) as Col,
+            utf8_col: self.source[line_start..offset].chars().count() as Col,
+        }
+    }
+
+    /// The column of `pos`, counted in UTF-16 code units. This is how the Language Server Protocol
+    /// counts columns by default.
+    pub fn utf16_col(&self, pos: Position) -> Col {
+        let line_start = self.newline_positions[pos.line as usize];
+        let prefix = &self.source[line_start..line_start + pos.col as usize];
+        prefix.chars().map(char::len_utf16).sum::<usize>() as Col
+    }
+
+    /// The offset of the position on `line` whose column, counted in UTF-16 code units, is
+    /// `utf16_col`. (The inverse of `utf16_col`.) A column past the end of the line gives the end
+    /// of the line, and a column in the middle of a surrogate pair gives the start of its char.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are fewer than `line` lines.
+    pub fn offset_from_utf16(&self, line: Line, utf16_col: Col) -> Offset {
+        let (start, end) = self.line_span(line);
+        let mut col = 0;
+        for (i, ch) in self.source[start..end].char_indices() {
+            col += ch.len_utf16();
+            if col > utf16_col as usize {
+                return start + i;
+            }
+        }
+        end
+    }
+
+    /// The column that `pos` is displayed at in a terminal, if its line is displayed starting at
+    /// column 0 with each tab expanded to the next multiple of `tab_width`. Wide characters (like
+    /// most CJK characters) take two columns, and each grapheme cluster takes as many columns as
+    /// its widest character.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tab_width` is 0.
+    pub fn display_col(&self, pos: Position, tab_width: usize) -> Col {
+        let line_start = self.newline_positions[pos.line as usize];
+        let prefix = &self.source[line_start..line_start + pos.col as usize];
+        display_width(prefix, tab_width) as Col
+    }
+
     /// Get the contents of the `line`th line. Excludes the line termination character(s).
     ///
     /// # Panics
@@ -227,39 +347,98 @@ impl Source {
     ///          ^^^^^
     /// ```
     pub fn show_span(&self, f: &mut fmt::Formatter, span: Span) -> fmt::Result {
-        if span.start.line == span.end.line {
-            self.show_line(
-                f,
-                span.start.line,
-                Some(span.start.utf8_col),
-                Some(span.end.utf8_col),
-            )
+        self.show_span_with_tab_width(f, span, DEFAULT_TAB_WIDTH)
+    }
+
+    /// Like `show_span`, but expanding tabs to multiples of `tab_width` columns instead of
+    /// `DEFAULT_TAB_WIDTH` (like `ErrorDisplay::tab_width`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tab_width` is 0.
+    pub fn show_span_with_tab_width(
+        &self,
+        f: &mut fmt::Formatter,
+        span: Span,
+        tab_width: usize,
+    ) -> fmt::Result {
+        let start = self.position_at(span.start);
+        let end = self.position_at(span.end);
+        if start.line == end.line {
+            self.show_line(f, start.line, Some(start.col), Some(end.col), tab_width)
         } else {
-            self.show_line(f, span.start.line, Some(span.start.utf8_col), None)?;
-            for line_num in span.start.line + 1..span.end.line {
-                self.show_line(f, line_num, None, None)?;
+            self.show_line(f, start.line, Some(start.col), None, tab_width)?;
+            for line_num in start.line + 1..end.line {
+                self.show_line(f, line_num, None, None, tab_width)?;
             }
-            self.show_line(f, span.end.line, None, Some(span.end.utf8_col))
+            self.show_line(f, end.line, None, Some(end.col), tab_width)
         }
     }
 
+    /// Show a line, and carets under the part of it between byte columns `start` and `end`.
     fn show_line(
         &self,
         f: &mut fmt::Formatter,
         line_num: Line,
         start: Option<Col>,
         end: Option<Col>,
+        tab_width: usize,
     ) -> fmt::Result {
         let line = self.line_contents(line_num);
-        let start = start.unwrap_or(0);
-        let end = end.unwrap_or_else(|| line.chars().count() as Col);
-        writeln!(f, "{}", line)?;
-        for _ in 0..start {
+        // A column can be past the end of the line's contents, if it's between a `\r` and a `\n`.
+        let start = (start.unwrap_or(0) as usize).min(line.len());
+        let end = end
+            .map(|end| end as usize)
+            .unwrap_or(line.len())
+            .min(line.len());
+        let carets_start = display_width(&line[..start], tab_width);
+        let carets_end = display_width(&line[..end], tab_width);
+        writeln!(f, "{}", expand_tabs(line, tab_width))?;
+        for _ in 0..carets_start {
             write!(f, " ")?;
         }
-        for _ in 0..(end - start).max(1) {
+        for _ in 0..(carets_end - carets_start).max(1) {
             write!(f, "^")?;
         }
         writeln!(f)
     }
 }
+
+/// The number of columns that `text` (which must not contain a newline) takes up in a terminal,
+/// if it starts at column 0 and each tab is expanded to the next multiple of `tab_width`.
+pub(crate) fn display_width(text: &str, tab_width: usize) -> usize {
+    assert!(tab_width > 0, "Tab width must be positive");
+    let mut width = 0;
+    for grapheme in text.graphemes(true) {
+        if grapheme == "\t" {
+            width += tab_width - width % tab_width;
+        } else {
+            width += grapheme
+                .chars()
+                .map(|ch| ch.width().unwrap_or(0))
+                .max()
+                .unwrap_or(0);
+        }
+    }
+    width
+}
+
+/// Replace each tab in `text` (which must not contain a newline) with spaces up to the next
+/// multiple of `tab_width`, so that it's displayed the same way in every terminal.
+pub(crate) fn expand_tabs(text: &str, tab_width: usize) -> Cow<'_, str> {
+    if !text.contains('\t') {
+        return Cow::Borrowed(text);
+    }
+    let mut expanded = String::new();
+    let mut width = 0;
+    for (i, part) in text.split('\t').enumerate() {
+        if i > 0 {
+            let padding = tab_width - width % tab_width;
+            expanded.push_str(&" ".repeat(padding));
+            width += padding;
+        }
+        expanded.push_str(part);
+        width += display_width(part, tab_width);
+    }
+    Cow::Owned(expanded)
+}
@@ -0,0 +1,165 @@
+//! Many source files at once. See `SourceMap`.
+
+use crate::{OwnedParseError, ParseError, ParseTree, Parser, Position, Source, Span};
+use std::fmt;
+use std::sync::Arc;
+
+/// A collection of `Source`s, for a tool that parses many files. Each file is identified by a
+/// `FileId`, so a span in any of them can be stored as a `FileSpan`, and resolved back to its
+/// filename, line, and column (or rendered as an error) through the map.
+///
+/// The sources are kept in `Arc`s, so they can be shared with `OwnedParseTree`s (see
+/// `ParseTree::into_owned_with`).
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    sources: Vec<Arc<Source>>,
+}
+
+/// Identifies a file in a `SourceMap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId(usize);
+
+/// A span in a particular file of a `SourceMap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileSpan {
+    pub file: FileId,
+    pub span: Span,
+}
+
+impl FileId {
+    /// The index of this file in its `SourceMap`, in the order the files were added.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+impl FileSpan {
+    pub fn new(file: FileId, span: Span) -> FileSpan {
+        FileSpan { file, span }
+    }
+}
+
+impl SourceMap {
+    /// An empty source map.
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    /// Add a file, returning its id. (The source can be given as a `Source` or an `Arc<Source>`.)
+    pub fn add(&mut self, source: impl Into<Arc<Source>>) -> FileId {
+        self.sources.push(source.into());
+        FileId(self.sources.len() - 1)
+    }
+
+    /// The source of a file.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `file` is from a different `SourceMap`.
+    pub fn source(&self, file: FileId) -> &Arc<Source> {
+        &self.sources[file.0]
+    }
+
+    /// The filename of a file.
+    pub fn filename(&self, file: FileId) -> &str {
+        self.sources[file.0].filename()
+    }
+
+    /// The number of files.
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Iterate over the files, in the order they were added.
+    pub fn files(&self) -> impl ExactSizeIterator<Item = (FileId, &Arc<Source>)> {
+        self.sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| (FileId(i), source))
+    }
+
+    /// Look up a file by its filename. If there are several with that name, gives the first.
+    pub fn file_id(&self, filename: &str) -> Option<FileId> {
+        self.sources
+            .iter()
+            .position(|source| source.filename() == filename)
+            .map(FileId)
+    }
+
+    /// The id of `source`, if it's one of the sources in this map (the very same one, not a copy).
+    /// This finds which file a `ParseTree`'s spans are in.
+    pub fn file_of(&self, source: &Source) -> Option<FileId> {
+        self.sources
+            .iter()
+            .position(|s| std::ptr::eq(&**s, source))
+            .map(FileId)
+    }
+
+    /// Parse a file with `parser`.
+    pub fn parse<'m, 'p>(
+        &'m self,
+        parser: &'p Parser,
+        file: FileId,
+    ) -> Result<ParseTree<'m, 'p>, ParseError<'m>> {
+        parser.parse(self.source(file))
+    }
+
+    /// The source text covered by `span`.
+    pub fn substr(&self, span: FileSpan) -> &str {
+        self.source(span.file).substr(span.span)
+    }
+
+    /// Where `span` starts: its filename, line, and column, which display like
+    /// `filename:line:col` (counting from 1, as errors do).
+    pub fn location(&self, span: FileSpan) -> Location<'_> {
+        Location {
+            filename: self.filename(span.file),
+            position: self.source(span.file).position_at(span.span.start),
+        }
+    }
+
+    /// The global span of an error from parsing one of this map's files, or `None` if the error
+    /// came from a source that isn't in this map.
+    pub fn error_span(&self, error: &ParseError) -> Option<FileSpan> {
+        self.file_of(error.source)
+            .map(|file| FileSpan::new(file, error.span))
+    }
+
+    /// Create a custom error at `span`, which displays the lines of the file that `span` is in.
+    pub fn error(&self, span: FileSpan, short_message: &str, message: &str) -> ParseError<'_> {
+        ParseError::custom_error(self.source(span.file), short_message, message, span.span)
+    }
+
+    /// Like `.error()`, but an `OwnedParseError`, which doesn't borrow the map.
+    pub fn owned_error(
+        &self,
+        span: FileSpan,
+        short_message: &str,
+        message: &str,
+    ) -> OwnedParseError {
+        self.error(span, short_message, message).into_owned()
+    }
+}
+
+/// A filename, line, and column. See `SourceMap::location`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location<'a> {
+    pub filename: &'a str,
+    pub position: Position,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.filename,
+            self.position.line + 1,
+            self.position.col + 1
+        )
+    }
+}
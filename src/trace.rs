@@ -0,0 +1,72 @@
+use crate::lexer::RecordingTokenSource;
+use crate::{Lexeme, ParseError, ParseTree, Parser, Source, Span, TOKEN_BLANK, TOKEN_JUXTAPOSE};
+
+/// A record of the lexemes produced by each stage of parsing, for debugging a grammar or testing
+/// its intermediate results. See `Parser::parse_traced`.
+#[derive(Debug, Clone, Default)]
+pub struct Trace<'g> {
+    /// The lexemes produced by the lexer, named by token.
+    pub lexed: Vec<TraceEntry<'g>>,
+    /// The lexemes after resolving which operator each token belongs to, named by operator. This
+    /// includes the inserted `Blank` and `Juxtapose` lexemes.
+    pub resolved: Vec<TraceEntry<'g>>,
+    /// The resolved lexemes, reordered into reverse polish notation.
+    pub shunted: Vec<TraceEntry<'g>>,
+    /// The shunted lexemes, filtered to only the first token of each operator. There is one of
+    /// these per node in the parse tree, in post-order.
+    pub filtered: Vec<TraceEntry<'g>>,
+}
+
+/// One lexeme in a `Trace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry<'g> {
+    /// The name of the lexeme's token (for `Trace::lexed`), or of the operator it's part of (for
+    /// the later stages).
+    pub name: &'g str,
+    pub span: Span,
+    /// Whether the parser inserted this lexeme, as a `Blank` for a missing argument or a
+    /// `Juxtapose` for a missing operator, rather than it coming from the source.
+    pub inserted: bool,
+}
+
+impl Parser {
+    /// Parse `source` like `parse_recovering` does, while also recording the lexemes produced by
+    /// each stage of parsing. This is meant for debugging grammars, and for testing.
+    pub fn parse_traced<'s, 'g>(
+        &'g self,
+        source: &'s Source,
+    ) -> (ParseTree<'s, 'g>, Vec<ParseError<'s>>, Trace<'g>) {
+        let mut trace = Trace::default();
+        let mut lexemes = self.lexer.lex_modal(source.source());
+        let mut recorder = RecordingTokenSource::new(&mut lexemes);
+        let (resolved, mut errors) = self.resolve_recovering(source, &mut recorder);
+        let lexed = recorder.recorded;
+        trace.lexed = lexed
+            .iter()
+            .map(|lexed| TraceEntry {
+                name: &self.tok_to_name[lexed.lexeme.token],
+                span: lexed.lexeme.span,
+                inserted: false,
+            })
+            .collect();
+        trace.resolved = resolved.iter().map(|lex| self.trace_optok(*lex)).collect();
+        let comments = lexemes.into_comments();
+        let tree = self.build_tree(
+            source,
+            resolved,
+            comments,
+            None,
+            &mut errors,
+            Some(&mut trace),
+        );
+        (tree, errors, trace)
+    }
+
+    pub(crate) fn trace_optok(&self, lex: Lexeme) -> TraceEntry<'_> {
+        TraceEntry {
+            name: &self.optok_to_name[lex.token],
+            span: lex.span,
+            inserted: lex.token == TOKEN_BLANK || lex.token == TOKEN_JUXTAPOSE,
+        }
+    }
+}
@@ -11,8 +11,9 @@ pub trait Arity: Debug + Copy {
     fn arity(&self) -> usize;
 }
 
+/// One node in a tree, without a reference to its forest. See `Forest::visit`.
 #[derive(Debug, Clone, Copy)]
-struct Node<I: Arity> {
+pub struct Node<I: Arity> {
     item: I,
     first_child_ptr: usize,
 }
@@ -24,6 +25,13 @@ pub struct Forest<I: Arity> {
     nodes: Vec<Node<I>>,
 }
 
+/// The state of a forest at some point while it was being built. See `Forest::checkpoint`.
+#[derive(Debug, Clone)]
+pub struct Checkpoint<I: Arity> {
+    roots: Vec<Node<I>>,
+    num_nodes: usize,
+}
+
 /// One node in a tree.
 #[derive(Debug, Clone, Copy)]
 pub struct Visitor<'f, I: Arity> {
@@ -43,6 +51,11 @@ impl<'f, I: Arity> Visitor<'f, I> {
         &self.node.item
     }
 
+    /// This node, without the reference to its forest.
+    pub fn node(&self) -> Node<I> {
+        self.node
+    }
+
     /// The number of children this node has.
     pub fn num_children(&self) -> usize {
         self.node.item.arity()
@@ -107,13 +120,41 @@ impl<I: Arity> Forest<I> {
         self.roots.push(node);
     }
 
+    /// Remove the last tree, returning the item at its root. Its descendants are left in storage,
+    /// unreachable.
+    pub fn pop(&mut self) -> Option<I> {
+        self.roots.pop().map(|node| node.item)
+    }
+
     /// The number of top-level nodes.
     pub fn num_trees(&self) -> usize {
         self.roots.len()
     }
 
+    /// Record the current state of the forest. O(`self.num_trees()`).
+    pub fn checkpoint(&self) -> Checkpoint<I> {
+        Checkpoint {
+            roots: self.roots.clone(),
+            num_nodes: self.nodes.len(),
+        }
+    }
+
+    /// Copy the forest as it was at `checkpoint`, which must have been taken from this forest (or
+    /// one that was built the same way up to that point). Takes time linear in its size then.
+    pub fn at_checkpoint(&self, checkpoint: &Checkpoint<I>) -> Forest<I> {
+        Forest {
+            roots: checkpoint.roots.clone(),
+            nodes: self.nodes[..checkpoint.num_nodes].to_vec(),
+        }
+    }
+
+    /// Visit a node of this forest.
+    pub fn visit(&self, node: Node<I>) -> Visitor<'_, I> {
+        Visitor { forest: self, node }
+    }
+
     /// Get the `n`th tree.
-    pub fn tree(&self, n: usize) -> Option<Visitor<I>> {
+    pub fn tree(&self, n: usize) -> Option<Visitor<'_, I>> {
         if n <= self.roots.len() {
             Some(Visitor {
                 forest: self,
@@ -170,4 +211,14 @@ fn test_tree_visitor() {
         &show_forest(&forest),
         "(3 (3 (1 (1 0)) 0 (1 0)) (2 0 (1 0)) 0)"
     );
+
+    let mut forest = Forest::from_iter([0, 1, 0].iter().copied());
+    let checkpoint = forest.checkpoint();
+    forest.push(0);
+    forest.push(2);
+    assert_eq!(&show_forest(&forest), "(1 0) (2 0 0)");
+    let mut old_forest = forest.at_checkpoint(&checkpoint);
+    assert_eq!(&show_forest(&old_forest), "(1 0) 0");
+    old_forest.push(2);
+    assert_eq!(&show_forest(&old_forest), "(2 (1 0) 0)");
 }
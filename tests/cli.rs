@@ -0,0 +1,81 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const GRAMMAR: &str = r#"
+regex num "[0-9]+"
+op parens "(" ")"
+left_assoc
+op times _ "*" _
+left_assoc
+op plus _ "+" _
+"#;
+
+/// Run the `panfix` binary on `input`, returning its exit code, stdout, and stderr.
+fn run_panfix(command: &str, input: &str) -> (i32, String, String) {
+    let grammar_path = std::env::temp_dir().join(format!(
+        "panfix-cli-{}-{}.panfix",
+        command,
+        std::process::id()
+    ));
+    std::fs::write(&grammar_path, GRAMMAR).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_panfix"))
+        .arg(command)
+        .arg(&grammar_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&grammar_path).unwrap();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn test_cli() {
+    assert_eq!(
+        run_panfix("sexpr", "1 + 2 * 3"),
+        (0, "(plus 1 (times 2 3))\n".to_owned(), String::new())
+    );
+    assert_eq!(
+        run_panfix("outline", "(1)").1,
+        "parens 0:0-0:3\n  num 0:1-0:2 \"1\"\n"
+    );
+    assert_eq!(
+        run_panfix("json", "7").1,
+        "{\"name\": \"num\", \"span\": {\"start\": {\"line\": 0, \"col\": 0}, \
+         \"end\": {\"line\": 0, \"col\": 1}}, \"text\": \"7\"}\n"
+    );
+    assert_eq!(
+        run_panfix("shunt", "1+2").1,
+        "0:0-0:1         num         \"1\"\n\
+         0:2-0:3         num         \"2\"\n\
+         0:1-0:2         plus        \"+\"\n"
+    );
+
+    assert_eq!(
+        run_panfix("filter", "1 2").1,
+        "0:0-0:1         num         \"1\"\n\
+         0:2-0:3         num         \"2\"\n\
+         0:1-0:1         Juxtapose   \"\" (inserted)\n"
+    );
+
+    let (code, stdout, stderr) = run_panfix("sexpr", "(1");
+    assert_eq!(code, 1);
+    assert_eq!(stdout, "");
+    assert!(stderr.contains("expected ')' but found end of file"));
+
+    let (code, _, stderr) = run_panfix("frobnicate", "");
+    assert_eq!(code, 2);
+    assert!(stderr.starts_with("Usage: panfix"));
+}
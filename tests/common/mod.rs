@@ -0,0 +1,66 @@
+//! Fixtures shared by the tests of the ways to parse that must give exactly the same result as
+//! `Parser::parse`.
+
+use panfix::{pattern, Grammar, GrammarError, ParseTree, Parser, Visitor};
+
+/// A small expression grammar with comments, whose strings match `str_regex`.
+pub fn make_parser(str_regex: &str) -> Result<Parser, GrammarError> {
+    let mut grammar = Grammar::new_with_unicode_whitespace()?;
+    grammar.line_comment("//");
+    grammar.block_comment("/*", "*/", true);
+    grammar.regex("var", "[a-z]+")?;
+    grammar.regex("num", "-?[0-9]+")?;
+    grammar.operand_only("num")?;
+    grammar.regex("str", str_regex)?;
+    grammar.op("parens", pattern!("(" ")"))?;
+    grammar.op("array", pattern!("[" "]"))?;
+    grammar.op("if", pattern!("if" "then" "else"))?;
+    grammar.right_assoc();
+    grammar.list_op("comma", ",")?;
+    grammar.left_assoc();
+    grammar.op("plus", pattern!(_ "+" _))?;
+    grammar.op("minus", pattern!(_ "-" _))?;
+    grammar.left_assoc();
+    grammar.op("times", pattern!(_ "*" _))?;
+    grammar.op("neg", pattern!("-" _))?;
+    grammar.finish()
+}
+
+/// Regexes for strings that can't, and can, span lines.
+pub const SINGLE_LINE_STR: &str = r#""[^"\n]*""#;
+pub const MULTI_LINE_STR: &str = r#""[^"]*""#;
+
+/// Show a tree with the span of every node, so that trees are only equal if their spans are.
+pub fn show_tree(visitor: Visitor) -> String {
+    let mut string = format!("({} {}", visitor.name(), visitor.span());
+    for span in visitor.token_spans() {
+        string.push_str(&format!(" {}", span));
+    }
+    for child in visitor.children_iter() {
+        string.push(' ');
+        string.push_str(&show_tree(child));
+    }
+    string.push(')');
+    string
+}
+
+pub fn show_parse(tree: &ParseTree, errors: &[panfix::ParseError]) -> String {
+    let mut string = show_tree(tree.visitor());
+    string.push_str(&format!("\ncomments: {:?}", tree.comments()));
+    for error in errors {
+        string.push_str(&format!("\n{}", error.display_with_color_override(false)));
+    }
+    string
+}
+
+/// A tiny deterministic random number generator (xorshift), so that the test is repeatable.
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
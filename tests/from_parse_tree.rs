@@ -0,0 +1,190 @@
+use panfix::{pattern, FromParseTree, Grammar, GrammarError, Parser, Source};
+use std::str::FromStr;
+
+fn make_json_parser() -> Result<Parser, GrammarError> {
+    let mut grammar = Grammar::new_with_unicode_whitespace()?;
+    grammar.regex("String", r#""[^"]*""#)?;
+    grammar.regex("Number", r#"-?[0-9.]+"#)?;
+    grammar.regex("Ident", "[a-z]+")?;
+    grammar.string("Null", "null")?;
+    grammar.string("True", "true")?;
+    grammar.string("False", "false")?;
+    grammar.op("Array", pattern!("[" "]"))?;
+    grammar.op("Object", pattern!("{" "}"))?;
+    grammar.op("Set", pattern!("set(" ")"))?;
+    grammar.right_assoc();
+    grammar.op("Keyval", pattern!(_ ":" _))?;
+    grammar.right_assoc();
+    grammar.list_op("Elems", ",")?;
+    grammar.finish()
+}
+
+#[derive(Debug, PartialEq, FromParseTree)]
+#[panfix(expected = "a JSON value")]
+enum Json<'s> {
+    Null,
+    True,
+    False,
+    String(&'s str),
+    Number(f64),
+    Ident(#[panfix(from_str)] Ident),
+    Array(Vec<Json<'s>>),
+    Object(Vec<Keyval<'s>>),
+    #[panfix(name = "Set")]
+    SetOf(Option<Box<Json<'s>>>),
+}
+
+#[derive(Debug, PartialEq, FromParseTree)]
+struct Keyval<'s> {
+    key: &'s str,
+    value: Json<'s>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Ident(String);
+
+impl FromStr for Ident {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Ident, String> {
+        if s.len() <= 3 {
+            Ok(Ident(s.to_owned()))
+        } else {
+            Err(format!("'{}' is too long", s))
+        }
+    }
+}
+
+#[track_caller]
+fn convert<'s, T: FromParseTree<'s>>(parser: &Parser, source: &'s Source) -> T {
+    let tree = parser.parse(source).unwrap();
+    T::from_parse_tree(tree.visitor()).unwrap()
+}
+
+#[track_caller]
+fn assert_convert_error<'s, T: FromParseTree<'s> + std::fmt::Debug>(
+    parser: &Parser,
+    source: &'s Source,
+    expected: &str,
+) {
+    let tree = parser.parse(source).unwrap();
+    let err = T::from_parse_tree(tree.visitor()).unwrap_err();
+    assert_eq!(
+        format!("{}", err.display_with_color_override(false)),
+        expected
+    );
+}
+
+#[test]
+fn test_derive_from_parse_tree() {
+    let parser = make_json_parser().unwrap();
+
+    let source = Source::new(
+        "testcase",
+        r#"{"a": [1, -2.5, null, abc], "b": true, "c": []}"#.to_owned(),
+    );
+    let json = convert::<Json>(&parser, &source);
+    assert_eq!(
+        json,
+        Json::Object(vec![
+            Keyval {
+                key: r#""a""#,
+                value: Json::Array(vec![
+                    Json::Number(1.0),
+                    Json::Number(-2.5),
+                    Json::Null,
+                    Json::Ident(Ident("abc".to_owned())),
+                ]),
+            },
+            Keyval {
+                key: r#""b""#,
+                value: Json::True,
+            },
+            Keyval {
+                key: r#""c""#,
+                value: Json::Array(vec![]),
+            },
+        ])
+    );
+
+    let source = Source::new("testcase", "[set(), set(false), [7]]".to_owned());
+    assert_eq!(
+        convert::<Json>(&parser, &source),
+        Json::Array(vec![
+            Json::SetOf(None),
+            Json::SetOf(Some(Box::new(Json::False))),
+            Json::Array(vec![Json::Number(7.0)]),
+        ])
+    );
+
+    let source = Source::new("testcase", "12".to_owned());
+    assert_eq!(convert::<f64>(&parser, &source), 12.0);
+    assert_eq!(convert::<&str>(&parser, &source), "12");
+    assert_eq!(convert::<Vec<u8>>(&parser, &source), vec![12]);
+}
+
+#[test]
+fn test_derive_from_parse_tree_errors() {
+    let parser = make_json_parser().unwrap();
+
+    assert_convert_error::<Json>(
+        &parser,
+        &Source::new("testcase", "[1, , 2]".to_owned()),
+        r#"Parse Error: Expected a JSON value, but found nothing.
+ --> testcase:1:4
+  |
+1 |[1, , 2]
+  |   ^ missing a JSON value
+"#,
+    );
+    assert_convert_error::<Json>(
+        &parser,
+        &Source::new("testcase", "[1 2]".to_owned()),
+        r#"Parse Error: Found two expressions next to each other, with nothing to join them.
+ --> testcase:1:2
+  |
+1 |[1 2]
+  | ^^^ missing operator
+"#,
+    );
+    assert_convert_error::<Json>(
+        &parser,
+        &Source::new("testcase", "{1}".to_owned()),
+        r#"Parse Error: Expected Keyval, but found Number.
+ --> testcase:1:2
+  |
+1 |{1}
+  | ^ unexpected Number
+"#,
+    );
+    assert_convert_error::<Json>(
+        &parser,
+        &Source::new("testcase", "1.2.3".to_owned()),
+        r#"Parse Error: Invalid f64: invalid float literal
+ --> testcase:1:1
+  |
+1 |1.2.3
+  |^^^^^ invalid f64
+"#,
+    );
+    assert_convert_error::<Json>(
+        &parser,
+        &Source::new("testcase", "abcd".to_owned()),
+        r#"Parse Error: Invalid Ident: 'abcd' is too long
+ --> testcase:1:1
+  |
+1 |abcd
+  |^^^^ invalid Ident
+"#,
+    );
+    assert_convert_error::<Keyval>(
+        &parser,
+        &Source::new("testcase", r#""a": "b": 1"#.to_owned()),
+        r#"Parse Error: Expected a JSON value, but found Keyval.
+ --> testcase:1:6
+  |
+1 |"a": "b": 1
+  |     ^^^^^^ unexpected Keyval
+"#,
+    );
+}
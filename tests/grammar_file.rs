@@ -0,0 +1,221 @@
+use panfix::{pattern, Grammar, GrammarError, GrammarFileError, Parser, Source};
+
+const JSON_GRAMMAR: &str = r#"
+# A grammar for JSON
+whitespace "[ \n\r\t]+"
+regex String '"([^\\"]|(\\.))*"'
+regex Number "-?(?:0|[1-9]\d*)(?:\.\d+)?(?:[eE][+-]?\d+)?"
+string Null "null"
+string True "true"
+string False "false"
+op Array "[" "]"
+op Object "{" "}"
+right_assoc
+op Keyval _ ":" _
+right_assoc
+op Comma _ "," _
+"#;
+
+fn make_json_parser() -> Result<Parser, GrammarError> {
+    let mut grammar = Grammar::new("[ \n\r\t]+")?;
+    grammar.regex("String", r#""([^\\"]|(\\.))*""#)?;
+    grammar.regex("Number", r#"-?(?:0|[1-9]\d*)(?:\.\d+)?(?:[eE][+-]?\d+)?"#)?;
+    grammar.string("Null", "null")?;
+    grammar.string("True", "true")?;
+    grammar.string("False", "false")?;
+    grammar.op("Array", pattern!("[" "]"))?;
+    grammar.op("Object", pattern!("{" "}"))?;
+    grammar.right_assoc();
+    grammar.op("Keyval", pattern!(_ ":" _))?;
+    grammar.right_assoc();
+    grammar.op("Comma", pattern!(_ "," _))?;
+    grammar.finish()
+}
+
+#[track_caller]
+fn assert_same_parse(file_parser: &Parser, rust_parser: &Parser, src: &str) {
+    let source = Source::new("testcase", src.to_owned());
+    let file_tree = file_parser.parse(&source).unwrap();
+    let rust_tree = rust_parser.parse(&source).unwrap();
+    assert_eq!(
+        format!("{}", file_tree.visitor()),
+        format!("{}", rust_tree.visitor())
+    );
+}
+
+#[track_caller]
+fn assert_file_error(text: &str, expected: &str) {
+    let err = text.parse::<Grammar>().unwrap_err();
+    assert_eq!(format!("{}", err), expected);
+}
+
+#[test]
+fn test_grammar_file_json() {
+    let file_parser = JSON_GRAMMAR.parse::<Grammar>().unwrap().finish().unwrap();
+    let rust_parser = make_json_parser().unwrap();
+    for src in [
+        r#"{"a": [1, 2.5e3, "x\"y"], "b": null}"#,
+        "[true, false, {}]",
+        "-12",
+    ] {
+        assert_same_parse(&file_parser, &rust_parser, src);
+    }
+
+    let source = Source::new("testcase", r#"["x\"y"]"#.to_owned());
+    let tree = file_parser.parse(&source).unwrap();
+    assert_eq!(format!("{}", tree.visitor()), r#"(Array "x\"y")"#);
+}
+
+#[test]
+fn test_grammar_file_features() {
+    let text = r#"
+        line_comment "//"
+        block_comment "/*" "*/" nested
+        regex num "[0-9]+"
+        regex name "[a-z]+"
+        string hole "?"
+        operand_only num
+        juxtapose
+        op group "(" ")"
+        op call _ "(" fn_args ")"
+        op let "let" "in" _
+        left_assoc
+        op plus _ "+" _
+        op neg "-" _
+        left_assoc
+        list_op seq ";"
+        subgrammar fn_args
+        regex name "[a-z]+"
+        op sep _ "," _
+    "#;
+    let parser = text.parse::<Grammar>().unwrap().finish().unwrap();
+    let source = Source::new(
+        "testcase",
+        "f(x, y) + /* /* */ */ let 1 in 2; 3 // done".to_owned(),
+    );
+    let tree = parser.parse(&source).unwrap();
+    assert_eq!(
+        format!("{}", tree.visitor()),
+        "(seq (plus (call f (sep x y)) (let 1 2)) 3)"
+    );
+    assert_eq!(tree.comments().len(), 2);
+}
+
+#[test]
+fn test_grammar_file_errors() {
+    assert_file_error(
+        "regex num \"[0-9]+\"\n\nfrobnicate",
+        "Grammar file syntax error on line 3. Unknown statement 'frobnicate'.",
+    );
+    assert_file_error(
+        "string plus \"+\n",
+        "Grammar file syntax error on line 1. Unterminated string.",
+    );
+    assert_file_error(
+        "# comment\nregex num",
+        "Grammar file syntax error on line 2. Expected 1 quoted string.",
+    );
+    assert_file_error(
+        "op plus _ \"+\" _\n  whitespace \" \"",
+        "Grammar file syntax error on line 2. 'whitespace' must be the first statement.",
+    );
+    assert_file_error(
+        "op bad \"(\" sub",
+        "Grammar file syntax error on line 1. The sub-grammar 'sub' must be between two tokens.",
+    );
+
+    let err = "\n\nregex num \"[0-9\"".parse::<Grammar>().unwrap_err();
+    assert!(matches!(
+        err,
+        GrammarFileError::GrammarError {
+            line: 3,
+            error: GrammarError::RegexError(_)
+        }
+    ));
+    let err = "left_assoc\nop neg \"-\" _\nop minus \"-\" _"
+        .parse::<Grammar>()
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        GrammarFileError::GrammarError {
+            line: 3,
+            error: GrammarError::PrefixyConflict(_)
+        }
+    ));
+}
+
+#[test]
+fn test_grammar_file_load() {
+    let path = std::env::temp_dir().join(format!("panfix-test-{}.panfix", std::process::id()));
+    std::fs::write(&path, JSON_GRAMMAR).unwrap();
+    let grammar = Grammar::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    let file_parser = grammar.unwrap().finish().unwrap();
+    assert_same_parse(&file_parser, &make_json_parser().unwrap(), "[1, {}]");
+
+    let err = Grammar::load(&path).unwrap_err();
+    assert!(matches!(err, GrammarFileError::IoError(..)));
+}
+
+#[test]
+fn test_grammar_file_groups() {
+    let text = r#"
+        regex var "[a-z]+"
+        group Sum left
+        op plus _ "+" _
+        group Comparison non_assoc
+        op lt _ "<" _
+        group Product left
+        op times _ "*" _
+        tighter_than Product Sum
+        tighter_than Sum Comparison
+    "#;
+    let parser = text.parse::<Grammar>().unwrap().finish().unwrap();
+    let source = Source::new("testcase", "a * b + c < d".to_owned());
+    let tree = parser.parse(&source).unwrap();
+    assert_eq!(format!("{}", tree.visitor()), "(lt (plus (times a b) c) d)");
+    let source = Source::new("testcase", "a < b < c".to_owned());
+    assert!(parser.parse(&source).is_err());
+
+    assert_file_error(
+        "group Sum sideways",
+        "Grammar file syntax error on line 1. Unknown associativity 'sideways'.",
+    );
+}
+
+#[test]
+fn test_grammar_file_pattern_groups() {
+    let text = r#"
+        regex var "[a-z]+"
+        op if "if" "{" "}" ("elif" "{" "}")* ( "else" "{" "}" )?
+    "#;
+    let file_parser = text.parse::<Grammar>().unwrap().finish().unwrap();
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    grammar.regex("var", "[a-z]+").unwrap();
+    grammar
+        .op(
+            "if",
+            pattern!("if" "{" "}" ("elif" "{" "}")* ("else" "{" "}")?),
+        )
+        .unwrap();
+    let rust_parser = grammar.finish().unwrap();
+    for src in [
+        "if a { b }",
+        "if a { b } elif c { d } elif e { f } else { g }",
+    ] {
+        assert_same_parse(&file_parser, &rust_parser, src);
+    }
+
+    assert_file_error(
+        "op bad (\"a\")?",
+        "Grammar file syntax error on line 1. A pattern can't start with a group.",
+    );
+    assert_file_error(
+        "op bad \"a\" (\"b\" (\"c\")?)*",
+        "Grammar file syntax error on line 1. Groups can't be nested.",
+    );
+    assert_file_error(
+        "op bad \"a\" (\"b\"",
+        "Grammar file syntax error on line 1. A group is missing its ')*' or ')?'.",
+    );
+}
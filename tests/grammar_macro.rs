@@ -0,0 +1,209 @@
+use panfix::{grammar, pattern, Assoc, Grammar, GrammarError, Parser, Source};
+
+grammar! {
+    fn macro_json_parser() {
+        whitespace "[ \n\r\t]+";
+        regex String r#""([^\\"]|(\\.))*""#;
+        regex Number r"-?(?:0|[1-9]\d*)(?:\.\d+)?(?:[eE][+-]?\d+)?";
+        string Null "null";
+        string True "true";
+        string False "false";
+        op Array "[" "]";
+        op Object "{" "}";
+        right_assoc;
+        op Keyval _ ":" _;
+        right_assoc;
+        list_op Elems ",";
+    }
+}
+
+fn make_json_parser() -> Result<Parser, GrammarError> {
+    let mut grammar = Grammar::new("[ \n\r\t]+")?;
+    grammar.regex("String", r#""([^\\"]|(\\.))*""#)?;
+    grammar.regex("Number", r#"-?(?:0|[1-9]\d*)(?:\.\d+)?(?:[eE][+-]?\d+)?"#)?;
+    grammar.string("Null", "null")?;
+    grammar.string("True", "true")?;
+    grammar.string("False", "false")?;
+    grammar.op("Array", pattern!("[" "]"))?;
+    grammar.op("Object", pattern!("{" "}"))?;
+    grammar.right_assoc();
+    grammar.op("Keyval", pattern!(_ ":" _))?;
+    grammar.right_assoc();
+    grammar.list_op("Elems", ",")?;
+    grammar.finish()
+}
+
+grammar! {
+    /// A little language that uses the rest of the statements.
+    pub(crate) fn macro_lang_parser() {
+        line_comment "//";
+        block_comment "/*" "*/" nested;
+        regex Var "[a-z]+";
+        regex Num "-?[0-9]+";
+        operand_only Num;
+        subgrammar Param;
+        regex "Name" "[A-Z][a-z]*";
+        op Dot _ "." _;
+        main_subgrammar;
+        op Func "fn" Param "(" ")" "{" "}";
+        op If "if" "{" "}" ("elif" "{" "}")* ("else" "{" "}")?;
+        op Block ":" INDENT DEDENT;
+        group Product left;
+        op Times _ "*" _;
+        group Sum left;
+        op Plus _ "+" _;
+        op Minus _ "-" _;
+        tighter_than Product Sum;
+        group Compare non_assoc;
+        tighter_than Sum Compare;
+        op Less _ "<" _;
+        juxtapose;
+    }
+}
+
+fn make_lang_parser() -> Result<Parser, GrammarError> {
+    let mut grammar = Grammar::new_with_unicode_whitespace()?;
+    grammar.line_comment("//");
+    grammar.block_comment("/*", "*/", true);
+    grammar.regex("Var", "[a-z]+")?;
+    grammar.regex("Num", "-?[0-9]+")?;
+    grammar.operand_only("Num")?;
+    grammar.subgrammar("Param");
+    grammar.regex("Name", "[A-Z][a-z]*")?;
+    grammar.op("Dot", pattern!(_ "." _))?;
+    grammar.main_subgrammar();
+    grammar.op("Func", pattern!("fn" Param "(" ")" "{" "}"))?;
+    grammar.op(
+        "If",
+        pattern!("if" "{" "}" ("elif" "{" "}")* ("else" "{" "}")?),
+    )?;
+    grammar.op("Block", pattern!(":" INDENT DEDENT))?;
+    grammar.group("Product", Assoc::Left)?;
+    grammar.op("Times", pattern!(_ "*" _))?;
+    grammar.group("Sum", Assoc::Left)?;
+    grammar.op("Plus", pattern!(_ "+" _))?;
+    grammar.op("Minus", pattern!(_ "-" _))?;
+    grammar.tighter_than("Product", "Sum");
+    grammar.group("Compare", Assoc::NonAssoc)?;
+    grammar.tighter_than("Sum", "Compare");
+    grammar.op("Less", pattern!(_ "<" _))?;
+    grammar.juxtapose()?;
+    grammar.finish()
+}
+
+#[track_caller]
+fn assert_same_parse(macro_parser: &Parser, rust_parser: &Parser, src: &str) {
+    let source = Source::new("testcase", src.to_owned());
+    let macro_tree = macro_parser.parse_recovering(&source);
+    let rust_tree = rust_parser.parse_recovering(&source);
+    assert_eq!(
+        format!("{}", macro_tree.0.visitor()),
+        format!("{}", rust_tree.0.visitor())
+    );
+    assert_eq!(macro_tree.1.len(), rust_tree.1.len());
+}
+
+#[test]
+fn test_grammar_macro_json() {
+    let macro_parser = macro_json_parser();
+    let rust_parser = make_json_parser().unwrap();
+    for src in [
+        r#"{"a": [1, 2.5e3, "x\"y"], "b": null}"#,
+        "[true, false, {}]",
+        "[1, 2,]",
+        "-12",
+        "[1 2",
+    ] {
+        assert_same_parse(&macro_parser, &rust_parser, src);
+    }
+}
+
+#[test]
+fn test_grammar_macro_statements() {
+    let macro_parser = macro_lang_parser();
+    let rust_parser = make_lang_parser().unwrap();
+    for src in [
+        "fn Foo.Bar(a) { a * 2 + x -1 }",
+        "if { a } { b } elif { c } { d } else { e } { f }",
+        "a + /* b /* c */ */ d // e",
+        "a < b < c",
+        "f x * 2",
+        ":\n    a\n    b\nc",
+    ] {
+        assert_same_parse(&macro_parser, &rust_parser, src);
+    }
+}
+
+// The `compile_fail` examples in the docs for `grammar!`, with their mistakes fixed, to check that
+// those mistakes are the only reason they don't compile.
+grammar! { fn bad_regex() { regex Number "[0-9]"; } }
+grammar! { fn bad_whitespace_regex() { whitespace "[ \n]"; } }
+grammar! {
+    fn late_whitespace() {
+        whitespace "[ \n]+";
+        regex Number "[0-9]+";
+    }
+}
+grammar! {
+    fn prefixy_conflict() {
+        op Neg "-" _;
+        op Pos "+" _;
+    }
+}
+grammar! {
+    fn suffixy_conflict() {
+        op Minus _ "-" _;
+        op Decr _ "--";
+    }
+}
+grammar! {
+    fn unknown_token() {
+        regex Number "[0-9]+";
+        operand_only Number;
+    }
+}
+grammar! {
+    fn undefined_subgrammar() {
+        op Func "fn" Param "(" ")";
+        subgrammar Param;
+        regex Name "[a-z]+";
+    }
+}
+grammar! {
+    fn duplicate_group() {
+        group Sum left;
+        group Product right;
+    }
+}
+grammar! {
+    fn undefined_group() {
+        group Sum left;
+        group Product left;
+        tighter_than Product Sum;
+    }
+}
+grammar! {
+    fn precedence_cycle() {
+        group Sum left;
+        group Product left;
+        tighter_than Product Sum;
+    }
+}
+
+#[test]
+fn test_grammar_macro_fixed_mistakes() {
+    // Each of these panics if its grammar has an error
+    let parsers = [
+        bad_regex(),
+        bad_whitespace_regex(),
+        late_whitespace(),
+        prefixy_conflict(),
+        suffixy_conflict(),
+        unknown_token(),
+        undefined_subgrammar(),
+        duplicate_group(),
+        undefined_group(),
+        precedence_cycle(),
+    ];
+    assert_eq!(parsers.len(), 10);
+}
@@ -1,10 +1,8 @@
-use panfix::implementation::lexer::LexerBuilder;
-use panfix::{Lexeme, Span, Token};
+use panfix::implementation::lexer::{LexerBuilder, TokenMode, TokenSource};
+use panfix::{Lexeme, Source, Span, Token};
 
 fn get_span(src: &str, span: Span) -> &str {
-    // Assuming that no lexeme spans multiple lines!
-    let line = &src.lines().nth(span.start.line as usize).unwrap();
-    &line[span.start.col as usize..span.end.col as usize]
+    &src[span.start..span.end]
 }
 
 #[track_caller]
@@ -18,8 +16,9 @@ fn assert_lexeme(
         .next()
         .expect("Token stream in test case ended early");
     assert_eq!(lex.token, token);
-    let start = lex.span.start;
-    let end = lex.span.end;
+    let source = Source::new("testcase", src.to_owned());
+    let start = source.position_at(lex.span.start);
+    let end = source.position_at(lex.span.end);
     let lexeme = get_span(src, lex.span);
     let actual = format!(
         "{}:{}({})-{}:{}({}) {}",
@@ -125,3 +124,28 @@ fn test_lexing_horrible_things() {
     assert_eq!(lex("tr\nue"), vec!["tr", "ue"]);
     assert_eq!(lex("tr%ue%% %%true"), vec!["tr", "%ue%%", "%%true"]);
 }
+
+#[test]
+fn test_lexing_modal() {
+    let mut builder = LexerBuilder::new(" *").unwrap();
+    let tok_var = builder.regex("[a-z]+").unwrap();
+    let tok_num = builder.regex("-?[0-9]+").unwrap();
+    let tok_minus = builder.string("-").unwrap();
+    builder.set_mode(tok_num, TokenMode::OperandOnly);
+    let lexer = builder.finish().unwrap();
+
+    let src = "x -1";
+    let lexemes = &mut lexer.lex(src);
+    assert_lexeme(src, lexemes, "0:0(0)-0:1(1) x", tok_var);
+    assert_lexeme(src, lexemes, "0:2(2)-0:4(4) -1", tok_num);
+
+    let lexemes = &mut lexer.lex_modal(src);
+    assert_eq!(lexemes.next_lexeme(true).unwrap().token, tok_var);
+    assert_eq!(lexemes.next_lexeme(false).unwrap().token, tok_minus);
+    assert_eq!(lexemes.next_lexeme(true).unwrap().token, tok_num);
+    assert_eq!(lexemes.next_lexeme(true), None);
+
+    // If nothing allowed matches, anything goes
+    let lexemes = &mut lexer.lex_modal("1");
+    assert_eq!(lexemes.next_lexeme(false).unwrap().token, tok_num);
+}
@@ -6,6 +6,7 @@ fn make_parser() -> Result<Parser, GrammarError> {
     let mut grammar = Grammar::new_with_unicode_whitespace()?;
     grammar.regex("id", "[a-zA-Z]+")?;
     grammar.regex("num", "[+-]?[0-9]+")?;
+    grammar.operand_only("num")?;
     grammar.op("block", pattern!("{" "}"))?;
     grammar.op("group", pattern!("(" ")"))?;
     grammar.left_assoc();
@@ -259,6 +260,16 @@ fn test_parsing_parens() {
     assert_eq!(parse_to_string(&parser, "f(x)(y)"), "(call (call f x) y)");
 }
 
+#[test]
+fn test_parsing_signed_numbers() {
+    let parser = make_parser().unwrap();
+
+    assert_eq!(parse_to_string(&parser, "x +1"), "(+ x 1)");
+    assert_eq!(parse_to_string(&parser, "x + +1"), "(+ x 1)");
+    assert_eq!(parse_to_string(&parser, "x < -1"), "(< x -1)");
+    assert_eq!(parse_to_string(&parser, "f(x +2)"), "(call f (+ x 2))");
+}
+
 #[test]
 fn test_parsing_if() {
     let parser = make_parser().unwrap();
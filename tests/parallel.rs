@@ -0,0 +1,144 @@
+mod common;
+
+use common::{make_parser, show_parse, Rng, MULTI_LINE_STR, SINGLE_LINE_STR};
+use panfix::{pattern, Grammar, Parser, Source};
+
+#[track_caller]
+fn assert_same_parse(parser: &Parser, src: &str, threads: usize) {
+    let source = Source::new("testcase", src.to_owned());
+    let (tree, errors) = parser.parse_parallel_recovering(&source, threads);
+    let (fresh, fresh_errors) = parser.parse_recovering(&source);
+    assert_eq!(
+        show_parse(&tree, &errors),
+        show_parse(&fresh, &fresh_errors),
+        "with {} threads, parsing {:?}",
+        threads,
+        src
+    );
+    match (
+        parser.parse_parallel(&source, threads),
+        parser.parse(&source),
+    ) {
+        (Ok(tree), Ok(fresh)) => assert_eq!(show_parse(&tree, &[]), show_parse(&fresh, &[])),
+        (Err(error), Err(fresh_error)) => {
+            assert_eq!(error.span, fresh_error.span);
+            assert_eq!(error.message, fresh_error.message);
+        }
+        (tree, fresh) => panic!(
+            "parse_parallel gave {:?} but parse gave {:?}, parsing {:?}",
+            tree.is_ok(),
+            fresh.is_ok(),
+            src
+        ),
+    }
+}
+
+#[test]
+fn test_parse_parallel() {
+    let parser = make_parser(MULTI_LINE_STR).unwrap();
+    let src = "f(1, 2)\n// two\ng * -3 + x\n[a, b,\n c]\n";
+    let source = Source::new("testcase", src.to_owned());
+    let tree = parser.parse_parallel(&source, 4).unwrap();
+    let fresh = parser.parse(&source).unwrap();
+    assert_eq!(show_parse(&tree, &[]), show_parse(&fresh, &[]));
+
+    // Tokens and comments that span the chunks
+    assert_same_parse(&parser, "[\"a\nb\nc\nd\", x,\ny]", 3);
+    assert_same_parse(&parser, "a + /* b\n\"c\n*/ \"\nd\n\"\n+ e", 4);
+    assert_same_parse(&parser, "a\n\"\n", 2);
+    assert_same_parse(&parser, "", 4);
+    assert_same_parse(&parser, "\n\n\n", 4);
+}
+
+/// Differential test: parse many random sources, and check that parsing in parallel gives exactly
+/// the same result as parsing on one thread.
+#[test]
+fn test_parse_parallel_matches_parse() {
+    // Strings that can span lines, and strings that can't
+    check_random_sources(&make_parser(MULTI_LINE_STR).unwrap());
+    check_random_sources(&make_parser(SINGLE_LINE_STR).unwrap());
+}
+
+fn check_random_sources(parser: &Parser) {
+    let snippets = [
+        "x", "ab", "1", "-", "-2", " ", "\n", "\n", "\n", "(", ")", "[", "]", ",", "+", "*", "if",
+        "then", "else", "\"s\"", "\"", "//", "/*", "*/", "é", "\"a\nb\"",
+    ];
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..1000 {
+        let mut src = String::new();
+        for _ in 0..rng.below(60) {
+            src.push_str(snippets[rng.below(snippets.len())]);
+        }
+        assert_same_parse(parser, &src, 2 + rng.below(7));
+    }
+}
+
+/// Differential test for grammars where whether a chunk's work can be used depends on the ops
+/// that are open at its start.
+#[test]
+fn test_parse_parallel_matches_parse_with_open_ops() {
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    grammar.line_comment("//");
+    grammar.regex("var", "[a-z]+").unwrap();
+    grammar.op("parens", pattern!("(" ")")).unwrap();
+    // Ops with optional tokens, that are only ended once the next lexeme is seen
+    grammar
+        .op(
+            "if",
+            pattern!("if" "[" "]" ("elif" "[" "]")* ("else" "[" "]")?),
+        )
+        .unwrap();
+    // Followers that are also ops of their own
+    grammar.op("pair", pattern!("<" "|" ">")).unwrap();
+    grammar
+        .op("try", pattern!("try" "!" ("catch" "!")?))
+        .unwrap();
+    // An argument in a sub-grammar, where "+" means something else
+    grammar.op("block", pattern!("{" Block "}")).unwrap();
+    grammar.left_assoc();
+    grammar.op("plus", pattern!(_ "+" _)).unwrap();
+    grammar.op("or", pattern!(_ "|" _)).unwrap();
+    grammar.right_assoc();
+    grammar
+        .op("cond", pattern!("when" "then" ("else")? _))
+        .unwrap();
+    grammar.left_assoc();
+    grammar.list_op("seq", ",").unwrap();
+    grammar.op("neg", pattern!("-" _)).unwrap();
+    grammar.op("bang", pattern!(_ "!")).unwrap();
+    grammar.subgrammar("Block");
+    grammar.regex("var", "[a-z]+").unwrap();
+    grammar.op("parens", pattern!("(" ")")).unwrap();
+    grammar.left_assoc();
+    grammar.op("cat", pattern!(_ "+" _)).unwrap();
+    grammar.list_op("stmts", ";").unwrap();
+    let parser = grammar.finish().unwrap();
+
+    // An op that's ended by the first lexeme of a chunk's work that's used
+    assert_same_parse(&parser, "try aaaaaaaaaaaaaaaa\n! x catch y !\n", 2);
+
+    let snippets = [
+        "x", "y z", " ", "\n", "\n", "\n", "(", ")", "+", "|", "<", ">", ",", ";", "{", "}", "-",
+        "!", "if", "[", "]", "elif", "else", "when", "then", "try", "catch", "//", "a+b+c",
+        "a|b|c",
+    ];
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..1000 {
+        let mut src = String::new();
+        for _ in 0..rng.below(60) {
+            src.push_str(snippets[rng.below(snippets.len())]);
+            src.push(' ');
+        }
+        assert_same_parse(&parser, &src, 2 + rng.below(7));
+    }
+}
+
+#[test]
+fn test_parse_parallel_with_layout() {
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    grammar.regex("var", "[a-z]+").unwrap();
+    grammar.op("block", pattern!(":" INDENT DEDENT)).unwrap();
+    let parser = grammar.finish().unwrap();
+    assert_same_parse(&parser, ":\n  a\n  :\n    b\nc", 3);
+}
@@ -1,4 +1,7 @@
-use panfix::{pattern, Grammar, GrammarError, Parser, Source};
+use panfix::{
+    op_enum, pattern, Assoc, Fixity, Grammar, GrammarError, Lexeme, OpId, OpMap, OpMapError,
+    Parser, Pattern, Position, Source, Span, TraceEntry, Trivia, Visitor, TOKEN_ERROR,
+};
 
 #[track_caller]
 fn assert_parse(parser: &Parser, src: &str, expected: &str) {
@@ -141,3 +144,1229 @@ fn test_parsing_juxtapose() {
     assert_parse(&parser, "1 2 3", "(_ 1 (_ 2 3))");
     assert_parse(&parser, "1 2 + 3 4", "(_ 1 (_ (plus 2 3) 4))");
 }
+
+#[track_caller]
+fn assert_recover(parser: &Parser, src: &str, expected_tree: &str, expected_errors: &[&str]) {
+    let source = Source::new("testcase", src.to_owned());
+    let (tree, errors) = parser.parse_recovering(&source);
+    assert_eq!(format!("{}", tree.visitor()), expected_tree);
+    let errors = errors
+        .iter()
+        .map(|err| err.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(errors, expected_errors);
+}
+
+#[test]
+fn test_error_recovery() {
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.regex("num", "[0-9]+")?;
+        grammar.op("parens", pattern!("(" ")"))?;
+        grammar.op("block", pattern!("{" "}"))?;
+        grammar.left_assoc();
+        grammar.op("plus", pattern!(_ "+" _))?;
+        grammar.left_assoc();
+        grammar.op("func", pattern!("fn" "(" ")" "{" "}"))?;
+        grammar.finish()
+    }
+    let parser = make_parser().unwrap();
+
+    assert_recover(&parser, "1 + 2", "(plus 1 2)", &[]);
+    assert_recover(
+        &parser,
+        "1 + % + 2",
+        "(plus (plus 1 %) 2)",
+        &["Unrecognized token."],
+    );
+    assert_recover(&parser, "1 ) 2", "(_ (_ 1 )) 2)", &["Unexpected token ')'"]);
+    assert_recover(
+        &parser,
+        "(1 + 2",
+        "(parens (plus 1 2))",
+        &["While parsing 'parens', expected ')' but found end of file."],
+    );
+    assert_recover(
+        &parser,
+        "{ (1 } + 2",
+        "(plus (block (parens 1)) 2)",
+        &["While parsing 'parens', expected ')' but found '}'."],
+    );
+    assert_recover(
+        &parser,
+        "fn ( 1 + ",
+        "(func _ (plus 1 _) _ _)",
+        &["While parsing 'func', expected ')' but found end of file."],
+    );
+    assert_recover(
+        &parser,
+        "{ fn ( } ) 3",
+        "(_ (_ (block (func _ _ _ _)) )) 3)",
+        &[
+            "While parsing 'func', expected ')' but found '}'.",
+            "Unexpected token ')'",
+        ],
+    );
+}
+
+#[test]
+fn test_token_spans() {
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.regex("num", "[0-9]+")?;
+        grammar.op("parens", pattern!("(" ")"))?;
+        grammar.left_assoc();
+        grammar.op("index", pattern!(_ "[" "]"))?;
+        grammar.left_assoc();
+        grammar.op("plus", pattern!(_ "+" _))?;
+        grammar.right_assoc();
+        grammar.op("cond", pattern!(_ "?" ":" _))?;
+        grammar.left_assoc();
+        grammar.op("func", pattern!("fn" "(" ")" "{" "}" ";" _))?;
+        grammar.finish()
+    }
+    let parser = make_parser().unwrap();
+
+    #[track_caller]
+    fn assert_token_spans(parser: &Parser, src: &str, span: &str, tokens: &[&str]) {
+        let source = Source::new("testcase", src.to_owned());
+        let (tree, _) = parser.parse_recovering(&source);
+        let visitor = tree.visitor();
+        assert_eq!(visitor.source(), span);
+        let actual = visitor
+            .token_spans()
+            .iter()
+            .map(|span| source.substr(*span))
+            .collect::<Vec<_>>();
+        assert_eq!(actual, tokens);
+    }
+
+    assert_token_spans(&parser, "7", "7", &["7"]);
+    assert_token_spans(&parser, "( 1 + 2 )", "( 1 + 2 )", &["(", ")"]);
+    assert_token_spans(&parser, "1 [ 2 ]", "1 [ 2 ]", &["[", "]"]);
+    assert_token_spans(&parser, "1 ? 2 : 3 + 4", "1 ? 2 : 3 + 4", &["?", ":"]);
+    assert_token_spans(
+        &parser,
+        "fn (0) {1 [2]} ; 5",
+        "fn (0) {1 [2]} ; 5",
+        &["fn", "(", ")", "{", "}", ";"],
+    );
+    assert_token_spans(&parser, "(1 + 2", "(1 + 2", &["(", ""]);
+}
+
+#[test]
+fn test_subgrammars() {
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.regex("num", "[0-9]+")?;
+        grammar.regex("var", "[a-z]+")?;
+        grammar.op("table", pattern!("{" "}"))?;
+        grammar.op("function", pattern!("function" FuncName "(" ")" "end"))?;
+        grammar.left_assoc();
+        grammar.op("plus", pattern!(_ "+" _))?;
+        grammar.right_assoc();
+        grammar.op("pair", pattern!(_ ":" _))?;
+        grammar.left_assoc();
+        grammar.op("comma", pattern!(_ "," _))?;
+
+        grammar.subgrammar("FuncName");
+        grammar.regex("var", "[a-z]+")?;
+        grammar.left_assoc();
+        grammar.op("field", pattern!(_ "." _))?;
+        grammar.left_assoc();
+        grammar.op("method", pattern!(_ ":" _))?;
+        grammar.finish()
+    }
+    let parser = make_parser().unwrap();
+
+    assert_parse(
+        &parser,
+        "{ a : 1, b : 2 }",
+        "(table (comma (pair a 1) (pair b 2)))",
+    );
+    assert_parse(
+        &parser,
+        "function a.b:c (x) x + 1 end",
+        "(function (method (field a b) c) x (plus x 1))",
+    );
+    assert_parse(
+        &parser,
+        "function f (x) { a : function g (y) y end } end",
+        "(function f x (table (pair a (function g y y))))",
+    );
+    assert_error(
+        &parser,
+        "function a + b () end",
+        r#"Parse Error: While parsing 'function', expected '(' but found '+'.
+ --> testcase:1:12
+  |
+1 |function a + b () end
+  |           ^ expected function
+"#,
+    );
+
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    grammar.regex("var", "[a-z]+").unwrap();
+    grammar
+        .op("function", pattern!("function" FuncName "(" ")" "end"))
+        .unwrap();
+    assert!(matches!(
+        grammar.finish(),
+        Err(GrammarError::UndefinedSubgrammar(name)) if name == "FuncName"
+    ));
+}
+
+#[test]
+fn test_comments() {
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.line_comment("//");
+        grammar.block_comment("/*", "*/", true);
+        grammar.block_comment("{-", "-}", false);
+        grammar.regex("num", "[0-9]+")?;
+        grammar.op("parens", pattern!("(" ")"))?;
+        grammar.left_assoc();
+        grammar.op("div", pattern!(_ "/" _))?;
+        grammar.finish()
+    }
+    let parser = make_parser().unwrap();
+
+    assert_parse(&parser, "1 / 2 // half", "(div 1 2)");
+    assert_parse(&parser, "1 /* a /* b */ c */ / 2", "(div 1 2)");
+    assert_parse(&parser, "1 {- a {- b -} / 2", "(div 1 2)");
+    assert_parse(&parser, "// one\n1 // two\n/ // three\n2", "(div 1 2)");
+
+    let src = "(1 /* a */ / 2) // b\n/ /* c /* d */ */ 3";
+    let source = Source::new("testcase", src.to_owned());
+    let tree = parser.parse(&source).unwrap();
+    let comments = |spans: &[Span]| {
+        spans
+            .iter()
+            .map(|span| source.substr(*span))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        comments(tree.comments()),
+        &["/* a */", "// b", "/* c /* d */ */"]
+    );
+    let parens = tree.visitor().child(0);
+    assert_eq!(comments(tree.comments_in(parens.span())), &["/* a */"]);
+    let three = tree.visitor().child(1);
+    assert!(tree.comments_in(three.span()).is_empty());
+
+    assert_error(
+        &parser,
+        "1 / /* 2 /* */",
+        r#"Parse Error: Unrecognized token.
+ --> testcase:1:5
+  |
+1 |1 / /* 2 /* */
+  |    ^^^^^^^^^^ unrecognized token
+"#,
+    );
+}
+
+#[test]
+fn test_lossless() {
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.line_comment("//");
+        grammar.block_comment("/*", "*/", true);
+        grammar.regex("num", "[0-9]+")?;
+        grammar.op("parens", pattern!("(" ")"))?;
+        grammar.left_assoc();
+        grammar.op("plus", pattern!(_ "+" _))?;
+        grammar.right_assoc();
+        grammar.op("cond", pattern!(_ "?" ":" _))?;
+        grammar.finish()
+    }
+    let parser = make_parser().unwrap();
+
+    #[track_caller]
+    fn assert_lossless(parser: &Parser, src: &str) {
+        let source = Source::new("testcase", src.to_owned());
+        let (tree, _) = parser.parse_recovering(&source);
+        assert_eq!(tree.with_trivia().to_lossless_string(), src);
+    }
+
+    assert_lossless(&parser, "");
+    assert_lossless(&parser, "  \n");
+    assert_lossless(&parser, "1");
+    assert_lossless(&parser, " 1 + ( 2+3 ) \n");
+    assert_lossless(
+        &parser,
+        "// header\n1 /* one */ ? 2 // two\n : 3\n\n// footer\n",
+    );
+    assert_lossless(&parser, "1 /* a\n b */ + 2\r\n");
+    assert_lossless(&parser, "1 + + (2 3 ? ");
+    assert_lossless(&parser, "1 % 2 ) /* unterminated");
+
+    let src = "// header\n1 // one\n+ /* a\n b */ 2\n";
+    let source = Source::new("testcase", src.to_owned());
+    let tree = parser.parse(&source).unwrap().with_trivia();
+    let show = |trivia: Trivia| {
+        (
+            source.substr(trivia.leading),
+            source.substr(trivia.trailing),
+        )
+    };
+    let plus = tree.visitor();
+    assert_eq!(show(plus.trivia().unwrap()[0]), ("\n", " /* a\n b */ "));
+    assert_eq!(
+        show(plus.child(0).trivia().unwrap()[0]),
+        ("// header\n", " // one")
+    );
+    assert_eq!(show(plus.child(1).trivia().unwrap()[0]), ("", ""));
+    assert_eq!(source.substr(tree.end_of_file_trivia().unwrap()), "\n");
+    assert_eq!(plus.child(1).to_lossless_string(), "2");
+}
+
+#[test]
+fn test_layout() {
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.line_comment("#");
+        grammar.block_comment("/*", "*/", false);
+        grammar.regex("key", "[a-z]+")?;
+        grammar.regex("num", "[0-9]+")?;
+        grammar.left_assoc();
+        grammar.op("keyval", pattern!(_ "=" _))?;
+        grammar.left_assoc();
+        grammar.op("section", pattern!(_ ":" INDENT DEDENT))?;
+        grammar.left_assoc();
+        grammar.op("seq", pattern!(_ NEWLINE _))?;
+        grammar.finish()
+    }
+    let parser = make_parser().unwrap();
+
+    assert_parse(&parser, "a = 1", "(keyval a 1)");
+    assert_parse(&parser, "a = 1\nb = 2\n", "(seq (keyval a 1) (keyval b 2))");
+    assert_parse(
+        &parser,
+        "name = 1\nserver:\n  port = 80\n\n  # comment\n  tls:\n    on = 1\ndebug = 0\n",
+        "(seq (seq (keyval name 1) (section server _ (seq (keyval port 80) (section tls _ (keyval on 1))))) (keyval debug 0))",
+    );
+    assert_parse(&parser, "a:\n\tb = 1", "(section a _ (keyval b 1))");
+    // Comments before the first token of a line aren't indentation
+    assert_parse(&parser, "/* x */ a = 1", "(keyval a 1)");
+    assert_parse(
+        &parser,
+        "a:\n  b = 1\n  /* c */ d = 2",
+        "(section a _ (seq (keyval b 1) (keyval d 2)))",
+    );
+    assert_error(
+        &parser,
+        "a:\n    b = 1\n  c = 2\n",
+        r#"Parse Error: Indentation does not match any enclosing block.
+ --> testcase:3:1
+  |
+3 |  c = 2
+  |^^ inconsistent indentation
+"#,
+    );
+    assert_error(
+        &parser,
+        "a = 1\n  b = 2\n",
+        r#"Parse Error: Unexpected token 'INDENT'
+ --> testcase:2:3
+  |
+2 |  b = 2
+  |  ^ unexpected token
+"#,
+    );
+}
+
+#[test]
+fn test_parse_tokens() {
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.regex("num", "[0-9]+")?;
+        grammar.op("parens", pattern!("(" ")"))?;
+        grammar.left_assoc();
+        grammar.op("plus", pattern!(_ "+" _))?;
+        grammar.finish()
+    }
+    let parser = make_parser().unwrap();
+    assert_eq!(
+        &parser.token_names()[..3],
+        &[
+            "LexError".to_owned(),
+            "Blank".to_owned(),
+            "Juxtapose".to_owned()
+        ]
+    );
+
+    // A hand-written lexer for single-line sources, with one-character tokens.
+    fn lex(parser: &Parser, src: &str) -> Vec<Lexeme> {
+        src.char_indices()
+            .filter(|(_, ch)| *ch != ' ')
+            .map(|(i, ch)| {
+                let token = match ch {
+                    '0'..='9' => parser.token("num").unwrap(),
+                    _ => parser.token(&ch.to_string()).unwrap_or(TOKEN_ERROR),
+                };
+                Lexeme::new(token, i, i + 1)
+            })
+            .collect()
+    }
+
+    let source = Source::new("testcase", "1 + (2 + 3)".to_owned());
+    let tree = parser
+        .parse_tokens(&source, lex(&parser, source.source()).into_iter())
+        .unwrap();
+    assert_eq!(format!("{}", tree), "(plus 1 (parens (plus 2 3)))");
+
+    let source = Source::new("testcase", "1 + % + (2".to_owned());
+    let (tree, errors) =
+        parser.parse_tokens_recovering(&source, lex(&parser, source.source()).into_iter());
+    assert_eq!(format!("{}", tree), "(plus (plus 1 %) (parens 2))");
+    assert_eq!(errors.len(), 2);
+}
+
+#[test]
+fn test_parse_traced() {
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.regex("num", "[0-9]+")?;
+        grammar.op("parens", pattern!("(" ")"))?;
+        grammar.left_assoc();
+        grammar.op("times", pattern!(_ "*" _))?;
+        grammar.left_assoc();
+        grammar.op("plus", pattern!(_ "+" _))?;
+        grammar.finish()
+    }
+    fn names<'a>(entries: &[TraceEntry<'a>]) -> Vec<&'a str> {
+        entries.iter().map(|entry| entry.name).collect()
+    }
+    let parser = make_parser().unwrap();
+
+    let source = Source::new("testcase", "1 + (2 3) * ".to_owned());
+    let (tree, errors, trace) = parser.parse_traced(&source);
+    assert!(errors.is_empty());
+    assert_eq!(
+        format!("{}", tree.visitor()),
+        "(plus 1 (times (parens (_ 2 3)) _))"
+    );
+    assert_eq!(
+        names(&trace.lexed),
+        ["num", "+", "(", "num", "num", ")", "*"]
+    );
+    assert_eq!(
+        names(&trace.resolved),
+        [
+            "num",
+            "plus",
+            "parens",
+            "num",
+            "Juxtapose",
+            "num",
+            "parens",
+            "times",
+            "Blank"
+        ]
+    );
+    assert_eq!(
+        names(&trace.shunted),
+        [
+            "num",
+            "num",
+            "num",
+            "Juxtapose",
+            "parens",
+            "parens",
+            "Blank",
+            "times",
+            "plus"
+        ]
+    );
+    assert_eq!(
+        names(&trace.filtered),
+        [
+            "num",
+            "num",
+            "num",
+            "Juxtapose",
+            "parens",
+            "Blank",
+            "times",
+            "plus"
+        ]
+    );
+    let inserted = trace
+        .resolved
+        .iter()
+        .filter(|entry| entry.inserted)
+        .map(|entry| entry.name)
+        .collect::<Vec<_>>();
+    assert_eq!(inserted, ["Juxtapose", "Blank"]);
+    assert_eq!(source.substr(trace.shunted[4].span), ")");
+    assert_eq!(source.substr(trace.filtered[4].span), "(");
+
+    let source = Source::new("testcase", "(1 +".to_owned());
+    let (_, errors, trace) = parser.parse_traced(&source);
+    assert_eq!(names(&trace.lexed), ["(", "num", "+"]);
+    assert_eq!(
+        names(&trace.resolved),
+        ["parens", "num", "plus", "Blank", "parens"]
+    );
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn test_non_assoc() {
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.regex("var", "[a-z]+")?;
+        grammar.op("parens", pattern!("(" ")"))?;
+        grammar.left_assoc();
+        grammar.op("plus", pattern!(_ "+" _))?;
+        grammar.non_assoc();
+        grammar.op("lt", pattern!(_ "<" _))?;
+        grammar.op("eq", pattern!(_ "==" _))?;
+        grammar.left_assoc();
+        grammar.op("and", pattern!(_ "&&" _))?;
+        grammar.finish()
+    }
+    let parser = make_parser().unwrap();
+
+    assert_parse(&parser, "a + b < c + d", "(lt (plus a b) (plus c d))");
+    assert_parse(&parser, "a < b && b == c", "(and (lt a b) (eq b c))");
+    assert_parse(&parser, "(a < b) < c", "(lt (parens (lt a b)) c)");
+    assert_error(
+        &parser,
+        "a < b == c",
+        r#"Parse Error: 'lt' and 'eq' are non-associative, so they cannot be chained. Use parentheses.
+ --> testcase:1:7
+  |
+1 |a < b == c
+  |      ^^ cannot be chained
+"#,
+    );
+
+    let source = Source::new("testcase", "a < b < c < d && x == y".to_owned());
+    let (tree, errors) = parser.parse_recovering(&source);
+    assert_eq!(
+        format!("{}", tree.visitor()),
+        "(and (ChainError (ChainError (lt a b) c) d) (eq x y))"
+    );
+    assert_eq!(errors.len(), 1);
+    assert_eq!(source.substr(errors[0].span), "<");
+    assert_eq!(errors[0].span.start, 6);
+
+    // Errors are reported in order, together with those from resolving
+    let source = Source::new("testcase", "a < b < c && )".to_owned());
+    let (_, errors) = parser.parse_recovering(&source);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].short_message, "cannot be chained");
+    assert_eq!(errors[1].short_message, "unexpected token");
+}
+
+#[test]
+fn test_precedence_groups() {
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.regex("var", "[a-z]+")?;
+        grammar.op("parens", pattern!("(" ")"))?;
+        grammar.group("Or", Assoc::Left)?;
+        grammar.op("or", pattern!(_ "||" _))?;
+        grammar.group("And", Assoc::Left)?;
+        grammar.op("and", pattern!(_ "&&" _))?;
+        grammar.group("Comparison", Assoc::NonAssoc)?;
+        grammar.op("eq", pattern!(_ "==" _))?;
+        grammar.group("BitAnd", Assoc::Left)?;
+        grammar.op("bitand", pattern!(_ "&" _))?;
+        grammar.group("Addition", Assoc::Left)?;
+        grammar.op("plus", pattern!(_ "+" _))?;
+        grammar.op("neg", pattern!("-" _))?;
+        grammar.group("Multiplication", Assoc::Left)?;
+        grammar.op("times", pattern!(_ "*" _))?;
+        grammar.tighter_than("Multiplication", "Addition");
+        grammar.tighter_than("Addition", "Comparison");
+        grammar.tighter_than("Comparison", "And");
+        grammar.tighter_than("Comparison", "Or");
+        grammar.tighter_than("BitAnd", "And");
+        grammar.finish()
+    }
+    let parser = make_parser().unwrap();
+
+    assert_parse(
+        &parser,
+        "a + b * c == d && e",
+        "(and (eq (plus a (times b c)) d) e)",
+    );
+    assert_parse(&parser, "a & b && c", "(and (bitand a b) c)");
+    assert_parse(&parser, "- a * b", "(neg (times a b))");
+    assert_parse(&parser, "(a && b) || c", "(or (parens (and a b)) c)");
+    assert_error(
+        &parser,
+        "a && b || c",
+        r#"Parse Error: 'or' and 'and' have incomparable precedence, so they cannot be mixed. Use parentheses.
+ --> testcase:1:3
+  |
+1 |a && b || c
+  |  ^^ cannot be mixed
+"#,
+    );
+
+    let source = Source::new("testcase", "a & b == c && x + y & z".to_owned());
+    let (tree, errors) = parser.parse_recovering(&source);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].short_message, "cannot be mixed");
+    assert_eq!(source.substr(errors[0].span), "==");
+    assert_eq!(errors[1].short_message, "cannot be mixed");
+    assert_eq!(source.substr(errors[1].span), "+");
+    assert_eq!(
+        format!("{}", tree.visitor()),
+        "(and (ChainError (bitand a b) c) (ChainError x (bitand y z)))"
+    );
+    let [mixed, _] = tree.visitor().children();
+    assert_eq!(mixed.op_id(), OpId::CHAIN_ERROR);
+    assert_eq!(mixed.tokens(), ["=="]);
+
+    // Every element of a list is checked, not just the first and last
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    grammar.regex("var", "[a-z]+").unwrap();
+    grammar.group("Question", Assoc::Left).unwrap();
+    grammar.op("q", pattern!(_ "?" _)).unwrap();
+    grammar.group("List", Assoc::Right).unwrap();
+    grammar.list_op("elems", ",").unwrap();
+    let parser = grammar.finish().unwrap();
+    for (src, separator) in [("a ? b, d", 5), ("x, a ? b, d", 1), ("x, y, a ? b", 4)] {
+        let source = Source::new("testcase", src.to_owned());
+        let (tree, errors) = parser.parse_recovering(&source);
+        assert_eq!(tree.visitor().name(), "ChainError", "parsing {:?}", src);
+        assert_eq!(errors.len(), 1, "parsing {:?}", src);
+        assert_eq!(errors[0].short_message, "cannot be mixed");
+        assert_eq!(errors[0].span.start, separator, "parsing {:?}", src);
+    }
+    assert_parse(&parser, "x, y, z", "(elems x y z)");
+
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    grammar.group("A", Assoc::Left).unwrap();
+    grammar.group("B", Assoc::Left).unwrap();
+    assert!(matches!(
+        grammar.group("A", Assoc::Right),
+        Err(GrammarError::DuplicateGroup(_))
+    ));
+    grammar.tighter_than("A", "B");
+    let mut cyclic = grammar.clone();
+    cyclic.tighter_than("B", "A");
+    assert!(matches!(
+        cyclic.finish(),
+        Err(GrammarError::PrecedenceCycle(_))
+    ));
+    grammar.tighter_than("B", "C");
+    assert!(matches!(
+        grammar.finish(),
+        Err(GrammarError::UndefinedGroup(name)) if name == "C"
+    ));
+}
+
+#[test]
+fn test_list_op() {
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.regex("num", "[0-9]+")?;
+        grammar.op("array", pattern!("[" "]"))?;
+        grammar.left_assoc();
+        grammar.op("plus", pattern!(_ "+" _))?;
+        grammar.left_assoc();
+        grammar.list_op("elems", ",")?;
+        grammar.finish()
+    }
+    let parser = make_parser().unwrap();
+
+    assert_parse(&parser, "[1]", "(array 1)");
+    assert_parse(&parser, "[1, 2 + 3, 4]", "(array (elems 1 (plus 2 3) 4))");
+    assert_parse(
+        &parser,
+        "[1, [2, 3]]",
+        "(array (elems 1 (array (elems 2 3))))",
+    );
+    assert_parse(&parser, "[1, , 2]", "(array (elems 1 _ 2))");
+
+    let source = Source::new("testcase", "[1, 2, 3,]".to_owned());
+    let tree = parser.parse(&source).unwrap();
+    assert_eq!(format!("{}", tree.visitor()), "(array (elems 1 2 3))");
+    let list = tree.visitor().child(0);
+    assert!(list.has_trailing_separator());
+    assert_eq!(list.num_children(), 3);
+    assert_eq!(list.source(), "1, 2, 3,");
+    let spans = list.token_spans();
+    assert_eq!(spans.len(), 3);
+    assert!(spans.iter().all(|span| source.substr(*span) == ","));
+    assert!(spans.windows(2).all(|pair| pair[0].start < pair[1].start));
+    let elems = list
+        .children_iter()
+        .map(|elem| elem.source())
+        .collect::<Vec<_>>();
+    assert_eq!(elems, ["1", "2", "3"]);
+
+    let source = Source::new("testcase", "[4,]".to_owned());
+    let tree = parser.parse(&source).unwrap().with_trivia();
+    let list = tree.visitor().child(0);
+    assert_eq!(list.name(), "elems");
+    assert_eq!(list.num_children(), 1);
+    assert!(list.has_trailing_separator());
+    assert_eq!(tree.to_lossless_string(), "[4,]");
+
+    let source = Source::new("testcase", " [ 1 ,2,  3 ] ".to_owned());
+    let tree = parser.parse(&source).unwrap().with_trivia();
+    assert!(!tree.visitor().child(0).has_trailing_separator());
+    assert_eq!(tree.to_lossless_string(), " [ 1 ,2,  3 ] ");
+}
+
+#[test]
+fn test_pattern_groups() {
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.line_comment("//");
+        grammar.regex("var", "[a-z]+")?;
+        grammar.regex("num", "[0-9]+")?;
+        grammar.op(
+            "if",
+            pattern!("if" "{" "}" ("elif" "{" "}")* ("else" "{" "}")?),
+        )?;
+        grammar.op("match", pattern!("match" "{" ("case" "=>")* "}"))?;
+        grammar.left_assoc();
+        grammar.op("plus", pattern!(_ "+" _))?;
+        grammar.right_assoc();
+        grammar.op("cond", pattern!("when" "then" ("else")? _))?;
+        grammar.left_assoc();
+        grammar.list_op("seq", ";")?;
+        grammar.finish()
+    }
+    let parser = make_parser().unwrap();
+
+    assert_parse(&parser, "if a { b }", "(if a b)");
+    assert_parse(&parser, "if a { b } else { c }", "(if a b _ c)");
+    assert_parse(
+        &parser,
+        "if a { b } elif c { d } elif e { f } else { g }",
+        "(if a b c d e f _ g)",
+    );
+    assert_parse(&parser, "if a { b } + 1", "(plus (if a b) 1)");
+    assert_parse(&parser, "match x { }", "(match x _)");
+    assert_parse(
+        &parser,
+        "match x { case 1 => a case 2 => b + c }",
+        "(match x _ 1 a 2 (plus b c))",
+    );
+    assert_parse(&parser, "when a then b", "(cond a b)");
+    assert_parse(&parser, "when a then b else c; d", "(seq (cond a b c) d)");
+    assert_parse(
+        &parser,
+        "when a then b + 1; c",
+        "(seq (cond a (plus b 1)) c)",
+    );
+    assert_parse(
+        &parser,
+        "when a then when b then c else d",
+        "(cond a (cond b c d))",
+    );
+
+    let source = Source::new(
+        "testcase",
+        "if a { b } elif c { d } elif e { f }".to_owned(),
+    );
+    let tree = parser.parse(&source).unwrap();
+    let clauses = tree.visitor().clauses().collect::<Vec<_>>();
+    let names = clauses
+        .iter()
+        .map(|clause| clause.token())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["if", "elif", "elif"]);
+    let [cond, body] = clauses[1].children();
+    assert_eq!((cond.source(), body.source()), ("c", "d"));
+    assert_eq!(clauses[2].token_spans().len(), 3);
+    assert_eq!(source.substr(clauses[2].token_spans()[0]), "elif");
+    assert_eq!(tree.visitor().tokens().len(), 9);
+    assert_eq!(tree.visitor().token_spans().len(), 9);
+
+    let source = Source::new("testcase", "match x { case 1 => a }".to_owned());
+    let tree = parser.parse(&source).unwrap();
+    let clauses = tree.visitor().clauses().collect::<Vec<_>>();
+    let names = clauses
+        .iter()
+        .map(|clause| clause.token())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["match", "case", "}"]);
+    assert_eq!(clauses[0].num_children(), 2);
+    assert_eq!(clauses[1].child(1).source(), "a");
+    assert_eq!(clauses[2].num_children(), 0);
+
+    let source = Source::new("testcase", "1 + 2".to_owned());
+    let tree = parser.parse(&source).unwrap();
+    let clauses = tree.visitor().clauses().collect::<Vec<_>>();
+    assert_eq!(clauses.len(), 1);
+    assert_eq!(clauses[0].token(), "+");
+    assert_eq!(clauses[0].child(0).source(), "2");
+
+    assert_recover(
+        &parser,
+        "if a { b } elif c { d",
+        "(if a b c d)",
+        &["While parsing 'if', expected '}' but found end of file."],
+    );
+    assert_recover(
+        &parser,
+        "match x { case 1 => a",
+        "(match x _ 1 a)",
+        &["While parsing 'match', expected '}' but found end of file."],
+    );
+
+    // Printing losslessly skips the missing children between groups
+    for src in [
+        "if a {b} elif c {d} elif e {f} else {g} // z",
+        "if a { b }else{c}",
+        "match x { case 1 => a case 2 => b + c }",
+        " when a then b else c; d ",
+    ] {
+        let source = Source::new("testcase", src.to_owned());
+        let tree = parser.parse(&source).unwrap().with_trivia();
+        assert_eq!(tree.to_lossless_string(), src);
+    }
+
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    let err = grammar
+        .op("bad", pattern!("a" ("b")* ("b" "c")?))
+        .unwrap_err();
+    assert!(matches!(err, GrammarError::AmbiguousPattern(_, _)));
+
+    // Patterns that were changed after they were made
+    let mut pattern = pattern!("a" "b" ("c")?);
+    pattern.tokens.pop();
+    let err = grammar.op("bad", pattern).unwrap_err();
+    assert!(matches!(err, GrammarError::MalformedPattern(name, _) if name == "bad"));
+    let err = grammar
+        .op("bad", Pattern::new(Fixity::Nilfix, vec![]))
+        .unwrap_err();
+    assert!(matches!(err, GrammarError::MalformedPattern(_, _)));
+    let err = grammar
+        .add_raw_op("bad", 10, Assoc::Left, Fixity::Nilfix, vec![])
+        .unwrap_err();
+    assert!(matches!(err, GrammarError::MalformedPattern(_, _)));
+}
+
+#[test]
+fn test_op_ids() {
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    let num = grammar.regex("num", "[0-9]+").unwrap();
+    let call = grammar.op("call", pattern!(_ "(" Args ")")).unwrap();
+    grammar.left_assoc();
+    let plus = grammar.op("plus", pattern!(_ "+" _)).unwrap();
+    grammar.non_assoc();
+    let lt = grammar.op("lt", pattern!(_ "<" _)).unwrap();
+    grammar.subgrammar("Args");
+    assert_eq!(grammar.regex("num", "[0-9]+").unwrap(), num);
+    let args = grammar.list_op("args", ",").unwrap();
+    let parser = grammar.finish().unwrap();
+
+    assert_eq!(parser.op_id("plus"), Some(plus));
+    assert_eq!(parser.op_id("minus"), None);
+    assert_eq!(parser.op_names()[lt.index()], "lt");
+    assert_eq!(
+        &parser.op_names()[..4],
+        ["LexError", "Blank", "Juxtapose", "ChainError"]
+    );
+
+    let source = Source::new("testcase", "1 + 2(3, 4) + ".to_owned());
+    let tree = parser.parse(&source).unwrap();
+    let root = tree.visitor();
+    assert_eq!(root.op_id(), plus);
+    let [left, right] = root.children();
+    assert_eq!(right.op_id(), OpId::BLANK);
+    let [one, f] = left.children();
+    assert_eq!((one.op_id(), f.op_id()), (num, call));
+    let [_, list] = f.children();
+    assert_eq!(list.op_id(), args);
+    assert!(list.children_iter().all(|elem| elem.op_id() == num));
+
+    let source = Source::new("testcase", "1 < 2 < 3".to_owned());
+    let (tree, _) = parser.parse_recovering(&source);
+    assert_eq!(tree.visitor().op_id(), OpId::CHAIN_ERROR);
+    assert_eq!(tree.visitor().child(0).op_id(), lt);
+}
+
+#[test]
+fn test_op_enum() {
+    op_enum! {
+        /// The ops of the grammar below.
+        enum Op {
+            LexError,
+            Blank,
+            Juxtapose,
+            ChainError,
+            Num = "num",
+            Plus = "+",
+            Parens,
+        }
+    }
+    op_enum! {
+        enum Partial {
+            LexError,
+            Blank,
+            Juxtapose,
+            ChainError,
+            Parens,
+        }
+    }
+    op_enum! {
+        pub(crate) enum Extra {
+            LexError,
+            Blank,
+            Juxtapose,
+            ChainError,
+            Num = "num",
+            Plus = "+",
+            Parens,
+            Minus = "-",
+        }
+    }
+
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.regex("num", "[0-9]+")?;
+        grammar.op("Parens", pattern!("(" ")"))?;
+        grammar.left_assoc();
+        grammar.op("+", pattern!(_ "+" _))?;
+        grammar.finish()
+    }
+    let parser = make_parser().unwrap();
+    let ops = parser.op_map::<Op>().unwrap();
+
+    fn sum(ops: &OpMap<Op>, visitor: Visitor) -> Option<u32> {
+        match ops.get(visitor.op_id()) {
+            Op::Num => visitor.source().parse().ok(),
+            Op::Plus => Some(sum(ops, visitor.child(0))? + sum(ops, visitor.child(1))?),
+            Op::Parens => sum(ops, visitor.child(0)),
+            Op::LexError | Op::Blank | Op::Juxtapose | Op::ChainError => None,
+        }
+    }
+    let source = Source::new("testcase", "1 + (2 + 3)".to_owned());
+    let tree = parser.parse(&source).unwrap();
+    assert_eq!(sum(&ops, tree.visitor()), Some(6));
+    let source = Source::new("testcase", "1 + (2 3)".to_owned());
+    let tree = parser.parse(&source).unwrap();
+    assert_eq!(sum(&ops, tree.visitor()), None);
+
+    let err = parser.op_map::<Partial>().unwrap_err();
+    assert!(matches!(err, OpMapError::MissingOp(name) if name == "num"));
+    let err = parser.op_map::<Extra>().unwrap_err();
+    assert!(matches!(err, OpMapError::UnknownOp(name) if name == "-"));
+}
+
+#[test]
+fn test_owned_parse_tree() {
+    use panfix::{OwnedParseTree, OwnedVisitor};
+    use std::sync::Arc;
+
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.regex("num", "[0-9]+")?;
+        grammar.line_comment("//");
+        grammar.op("Parens", pattern!("(" ")"))?;
+        grammar.non_assoc();
+        grammar.op("<", pattern!(_ "<" _))?;
+        grammar.left_assoc();
+        grammar.op("+", pattern!(_ "+" _))?;
+        grammar.finish()
+    }
+
+    // The tree outlives the source and parser it was made from
+    fn parse_owned(src: &str) -> OwnedParseTree {
+        let parser = make_parser().unwrap();
+        let source = Source::new("testcase", src.to_owned());
+        let tree = parser.parse(&source).unwrap().with_trivia();
+        tree.into_owned()
+    }
+
+    fn assert_send_sync<T: Send + Sync + 'static>(_: &T) {}
+
+    let tree = parse_owned("1 + (2 + 3) // sum");
+    assert_send_sync(&tree);
+    assert_eq!(format!("{}", tree), "(+ 1 (Parens (+ 2 3)))");
+    assert_eq!(tree.to_lossless_string(), "1 + (2 + 3) // sum");
+    assert_eq!(tree.comments().len(), 1);
+
+    let root: OwnedVisitor = tree.root();
+    assert_send_sync(&root);
+    drop(tree);
+    let [left, right] = root.children();
+    assert_eq!(root.name(), "+");
+    assert_eq!(left.source(), "1");
+    assert_eq!(right.token_source(), "(");
+    assert_eq!(right.tokens(), &["(", ")"]);
+    let inner = std::thread::spawn(move || right.child(0)).join().unwrap();
+    assert_eq!(format!("{}", inner), "(+ 2 3)");
+    assert_eq!(inner.span(), inner.visitor().span());
+    assert_eq!(inner.children_iter().count(), 2);
+    assert_eq!(inner.tree().source().source(), "1 + (2 + 3) // sum");
+    assert_eq!(inner.op_id(), inner.tree().parser().op_id("+").unwrap());
+
+    // Chain errors are kept too
+    let parser = Arc::new(make_parser().unwrap());
+    let source = Arc::new(Source::new("testcase", "1 < 2 < 3".to_owned()));
+    let (tree, errors) = parser.parse_recovering(&source);
+    assert_eq!(errors.len(), 1);
+    let tree = tree.into_owned_with(source.clone(), parser.clone());
+    assert_eq!(format!("{}", tree), "(ChainError (< 1 2) 3)");
+    assert!(Arc::ptr_eq(tree.source(), &source));
+}
+
+#[test]
+#[should_panic(expected = "must be given the tree's own source and parser")]
+fn test_owned_parse_tree_wrong_source() {
+    use std::sync::Arc;
+
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    grammar.regex("num", "[0-9]+").unwrap();
+    let parser = Arc::new(grammar.finish().unwrap());
+    let source = Source::new("testcase", "1".to_owned());
+    let tree = parser.parse(&source).unwrap();
+    tree.into_owned_with(Arc::new(source.clone()), parser.clone());
+}
+
+#[test]
+fn test_owned_parse_error() {
+    use panfix::OwnedParseError;
+    use std::error::Error;
+
+    fn make_parser() -> Result<Parser, GrammarError> {
+        let mut grammar = Grammar::new_with_unicode_whitespace()?;
+        grammar.regex("num", "[0-9]+")?;
+        grammar.op("Parens", pattern!("(" ")"))?;
+        grammar.left_assoc();
+        grammar.op("+", pattern!(_ "+" _))?;
+        grammar.finish()
+    }
+
+    fn parse_boxed(parser: &Parser, src: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let source = Source::new("testcase", src.to_owned());
+        let tree = parser.parse(&source).map_err(OwnedParseError::from)?;
+        Ok(format!("{}", tree.visitor()))
+    }
+
+    let parser = make_parser().unwrap();
+    assert_eq!(parse_boxed(&parser, "1 + 2").unwrap(), "(+ 1 2)");
+    let err = parse_boxed(&parser, "1 + 2)").unwrap_err();
+    assert!(err.to_string().contains("Unexpected token ')'"), "{}", err);
+
+    // Errors on one line, two lines, and more than two lines
+    for src in ["1 + )", "(1 +\n2)", "(1 +\n\n\n2 + 3)"] {
+        let source = Source::new("testcase", src.to_owned());
+        let err = match parser.parse(&source) {
+            Ok(tree) => tree.visitor().error("this", "Custom error."),
+            Err(err) => err,
+        };
+        let expected = format!("{}", err.display_with_color_override(false));
+        let (message, span) = (err.message.clone(), err.span);
+        let owned = err.into_owned();
+        assert_eq!(owned.filename, "testcase");
+        assert_eq!(owned.message, message);
+        assert_eq!(owned.span, span);
+        let owned = std::thread::spawn(move || owned).join().unwrap();
+        drop(source);
+        assert_eq!(
+            format!("{}", owned.display_with_color_override(false)),
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_source_map() {
+    use panfix::{FileSpan, SourceMap};
+    use std::collections::HashMap;
+
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    grammar.regex("var", "[a-z]+").unwrap();
+    grammar.string("num", "1").unwrap();
+    grammar.right_assoc();
+    grammar.op("=", pattern!(_ "=" _)).unwrap();
+    grammar.op("let", pattern!("let" ";")).unwrap();
+    let parser = grammar.finish().unwrap();
+
+    let mut map = SourceMap::new();
+    let a = map.add(Source::new("a.txt", "let x = 1;".to_owned()));
+    let b = map.add(Source::new("b.txt", "\n  let y = x;".to_owned()));
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.file_id("b.txt"), Some(b));
+    assert_eq!(map.filename(a), "a.txt");
+
+    // Collect the definitions of every file into one table of global spans
+    let mut definitions = HashMap::new();
+    for (file, _) in map.files() {
+        let tree = map.parse(&parser, file).unwrap();
+        assert_eq!(map.file_of(tree.source()), Some(file));
+        let name = tree.visitor().child(0).child(0);
+        definitions.insert(name.source(), FileSpan::new(file, name.span()));
+    }
+    let x = definitions["x"];
+    let y = definitions["y"];
+    assert_eq!(x.file, a);
+    assert_eq!(map.substr(y), "y");
+    assert_eq!(format!("{}", map.location(x)), "a.txt:1:5");
+    assert_eq!(format!("{}", map.location(y)), "b.txt:2:7");
+
+    let err = map.error(y, "defined here", "Defined in a different file.");
+    assert_eq!(
+        format!("{}", err.display_with_color_override(false)),
+        "Parse Error: Defined in a different file.
+ --> b.txt:2:7
+  |
+2 |  let y = x;
+  |      ^ defined here
+"
+    );
+    assert_eq!(map.error_span(&err), Some(y));
+    let owned = map.owned_error(x, "here", "Defined here.");
+    assert_eq!(owned.filename, "a.txt");
+
+    // Errors from parsing a file know which file they're in
+    let c = map.add(Source::new("c.txt", "let z = ;".to_owned()));
+    let tree = map.parse(&parser, c).unwrap();
+    let blank = tree.visitor().child(0).child(1);
+    let err = blank.error("missing", "Missing value.");
+    assert_eq!(map.error_span(&err).unwrap().file, c);
+    let elsewhere = Source::new("c.txt", "let z = ;".to_owned());
+    let err = parser.parse(&elsewhere).unwrap().visitor().error("", "");
+    assert_eq!(map.error_span(&err), None);
+}
+
+#[test]
+fn test_position_at() {
+    let source = Source::new("testcase", "ab\nλx\n\nz".to_owned());
+    let pos = |line, col, utf8_col| Position {
+        line,
+        col,
+        utf8_col,
+    };
+    assert_eq!(source.position_at(0), pos(0, 0, 0));
+    assert_eq!(source.position_at(2), pos(0, 2, 2));
+    assert_eq!(source.position_at(3), pos(1, 0, 0));
+    assert_eq!(source.position_at(5), pos(1, 2, 1));
+    assert_eq!(source.position_at(6), pos(1, 3, 2));
+    assert_eq!(source.position_at(7), pos(2, 0, 0));
+    assert_eq!(source.position_at(8), pos(3, 0, 0));
+    assert_eq!(source.position_at(9), pos(3, 1, 1));
+    for offset in [0, 2, 5, 6, 8, 9] {
+        assert_eq!(source.offset(source.position_at(offset)), offset);
+    }
+
+    // Spans are byte offsets
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    grammar.regex("var", "[a-zλ]+").unwrap();
+    grammar.op("plus", pattern!(_ "+" _)).unwrap();
+    let parser = grammar.finish().unwrap();
+    let source = Source::new("testcase", "a +\n  λb".to_owned());
+    let tree = parser.parse(&source).unwrap();
+    let rhs = tree.visitor().child(1);
+    assert_eq!(rhs.span(), Span::new(6, 9));
+    assert_eq!(source.position_at(rhs.span().start), pos(1, 2, 2));
+    assert_eq!(source.position_at(rhs.span().end), pos(1, 5, 4));
+}
+
+#[test]
+fn test_column_modes() {
+    let source = Source::new("testcase", "x = \"日本\" + 😀y\n\tz = é".to_owned());
+    let y = source.position_at(source.source().find('y').unwrap());
+    assert_eq!((y.col, y.utf8_col), (19, 12));
+    assert_eq!(source.utf16_col(y), 13);
+    assert_eq!(source.display_col(y, 4), 15);
+    assert_eq!(source.offset_from_utf16(0, 13), source.offset(y));
+    assert_eq!(source.offset_from_utf16(0, 12), source.offset(y) - 4);
+    assert_eq!(source.offset_from_utf16(0, 11), source.offset(y) - 4);
+    assert_eq!(source.offset_from_utf16(0, 100), source.offset(y) + 1);
+
+    let z = source.position_at(source.source().find('z').unwrap());
+    assert_eq!(source.display_col(z, 4), 4);
+    assert_eq!(source.display_col(z, 8), 8);
+    // A combining accent doesn't take up a column
+    let source = Source::new("testcase", "e\u{301}e\u{301}!".to_owned());
+    let bang = source.position_at(source.source().find('!').unwrap());
+    assert_eq!(bang.utf8_col, 4);
+    assert_eq!(source.display_col(bang, 4), 2);
+
+    // Errors place carets by display width, and expand tabs
+    let source = Source::new("testcase", "日x\t+ 本本".to_owned());
+    let span = Span::new(source.source().find('+').unwrap(), source.source().len());
+    let error = panfix::ParseError::custom_error(&source, "wide", "Too wide.", span);
+    assert_eq!(
+        format!("{}", error.display_with_color_override(false)),
+        "Parse Error: Too wide.
+ --> testcase:1:6
+  |
+1 |日x + 本本
+  |    ^^^^^^ wide
+"
+    );
+    assert_eq!(
+        format!("{}", error.display_with_tab_width(8).color_override(false)),
+        "Parse Error: Too wide.
+ --> testcase:1:6
+  |
+1 |日x     + 本本
+  |        ^^^^^^ wide
+"
+    );
+
+    // Source::show_span uses the same tab width
+    struct ShowSpan<'a>(&'a Source, Span, usize);
+    impl std::fmt::Display for ShowSpan<'_> {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            self.0.show_span_with_tab_width(f, self.1, self.2)
+        }
+    }
+    assert_eq!(
+        ShowSpan(&source, span, 8).to_string(),
+        "日x     + 本本\n        ^^^^^^\n"
+    );
+    assert_eq!(
+        ShowSpan(&source, span, 4).to_string(),
+        "日x + 本本\n    ^^^^^^\n"
+    );
+}
+
+#[test]
+fn test_crlf_errors() {
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    grammar.regex("comment", "#[^\n]*").unwrap();
+    grammar.juxtapose().unwrap();
+    let parser = grammar.finish().unwrap();
+
+    // The first atom ends between a `\r` and a `\n`
+    let source = Source::new("testcase", "#a\r\n#b\r\n".to_owned());
+    let tree = parser.parse(&source).unwrap();
+    let first = tree.visitor().child(0);
+    assert_eq!(first.source(), "#a\r");
+    let error = first.error("comment", "A comment.");
+    assert_eq!(
+        format!("{}", error.display_with_color_override(false)),
+        "Parse Error: A comment.
+ --> testcase:1:1
+  |
+1 |#a
+  |^^ comment
+"
+    );
+    let error = tree.visitor().error("both", "Both comments.");
+    assert_eq!(
+        format!("{}", error.display_with_color_override(false)),
+        "Parse Error: Both comments.
+ --> testcase:1:1
+  |
+1 |#a
+  |^^
+2 |#b
+  |^^ both
+"
+    );
+    struct ShowSpan<'a>(&'a Source, Span);
+    impl std::fmt::Display for ShowSpan<'_> {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            self.0.show_span(f, self.1)
+        }
+    }
+    assert_eq!(ShowSpan(&source, first.span()).to_string(), "#a\n^^\n");
+}
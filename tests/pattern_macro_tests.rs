@@ -1,4 +1,4 @@
-use panfix::{pattern, Fixity, Pattern};
+use panfix::{pattern, Fixity, Pattern, PatternGroup, Repetition};
 
 #[test]
 fn test_pattern_macro() {
@@ -6,65 +6,106 @@ fn test_pattern_macro() {
 
     assert_eq!(
         pattern!("null"),
-        Pattern {
-            fixity: Nilfix,
-            tokens: vec!["null".to_string()],
-        }
+        Pattern::new(Nilfix, vec!["null".to_string()])
     );
 
     assert_eq!(
         pattern!("(" ")"),
-        Pattern {
-            fixity: Nilfix,
-            tokens: vec!["(".to_string(), ")".to_string()],
-        }
+        Pattern::new(Nilfix, vec!["(".to_string(), ")".to_string()])
     );
 
-    assert_eq!(
-        pattern!("-" _),
-        Pattern {
-            fixity: Prefix,
-            tokens: vec!["-".to_string()],
-        }
-    );
+    assert_eq!(pattern!("-" _), Pattern::new(Prefix, vec!["-".to_string()]));
 
     assert_eq!(
         pattern!("if" "then" "else" _),
-        Pattern {
-            fixity: Prefix,
-            tokens: vec!["if".to_string(), "then".to_string(), "else".to_string()],
-        }
+        Pattern::new(
+            Prefix,
+            vec!["if".to_string(), "then".to_string(), "else".to_string()]
+        )
     );
 
     assert_eq!(
         pattern!(_ "++"),
-        Pattern {
-            fixity: Suffix,
-            tokens: vec!["++".to_string()]
-        }
+        Pattern::new(Suffix, vec!["++".to_string()])
     );
 
     assert_eq!(
         pattern!(_ "[" "]"),
-        Pattern {
-            fixity: Suffix,
-            tokens: vec!["[".to_string(), "]".to_string()],
-        }
+        Pattern::new(Suffix, vec!["[".to_string(), "]".to_string()])
     );
 
     assert_eq!(
         pattern!(_ "+" _),
-        Pattern {
-            fixity: Infix,
-            tokens: vec!["+".to_string()],
-        }
+        Pattern::new(Infix, vec!["+".to_string()])
     );
 
     assert_eq!(
         pattern!(_ "?" ":" _),
-        Pattern {
-            fixity: Infix,
-            tokens: vec!["?".to_string(), ":".to_string()],
-        }
+        Pattern::new(Infix, vec!["?".to_string(), ":".to_string()])
+    );
+
+    let pattern = pattern!("function" FuncName "(" ")" "end");
+    assert_eq!(pattern.fixity, Nilfix);
+    assert_eq!(pattern.tokens, ["function", "(", ")", "end"]);
+    assert_eq!(
+        pattern.subgrammars(),
+        [Some("FuncName".to_string()), None, None]
+    );
+    assert_eq!(pattern.groups(), []);
+
+    let pattern = pattern!(_ "[" Index "]" _);
+    assert_eq!(pattern.fixity, Infix);
+    assert_eq!(pattern.tokens, ["[", "]"]);
+    assert_eq!(pattern.subgrammars(), [Some("Index".to_string())]);
+
+    let pattern = pattern!(_ ":" INDENT Body DEDENT);
+    assert_eq!(pattern.fixity, Suffix);
+    assert_eq!(pattern.tokens, [":", "INDENT", "DEDENT"]);
+    assert_eq!(pattern.subgrammars(), [None, Some("Body".to_string())]);
+
+    assert_eq!(
+        pattern!(_ NEWLINE _),
+        Pattern::new(Infix, vec!["NEWLINE".to_string()])
+    );
+
+    let pattern = pattern!("if" "{" "}" ("elif" "{" Block "}")* ("else" "{" "}")?);
+    assert_eq!(pattern.fixity, Nilfix);
+    assert_eq!(
+        pattern.tokens,
+        ["if", "{", "}", "elif", "{", "}", "else", "{", "}"]
+    );
+    assert_eq!(
+        pattern.subgrammars(),
+        [
+            None,
+            None,
+            None,
+            None,
+            Some("Block".to_string()),
+            None,
+            None,
+            None
+        ]
+    );
+    assert_eq!(
+        pattern.groups(),
+        [
+            PatternGroup {
+                tokens: 3..6,
+                repetition: Repetition::Many,
+            },
+            PatternGroup {
+                tokens: 6..9,
+                repetition: Repetition::Optional,
+            },
+        ]
+    );
+
+    assert_eq!(
+        pattern!(_ "?" (":")? _).groups(),
+        [PatternGroup {
+            tokens: 1..2,
+            repetition: Repetition::Optional,
+        }]
     );
 }
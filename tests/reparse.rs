@@ -0,0 +1,208 @@
+mod common;
+
+use common::{make_parser, show_parse, Rng, MULTI_LINE_STR, SINGLE_LINE_STR};
+use panfix::{pattern, Grammar, Parser, Source, Span};
+
+/// Pick a random offset on a char boundary.
+fn random_offset(rng: &mut Rng, text: &str) -> usize {
+    let mut offset = rng.below(text.len() + 1);
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+#[test]
+fn test_apply_edit() {
+    let mut source = Source::new("testcase", "ab\ncd\nef".to_owned());
+    let edit = source.apply_edit(Span::new(1, 4), "X\nY\nZ");
+    assert_eq!(source.source(), "aX\nY\nZd\nef");
+    assert_eq!(edit.old_span, Span::new(1, 4));
+    assert_eq!(edit.new_span, Span::new(1, 6));
+    assert_eq!(source.line_contents(2), "Zd");
+    assert_eq!(source.line_contents(3), "ef");
+    let fresh = Source::new("testcase", source.source().to_owned());
+    assert_eq!(format!("{:?}", source), format!("{:?}", fresh));
+
+    source.apply_edit(Span::new(0, source.source().len()), "x\n");
+    let fresh = Source::new("testcase", "x\n".to_owned());
+    assert_eq!(format!("{:?}", source), format!("{:?}", fresh));
+}
+
+#[test]
+fn test_reparse() {
+    let parser = make_parser(SINGLE_LINE_STR).unwrap();
+    let old_source = Source::new("testcase", "f(1, 2)\n// two\ng * -3 + x".to_owned());
+    let old_tree = parser.parse_for_reparse(&old_source).unwrap();
+
+    let mut source = old_source.clone();
+    let edit = source.apply_edit(Span::new(2, 3), "10 - y");
+    assert_eq!(source.source(), "f(10 - y, 2)\n// two\ng * -3 + x");
+    let tree = parser.reparse(&source, &old_tree, &edit).unwrap();
+    let fresh = parser.parse(&source).unwrap();
+    assert_eq!(show_parse(&tree, &[]), show_parse(&fresh, &[]));
+    assert_eq!(tree.comments(), &[Span::new(13, 19)]);
+
+    // A tree from `parse` has nothing to reuse, but can still be reparsed
+    let plain_tree = parser.parse(&old_source).unwrap();
+    let tree = parser.reparse(&source, &plain_tree, &edit).unwrap();
+    assert_eq!(show_parse(&tree, &[]), show_parse(&fresh, &[]));
+
+    // Errors from the reparsed source are reported the same way
+    let mut newer_source = source.clone();
+    let edit = newer_source.apply_edit(Span::new(0, 1), "[");
+    let error = parser.reparse(&newer_source, &tree, &edit).unwrap_err();
+    let fresh_error = parser.parse(&newer_source).unwrap_err();
+    assert_eq!(error.span, fresh_error.span);
+    assert_eq!(error.message, fresh_error.message);
+}
+
+/// Differential test: make many random edits, and check that reparsing after each one gives
+/// exactly the same result as parsing from scratch.
+#[test]
+fn test_reparse_matches_parse() {
+    // Strings that can span lines, and strings that can't
+    let source = "if a then [1, -2, \"s\"] else b * (c - -3) // end\n/* x */ y";
+    check_random_edits(&make_parser(SINGLE_LINE_STR).unwrap(), source, 2000);
+    check_random_edits(&make_parser(MULTI_LINE_STR).unwrap(), source, 2000);
+
+    // A source long enough for the resolver and tree builder to be resumed part way through
+    let mut source = String::new();
+    for i in 0..60 {
+        match i % 3 {
+            0 => source.push_str("[a, (b + -1) * c, \"s\"],\n"),
+            1 => source.push_str("if x then (y * [z, w]) else - v, // note\n"),
+            _ => source.push_str("f (g + h) /* c */ - 2,\n"),
+        }
+    }
+    check_random_edits(&make_parser(SINGLE_LINE_STR).unwrap(), &source, 300);
+    check_random_edits(&make_parser(MULTI_LINE_STR).unwrap(), &source, 300);
+
+    // Ops with optional tokens, that are only ended once the next lexeme is seen
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    grammar.line_comment("//");
+    grammar.regex("var", "[a-z]+").unwrap();
+    grammar.regex("num", "[0-9]+").unwrap();
+    grammar.op("parens", pattern!("(" ")")).unwrap();
+    grammar
+        .op(
+            "if",
+            pattern!("if" "[" "]" ("elif" "[" "]")* ("else" "[" "]")?),
+        )
+        .unwrap();
+    grammar.left_assoc();
+    grammar.op("plus", pattern!(_ "+" _)).unwrap();
+    grammar.right_assoc();
+    grammar
+        .op("cond", pattern!("when" "then" ("else")? _))
+        .unwrap();
+    grammar.left_assoc();
+    grammar.list_op("seq", ",").unwrap();
+    let parser = grammar.finish().unwrap();
+    let mut source = String::new();
+    for i in 0..60 {
+        match i % 3 {
+            0 => source.push_str("if a [b] elif c [(d + 1)] else [e],\n"),
+            1 => source.push_str("when x then y else when z then w + 2, // note\n"),
+            _ => source.push_str("if (f) [g + if h [i]] + j,\n"),
+        }
+    }
+    check_random_edits(&parser, &source, 300);
+}
+
+fn check_random_edits(parser: &Parser, source: &str, num_edits: usize) {
+    let snippets = [
+        "x", "ab", "1", "-", "-2", " ", "\n", "(", ")", "[", "]", ",", "+", "*", "if", "then",
+        "else", "\"s\"", "\"", "//", "/*", "*/", "é", "", "\"a\nb\"", "\"", "\n", "when", "elif",
+    ];
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    // Trees borrow their sources, so keep every version of the source around.
+    let mut source: &Source = Box::leak(Box::new(Source::new("testcase", source.to_owned())));
+    let mut tree = parser.parse_for_reparse_recovering(source).0;
+    for _ in 0..num_edits {
+        let text = source.source();
+        let start = random_offset(&mut rng, text);
+        let end = start + random_offset(&mut rng, &text[start..]).min(rng.below(6));
+        let end = (end..=text.len())
+            .find(|end| text.is_char_boundary(*end))
+            .unwrap();
+        let mut new_text = String::new();
+        for _ in 0..rng.below(4) {
+            new_text.push_str(snippets[rng.below(snippets.len())]);
+        }
+
+        let mut new_source = source.clone();
+        let edit = new_source.apply_edit(Span::new(start, end), &new_text);
+        let new_source: &Source = Box::leak(Box::new(new_source));
+        let fresh_source = Source::new("testcase", new_source.source().to_owned());
+        assert_eq!(format!("{:?}", new_source), format!("{:?}", fresh_source));
+
+        let (reparsed, reparse_errors) = parser.reparse_recovering(new_source, &tree, &edit);
+        let (fresh, fresh_errors) = parser.parse_recovering(new_source);
+        assert_eq!(
+            show_parse(&reparsed, &reparse_errors),
+            show_parse(&fresh, &fresh_errors),
+            "after changing {:?} to {:?} in {:?}",
+            &text[start..end],
+            new_text,
+            text
+        );
+        match (
+            parser.reparse(new_source, &tree, &edit),
+            parser.parse(new_source),
+        ) {
+            (Ok(reparsed), Ok(fresh)) => {
+                assert_eq!(show_parse(&reparsed, &[]), show_parse(&fresh, &[]))
+            }
+            (Err(reparse_error), Err(fresh_error)) => {
+                assert_eq!(reparse_error.span, fresh_error.span);
+                assert_eq!(reparse_error.message, fresh_error.message);
+            }
+            (reparsed, fresh) => panic!(
+                "reparse gave {:?} but parse gave {:?}",
+                reparsed.is_ok(),
+                fresh.is_ok()
+            ),
+        }
+
+        source = new_source;
+        tree = reparsed;
+    }
+}
+
+#[test]
+fn test_reparse_multiline_token() {
+    // Closing a string changes how the lines before the edit lex
+    let parser = make_parser(MULTI_LINE_STR).unwrap();
+    let old_source = Source::new("testcase", "x + \"a\nb".to_owned());
+    let (old_tree, _) = parser.parse_for_reparse_recovering(&old_source);
+    let mut source = old_source.clone();
+    let edit = source.apply_edit(Span::new(8, 8), "\"");
+    let (tree, errors) = parser.reparse_recovering(&source, &old_tree, &edit);
+    assert!(errors.is_empty());
+    let fresh = parser.parse(&source).unwrap();
+    assert_eq!(show_parse(&tree, &[]), show_parse(&fresh, &[]));
+    assert_eq!(
+        tree.visitor().children_iter().nth(1).unwrap().span(),
+        Span::new(4, 9)
+    );
+}
+
+#[test]
+fn test_reparse_with_layout() {
+    let mut grammar = Grammar::new_with_unicode_whitespace().unwrap();
+    grammar.regex("var", "[a-z]+").unwrap();
+    grammar.op("block", pattern!(":" INDENT DEDENT)).unwrap();
+    let parser = grammar.finish().unwrap();
+
+    let old_source = Source::new("testcase", ":\n  a\nb".to_owned());
+    let (old_tree, _) = parser.parse_recovering(&old_source);
+    let mut source = old_source.clone();
+    let edit = source.apply_edit(Span::new(4, 4), "c\n  ");
+    let (tree, errors) = parser.reparse_recovering(&source, &old_tree, &edit);
+    let (fresh, fresh_errors) = parser.parse_recovering(&source);
+    assert_eq!(
+        show_parse(&tree, &errors),
+        show_parse(&fresh, &fresh_errors)
+    );
+}
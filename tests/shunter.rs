@@ -1,5 +1,5 @@
 use panfix::implementation::shunter::shunt;
-use panfix::{Col, Lexeme, Position, Prec, Token, TOKEN_BLANK, TOKEN_ERROR, TOKEN_JUXTAPOSE};
+use panfix::{Lexeme, Prec, Token, TOKEN_BLANK, TOKEN_ERROR, TOKEN_JUXTAPOSE};
 
 #[test]
 fn test_shunting() {
@@ -37,24 +37,14 @@ fn test_shunting() {
                 ':' => TOKEN_COLON,
                 _ => TOKEN_ERROR,
             };
-            let start = Position {
-                line: 0,
-                col: i as Col,
-                utf8_col: i as Col,
-            };
-            let end = Position {
-                line: 0,
-                col: (i + 1) as Col,
-                utf8_col: (i + 1) as Col,
-            };
-            lexemes.push(Lexeme::new(token, start, end));
+            lexemes.push(Lexeme::new(token, i, i + 1));
         }
         lexemes.into_iter()
     }
 
     fn show_stream(src: &str, stream: impl Iterator<Item = Lexeme>) -> String {
         stream
-            .map(|lex| &src[lex.span.start.col as usize..lex.span.end.col as usize])
+            .map(|lex| &src[lex.span.start..lex.span.end])
             .map(|lex| if lex.is_empty() { "_" } else { lex })
             .collect::<Vec<_>>()
             .join(" ")